@@ -167,6 +167,7 @@ module.exports = grammar({
         optional(field("type_arguments", $.generic_argument_list)),
         field("parameters", $.parameter_list),
         optional(seq("->", field("return_type", $._type))),
+        optional(field("where_clause", $.where_clause)),
         choice(field("body", $.block), ";")
       ),
 
@@ -199,6 +200,7 @@ module.exports = grammar({
         field("kind", choice("struct", "union")),
         field("name", $.identifier),
         optional(field("type_arguments", $.generic_argument_list)),
+        optional(field("where_clause", $.where_clause)),
         "{",
         sepBy(",", field("body", $.struct_field)),
         optional(","),
@@ -321,6 +323,23 @@ module.exports = grammar({
         optional(seq("=", field("default", $._type)))
       ),
 
+    where_clause: ($) =>
+      seq(
+        "where",
+        sepBy1(",", field("predicate", $.where_predicate)),
+        optional(",")
+      ),
+
+    where_predicate: ($) =>
+      seq(
+        field("placeholder", $._type_identifier),
+        ":",
+        choice(
+          field("all_bounds", sepBy1("+", field("bound", $.protocol_bound))),
+          field("any_bounds", sepBy1("|", field("bound", $.protocol_bound))),
+        )
+      ),
+
     generic_argument_list: ($) =>
       seq(
         "<",
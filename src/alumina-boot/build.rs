@@ -71,6 +71,13 @@ fn sanitize_identifier(name: &str) -> String {
     result
 }
 
+/// 64-bit FNV-1a, which (unlike `DefaultHasher`) gives the same result with every Rust version.
+fn fnv1a(hash: u64, data: &[u8]) -> u64 {
+    data.iter().fold(hash, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
 fn generate_visitor(filename: PathBuf) -> String {
     let file = File::open(filename).unwrap();
     let parsed: Vec<Node> = from_reader(file).expect("could not parse the node types JSON");
@@ -110,9 +117,16 @@ fn generate_visitor(filename: PathBuf) -> String {
         type ReturnType;
     };
 
-    let dispatch_visit_fn: TraitItem = parse_quote! {
+    let visit_fn: TraitItem = parse_quote! {
         #[doc=r"Visits a node of any type."]
         fn visit(&mut self, node: ::tree_sitter::Node<'tree>) -> Self::ReturnType {
+            self.dispatch(node)
+        }
+    };
+
+    let dispatch_visit_fn: TraitItem = parse_quote! {
+        #[doc=r"Calls the visitor method for the type of the node. Visitors that override `visit` (e.g. to limit the recursion depth) can use this to delegate to the default behavior."]
+        fn dispatch(&mut self, node: ::tree_sitter::Node<'tree>) -> Self::ReturnType {
             match node.kind() {
                 #(#match_arms,)*
                 _ => panic!("unknown node kind: {}", node.kind())
@@ -131,6 +145,7 @@ fn generate_visitor(filename: PathBuf) -> String {
 
         pub trait AluminaVisitor<'tree>: Sized {
             #return_item
+            #visit_fn
             #dispatch_visit_fn
             #(#trait_fns)*
         }
@@ -178,5 +193,23 @@ fn main() {
         .write_all(visitor.as_bytes())
         .unwrap();
 
-    println!("cargo:rerun-if-changed={}", grammar_path.display());
+    // The incremental cache is only valid for the grammar and the cache format that it was
+    // written with, so they are part of the key of every entry. Declarations also store the
+    // attributes of items, which are encoded in visitors.rs and defined in ast/mod.rs.
+    let cache_inputs = [
+        grammar_path.clone(),
+        manifest_dir.join("src/incremental.rs"),
+        manifest_dir.join("src/name_resolution/pass1.rs"),
+        manifest_dir.join("src/ast/mod.rs"),
+        manifest_dir.join("src/visitors.rs"),
+    ];
+
+    let cache_key = cache_inputs.iter().fold(0xcbf29ce484222325, |hash, path| {
+        fnv1a(hash, &std::fs::read(path).unwrap())
+    });
+
+    println!("cargo:rustc-env=ALUMINA_CACHE_KEY={:016x}", cache_key);
+    for path in &cache_inputs {
+        println!("cargo:rerun-if-changed={}", path.display());
+    }
 }
@@ -22,6 +22,7 @@ use crate::{
     },
 };
 
+use super::lang::LangItemKind;
 use super::macros::{MacroExpander, MacroMaker};
 use super::maker::AstItemMaker;
 use super::types::TypeVisitor;
@@ -53,13 +54,7 @@ where
         scope: &Scope<'ast, 'src>,
         node: tree_sitter::Node<'src>,
     ) -> Self::ReturnType {
-        let span = Span {
-            start: node.start_byte(),
-            end: node.end_byte(),
-            line: node.start_position().row,
-            column: node.start_position().column,
-            file: scope.code().unwrap().file_id(),
-        };
+        let span = scope.code().unwrap().span(node);
 
         self.alloc_with_span(ast, span)
     }
@@ -113,6 +108,8 @@ pub struct ExpressionVisitor<'ast, 'src> {
     in_a_loop: bool,
     in_a_macro: bool,
     has_et_cetera: bool,
+    depth: usize,
+    max_depth: usize,
 }
 macro_rules! suffixed_literals {
     ($e:expr, $($suffix:literal => $typ:path),+) => {
@@ -141,10 +138,12 @@ impl<'ast, 'src> ExpressionVisitor<'ast, 'src> {
                 .code()
                 .expect("cannot run on scope without parse context"),
             scope,
+            max_depth: global_ctx.max_nesting_depth(),
             global_ctx,
             in_a_macro,
             in_a_loop: false,
             has_et_cetera: false,
+            depth: 0,
         }
     }
 
@@ -160,10 +159,12 @@ impl<'ast, 'src> ExpressionVisitor<'ast, 'src> {
                 .code()
                 .expect("cannot run on scope without parse context"),
             scope,
+            max_depth: global_ctx.max_nesting_depth(),
             global_ctx,
             in_a_loop: false,
             in_a_macro: true,
             has_et_cetera,
+            depth: 0,
         }
     }
 
@@ -172,6 +173,31 @@ impl<'ast, 'src> ExpressionVisitor<'ast, 'src> {
         Ok(result)
     }
 
+    /// Enum members can be matched by their name alone in switch arms (e.g. `Red` instead of
+    /// `Color::Red`) if the name does not resolve to anything else. In that case, it is resolved
+    /// against the type of the switch value during monomorphization.
+    fn visit_switch_alternative(
+        &mut self,
+        node: tree_sitter::Node<'src>,
+    ) -> Result<ExprP<'ast>, AluminaError> {
+        if node.kind() == "identifier" {
+            let name = self.code.node_text(node).alloc_on(self.ast);
+            let mut resolver = NameResolver::new();
+            if resolver
+                .resolve_item(self.scope.clone(), PathSegment(name).into())
+                .is_err()
+            {
+                return Ok(ExprKind::InferredEnumValue(name).alloc_with_span_from(
+                    self.ast,
+                    &self.scope,
+                    node,
+                ));
+            }
+        }
+
+        self.visit(node)
+    }
+
     fn visit_ref(&mut self, node: tree_sitter::Node<'src>) -> Result<ExprP<'ast>, AluminaError> {
         let mut visitor = ScopedPathVisitor::new(self.ast, self.scope.clone(), self.in_a_macro);
         let path = visitor.visit(node)?;
@@ -191,7 +217,7 @@ impl<'ast, 'src> ExpressionVisitor<'ast, 'src> {
                 NamedItemKind::MacroParameter(var, _) => ExprKind::Local(var),
                 NamedItemKind::Parameter(var, _) => ExprKind::Local(var),
                 NamedItemKind::Static(var, _, _) => ExprKind::Static(var, None),
-                NamedItemKind::Const(var, _) => ExprKind::Const(var),
+                NamedItemKind::Const(var, _, _) => ExprKind::Const(var),
                 NamedItemKind::EnumMember(typ, var, _) => ExprKind::EnumValue(typ, var),
                 NamedItemKind::Macro(_, _, _) => {
                     return Err(CodeErrorKind::IsAMacro(path.to_string()))
@@ -250,16 +276,19 @@ impl<'ast, 'src> ExpressionVisitor<'ast, 'src> {
                     .map(|n| self.visit(n))
                     .transpose()?;
 
+                let name = inner
+                    .child_by_field_name("name")
+                    .map(|name| self.code.node_text(name).alloc_on(self.ast));
+
                 let let_decl = LetDeclaration {
                     id: value_id,
+                    name,
                     typ,
                     value,
                 };
 
                 let mut statements = Vec::new();
-                if let Some(name) = inner.child_by_field_name("name") {
-                    let name = self.code.node_text(name).alloc_on(self.ast);
-
+                if let Some(name) = name {
                     self.scope
                         .add_item(
                             Some(name),
@@ -296,6 +325,7 @@ impl<'ast, 'src> ExpressionVisitor<'ast, 'src> {
 
                         let elem_decl = LetDeclaration {
                             id: elem_id,
+                            name: Some(name),
                             typ: None,
                             value: Some(rhs),
                         };
@@ -355,15 +385,18 @@ impl<'ast, 'src> ExpressionVisitor<'ast, 'src> {
         &self,
         last_node: Option<tree_sitter::Node<'src>>,
         statements: &mut Vec<Statement<'ast>>,
+        closing_brace: Span,
     ) -> Result<ExprP<'ast>, AluminaError> {
+        // Implicit void results are attributed to the closing brace of the block, so that
+        // e.g. a missing return value is reported where the control falls off the end.
         let last_node = match last_node {
             Some(n) => n,
-            None => return Ok(ExprKind::Void.alloc_with_no_span(self.ast)),
+            None => return Ok(ExprKind::Void.alloc_with_span(self.ast, closing_brace)),
         };
 
         let expression_node = match last_node.kind() {
             "expression_statement" => last_node.child_by_field_name("inner").unwrap(),
-            _ => return Ok(ExprKind::Void.alloc_with_span_from(self.ast, &self.scope, last_node)),
+            _ => return Ok(ExprKind::Void.alloc_with_span(self.ast, closing_brace)),
         };
 
         match expression_node.kind() {
@@ -376,10 +409,10 @@ impl<'ast, 'src> ExpressionVisitor<'ast, 'src> {
                 _ => {
                     // There are no statements preceeding the return expression. From the pure syntax perspective this
                     // should not be possible, however we may have filtered a statement out due to #[cfg] directive on it.
-                    Ok(ExprKind::Void.alloc_with_span_from(self.ast, &self.scope, expression_node))
+                    Ok(ExprKind::Void.alloc_with_span(self.ast, closing_brace))
                 }
             },
-            _ => Ok(ExprKind::Void.alloc_with_span_from(self.ast, &self.scope, expression_node)),
+            _ => Ok(ExprKind::Void.alloc_with_span(self.ast, closing_brace)),
         }
     }
 
@@ -424,11 +457,29 @@ impl<'ast, 'src> ExpressionVisitor<'ast, 'src> {
 
         Ok(result)
     }
+
+    fn require_lang_items(&self, kinds: &[LangItemKind], node: tree_sitter::Node<'src>) {
+        let span = self.code.span(node);
+
+        self.ast.require_lang_items(kinds, Some(span));
+    }
 }
 
 impl<'ast, 'src> AluminaVisitor<'src> for ExpressionVisitor<'ast, 'src> {
     type ReturnType = Result<ExprP<'ast>, AluminaError>;
 
+    fn visit(&mut self, node: tree_sitter::Node<'src>) -> Self::ReturnType {
+        if self.depth >= self.max_depth {
+            return Err(CodeErrorKind::TooDeeplyNested).with_span_from(&self.scope, node);
+        }
+
+        self.depth += 1;
+        let result = self.dispatch(node);
+        self.depth -= 1;
+
+        result
+    }
+
     fn visit_block(&mut self, node: tree_sitter::Node<'src>) -> Self::ReturnType {
         let mut cursor = node.walk();
         let mut statements = Vec::new();
@@ -449,7 +500,16 @@ impl<'ast, 'src> AluminaVisitor<'src> for ExpressionVisitor<'ast, 'src> {
                     // This is a bit of a hack to work around Tree-Sitter. _expression_ending_with_block nodes
                     // are treated as statements even if they appear in the terminal positions. If they are
                     // actually statements (semicolon), there is another empty_statement inserted, so it's fine.
-                    self.extract_expression_ending_with_block(last_node, &mut statements)?
+                    let closing_brace = Span::new(
+                        self.scope.code().unwrap().file_id(),
+                        node.end_byte() - 1..node.end_byte(),
+                    );
+
+                    self.extract_expression_ending_with_block(
+                        last_node,
+                        &mut statements,
+                        closing_brace,
+                    )?
                 }
             }
         });
@@ -549,6 +609,8 @@ impl<'ast, 'src> AluminaVisitor<'src> for ExpressionVisitor<'ast, 'src> {
         let s =
             parse_string_literal(self.code.node_text(node)).with_span_from(&self.scope, node)?;
 
+        self.require_lang_items(&[LangItemKind::Slice, LangItemKind::SliceNew], node);
+
         let s = self.ast.arena.alloc_slice_copy(&s);
         Ok(ExprKind::Lit(Lit::Str(s)).alloc_with_span_from(self.ast, &self.scope, node))
     }
@@ -705,13 +767,7 @@ impl<'ast, 'src> AluminaVisitor<'src> for ExpressionVisitor<'ast, 'src> {
 
     fn visit_try_expression(&mut self, node: tree_sitter::Node<'src>) -> Self::ReturnType {
         let path = PathSegment("try").into();
-        let span = Span {
-            start: node.start_byte(),
-            end: node.end_byte(),
-            line: node.start_position().row,
-            column: node.start_position().column,
-            file: self.scope.code().unwrap().file_id(),
-        };
+        let span = self.scope.code().unwrap().span(node);
         let inner = self.visit(node.child_by_field_name("inner").unwrap())?;
 
         self.visit_macro_invocation_impl(path, vec![inner], span)
@@ -801,7 +857,12 @@ impl<'ast, 'src> AluminaVisitor<'src> for ExpressionVisitor<'ast, 'src> {
 
     fn visit_index_expression(&mut self, node: tree_sitter::Node<'src>) -> Self::ReturnType {
         let value = self.visit(node.child_by_field_name("value").unwrap())?;
-        let index = self.visit(node.child_by_field_name("index").unwrap())?;
+        let index_node = node.child_by_field_name("index").unwrap();
+        let index = self.visit(index_node)?;
+
+        if index_node.kind() == "range_expression" {
+            self.require_lang_items(&[LangItemKind::SliceRangeIndex], node);
+        }
 
         let result = ExprKind::Index(value, index);
 
@@ -823,6 +884,22 @@ impl<'ast, 'src> AluminaVisitor<'src> for ExpressionVisitor<'ast, 'src> {
             .map(|n| self.code.node_text(n) == "..=")
             .unwrap_or(false);
 
+        let lang_items = match (lower_bound.is_some(), upper_bound.is_some(), inclusive) {
+            (false, false, _) => [LangItemKind::RangeFull, LangItemKind::RangeFullNew],
+            (true, false, _) => [LangItemKind::RangeFrom, LangItemKind::RangeFromNew],
+            (false, true, false) => [LangItemKind::RangeTo, LangItemKind::RangeToNew],
+            (false, true, true) => [
+                LangItemKind::RangeToInclusive,
+                LangItemKind::RangeToInclusiveNew,
+            ],
+            (true, true, false) => [LangItemKind::Range, LangItemKind::RangeNew],
+            (true, true, true) => [
+                LangItemKind::RangeInclusive,
+                LangItemKind::RangeInclusiveNew,
+            ],
+        };
+        self.require_lang_items(&lang_items, node);
+
         let result = ExprKind::Range(lower_bound, upper_bound, inclusive);
 
         Ok(result.alloc_with_span_from(self.ast, &self.scope, node))
@@ -830,8 +907,10 @@ impl<'ast, 'src> AluminaVisitor<'src> for ExpressionVisitor<'ast, 'src> {
 
     fn visit_if_expression(&mut self, node: tree_sitter::Node<'src>) -> Self::ReturnType {
         let consequence = self.visit(node.child_by_field_name("consequence").unwrap())?;
+        // Looks through the `else` clause, so that each `else if` in a chain only counts as one
+        // level of nesting.
         let alternative = match node.child_by_field_name("alternative") {
-            Some(node) => self.visit(node)?,
+            Some(node) => self.visit(node.child_by_field_name("inner").unwrap())?,
             None => ExprKind::Void.alloc_with_span_from(self.ast, &self.scope, node),
         };
 
@@ -960,6 +1039,9 @@ impl<'ast, 'src> AluminaVisitor<'src> for ExpressionVisitor<'ast, 'src> {
     }
 
     fn visit_for_expression(&mut self, node: tree_sitter::Node<'src>) -> Self::ReturnType {
+        // The loop below reads the fields of the `Option` returned by `next()` directly
+        self.require_lang_items(&[LangItemKind::Option], node);
+
         let iterable_node = node.child_by_field_name("value").unwrap();
         let iterable = self.visit(iterable_node)?;
 
@@ -1002,6 +1084,7 @@ impl<'ast, 'src> AluminaVisitor<'src> for ExpressionVisitor<'ast, 'src> {
 
                     let elem_decl = LetDeclaration {
                         id: elem_id,
+                        name: Some(name),
                         typ: None,
                         value: Some(rhs),
                     };
@@ -1055,6 +1138,7 @@ impl<'ast, 'src> AluminaVisitor<'src> for ExpressionVisitor<'ast, 'src> {
             ExprKind::Block(
                 vec![StatementKind::LetDeclaration(LetDeclaration {
                     id,
+                    name: None,
                     typ: None,
                     value: Some(
                         ExprKind::Field(
@@ -1077,6 +1161,7 @@ impl<'ast, 'src> AluminaVisitor<'src> for ExpressionVisitor<'ast, 'src> {
             ExprKind::Block(
                 vec![StatementKind::LetDeclaration(LetDeclaration {
                     id: iterator_result,
+                    name: None,
                     typ: None,
                     value: Some(
                         ExprKind::Call(
@@ -1101,6 +1186,7 @@ impl<'ast, 'src> AluminaVisitor<'src> for ExpressionVisitor<'ast, 'src> {
         let result = ExprKind::Block(
             vec![StatementKind::LetDeclaration(LetDeclaration {
                 id: iterator,
+                name: None,
                 typ: None,
                 value: Some(
                     ExprKind::Call(
@@ -1142,7 +1228,7 @@ impl<'ast, 'src> AluminaVisitor<'src> for ExpressionVisitor<'ast, 'src> {
 
             let alternatives = pattern
                 .children_by_field_name("value", &mut cursor)
-                .map(|child| self.visit(child))
+                .map(|child| self.visit_switch_alternative(child))
                 .collect::<Result<Vec<_>, _>>()?;
 
             if !alternatives.is_empty() {
@@ -1160,6 +1246,7 @@ impl<'ast, 'src> AluminaVisitor<'src> for ExpressionVisitor<'ast, 'src> {
         let local = ExprKind::Local(local_id).alloc_with_span_from(self.ast, &self.scope, node);
         let stmts = vec![StatementKind::LetDeclaration(LetDeclaration {
             id: local_id,
+            name: None,
             typ: None,
             value: Some(value),
         })
@@ -1230,13 +1317,7 @@ impl<'ast, 'src> AluminaVisitor<'src> for ExpressionVisitor<'ast, 'src> {
 
                 let value = self.visit(node.child_by_field_name("value").unwrap())?;
 
-                let span = Span {
-                    start: node.start_byte(),
-                    end: node.end_byte(),
-                    line: node.start_position().row,
-                    column: node.start_position().column,
-                    file: self.scope.code().unwrap().file_id(),
-                };
+                let span = self.scope.code().unwrap().span(node);
 
                 field_initializers.push(FieldInitializer {
                     name: name.alloc_on(self.ast),
@@ -1291,13 +1372,7 @@ impl<'ast, 'src> AluminaVisitor<'src> for ExpressionVisitor<'ast, 'src> {
         let mut visitor = ScopedPathVisitor::new(self.ast, self.scope.clone(), self.in_a_macro);
         let path = visitor.visit(node.child_by_field_name("macro").unwrap())?;
 
-        let span = Span {
-            start: node.start_byte(),
-            end: node.end_byte(),
-            line: node.start_position().row,
-            column: node.start_position().column,
-            file: self.scope.code().unwrap().file_id(),
-        };
+        let span = self.scope.code().unwrap().span(node);
 
         let mut arguments = Vec::new();
         let arguments_node = node.child_by_field_name("arguments").unwrap();
@@ -1316,13 +1391,7 @@ impl<'ast, 'src> AluminaVisitor<'src> for ExpressionVisitor<'ast, 'src> {
         let mut visitor = ScopedPathVisitor::new(self.ast, self.scope.clone(), self.in_a_macro);
         let path = visitor.visit(node.child_by_field_name("macro").unwrap())?;
 
-        let span = Span {
-            start: node.start_byte(),
-            end: node.end_byte(),
-            line: node.start_position().row,
-            column: node.start_position().column,
-            file: self.scope.code().unwrap().file_id(),
-        };
+        let span = self.scope.code().unwrap().span(node);
 
         let mut arguments = Vec::new();
         arguments.push(self.visit(node.child_by_field_name("value").unwrap())?);
@@ -1338,7 +1407,17 @@ impl<'ast, 'src> AluminaVisitor<'src> for ExpressionVisitor<'ast, 'src> {
 }
 
 pub fn parse_string_literal(lit: &str) -> Result<Vec<u8>, CodeErrorKind> {
+    parse_string_literal_with_offsets(lit).map(|(result, _)| result)
+}
+
+/// Like [`parse_string_literal`], but also returns the offset in `lit` of the character or
+/// the escape sequence that each of the bytes comes from.
+pub fn parse_string_literal_with_offsets(
+    lit: &str,
+) -> Result<(Vec<u8>, Vec<usize>), CodeErrorKind> {
     let mut result = Vec::<u8>::with_capacity(lit.len());
+    let mut offsets = Vec::with_capacity(lit.len());
+    let mut start = 0;
 
     enum State {
         Normal,
@@ -1352,7 +1431,12 @@ pub fn parse_string_literal(lit: &str) -> Result<Vec<u8>, CodeErrorKind> {
     let mut state = State::Normal;
     let mut buf = String::with_capacity(4);
 
-    for ch in lit[1..lit.len() - 1].bytes() {
+    for (idx, ch) in lit[1..lit.len() - 1].bytes().enumerate() {
+        if let State::Normal = state {
+            // Skip the opening quote
+            start = idx + 1;
+        }
+
         state = match state {
             State::Normal => match ch {
                 b'\\' => State::Escape,
@@ -1450,10 +1534,12 @@ pub fn parse_string_literal(lit: &str) -> Result<Vec<u8>, CodeErrorKind> {
                 }
             },
         };
+
+        offsets.resize(result.len(), start);
     }
 
     match state {
-        State::Normal => Ok(result),
+        State::Normal => Ok((result, offsets)),
         _ => Err(CodeErrorKind::InvalidEscapeSequence),
     }
 }
@@ -1504,13 +1590,7 @@ impl<'ast, 'src> ClosureVisitor<'ast, 'src> {
         self.visit(node)?;
 
         let symbol = self.ast.make_symbol();
-        let span = Span {
-            start: node.start_byte(),
-            end: node.end_byte(),
-            line: node.start_position().row,
-            column: node.start_position().column,
-            file: self.scope.code().unwrap().file_id(),
-        };
+        let span = self.scope.code().unwrap().span(node);
 
         if !self.bound_values.is_empty() {
             let placeholder = self.ast.make_id();
@@ -1523,13 +1603,7 @@ impl<'ast, 'src> ClosureVisitor<'ast, 'src> {
                 default: None,
             });
 
-            let span = Span {
-                start: node.start_byte(),
-                end: node.end_byte(),
-                line: node.start_position().row,
-                column: node.start_position().column,
-                file: self.scope.code().unwrap().file_id(),
-            };
+            let span = self.scope.code().unwrap().span(node);
 
             // Closure struct is passed by a mutable pointer
             let placeholder_ty = self.ast.intern_type(Ty::Placeholder(placeholder));
@@ -1594,13 +1668,7 @@ impl<'ast, 'src> AluminaVisitor<'src> for ClosureVisitor<'ast, 'src> {
         )
         .visit(node.child_by_field_name("type").unwrap())?;
 
-        let span = Span {
-            start: node.start_byte(),
-            end: node.end_byte(),
-            line: node.start_position().row,
-            column: node.start_position().column,
-            file: self.scope.code().unwrap().file_id(),
-        };
+        let span = self.scope.code().unwrap().span(node);
 
         self.parameters.push(Parameter {
             id,
@@ -1,5 +1,8 @@
-use super::{BinOp, BuiltinType};
-use crate::common::CodeErrorKind;
+use std::collections::HashMap;
+use std::fmt::Display;
+
+use super::{BinOp, BuiltinType, ItemP, Span};
+use crate::common::{AluminaError, CodeError, CodeErrorKind};
 use crate::utils::regex;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
@@ -60,6 +63,7 @@ pub enum LangItemKind {
     TypeopEnumTypeOf,
 
     EntrypointGlue,
+    Option,
     TestCaseMeta,
     TestCaseMetaNew,
 
@@ -74,7 +78,17 @@ pub enum LangItemKind {
     Operator(BinOp),
 
     FormatArg,
+    FormatWriteStr,
+    FormatWriteInt,
+    FormatWriteFormattable,
+    ProtoFormattable,
+
     EnumVariantNew,
+    Drop,
+    // Only required by the panics that the compiler generates itself (the fallback of the
+    // name function of `derive(debug)`). `panic!` is an ordinary macro in the standard library,
+    // so a missing implementation is reported as an unresolved path where it is expanded.
+    Panic,
 }
 
 impl LangItemKind {
@@ -127,115 +141,235 @@ impl LangItemKind {
             _ => false,
         }
     }
+
+    /// All the valid names for the `#[lang(...)]` attribute.
+    pub fn names() -> impl Iterator<Item = &'static str> {
+        LANG_ITEM_NAMES
+            .iter()
+            .map(|(name, _)| *name)
+            .chain(std::iter::once("builtin_tuple_<N>"))
+    }
+}
+
+/// Names of the lang items, as used in the `#[lang(...)]` attribute. Tuple impls are named
+/// `builtin_tuple_N` and are not listed.
+const LANG_ITEM_NAMES: &[(&str, LangItemKind)] = &[
+    ("slice", LangItemKind::Slice),
+    ("slice_new", LangItemKind::SliceNew),
+    ("slice_const_coerce", LangItemKind::SliceConstCoerce),
+    ("slice_const_cast", LangItemKind::SliceConstCast),
+    ("slice_index", LangItemKind::SliceIndex),
+    ("slice_range_index", LangItemKind::SliceRangeIndex),
+    ("range_full", LangItemKind::RangeFull),
+    ("range_from", LangItemKind::RangeFrom),
+    ("range_to", LangItemKind::RangeTo),
+    ("range_to_inclusive", LangItemKind::RangeToInclusive),
+    ("range", LangItemKind::Range),
+    ("range_inclusive", LangItemKind::RangeInclusive),
+    ("range_full_new", LangItemKind::RangeFullNew),
+    ("range_from_new", LangItemKind::RangeFromNew),
+    ("range_to_new", LangItemKind::RangeToNew),
+    ("range_new", LangItemKind::RangeNew),
+    ("range_to_inclusive_new", LangItemKind::RangeToInclusiveNew),
+    ("range_inclusive_new", LangItemKind::RangeInclusiveNew),
+    ("proto_primitive", LangItemKind::ProtoPrimitive),
+    ("proto_numeric", LangItemKind::ProtoNumeric),
+    ("proto_integer", LangItemKind::ProtoInteger),
+    ("proto_floating_point", LangItemKind::ProtoFloatingPoint),
+    ("proto_signed", LangItemKind::ProtoSigned),
+    ("proto_unsigned", LangItemKind::ProtoUnsigned),
+    ("proto_pointer", LangItemKind::ProtoPointer),
+    ("proto_zero_sized", LangItemKind::ProtoZeroSized),
+    ("proto_array", LangItemKind::ProtoArray),
+    ("proto_struct", LangItemKind::ProtoStruct),
+    ("proto_enum", LangItemKind::ProtoEnum),
+    ("proto_union", LangItemKind::ProtoUnion),
+    ("proto_tuple", LangItemKind::ProtoTuple),
+    ("proto_range", LangItemKind::ProtoRange),
+    ("proto_named_function", LangItemKind::ProtoNamedFunction),
+    ("proto_function_pointer", LangItemKind::ProtoFunctionPointer),
+    ("proto_callable", LangItemKind::ProtoCallable),
+    ("proto_array_of", LangItemKind::ProtoArrayOf),
+    ("proto_pointer_of", LangItemKind::ProtoPointerOf),
+    ("proto_range_of", LangItemKind::ProtoRangeOf),
+    ("proto_meta", LangItemKind::ProtoMeta),
+    (
+        "builtin_never",
+        LangItemKind::ImplBuiltin(BuiltinType::Never),
+    ),
+    ("builtin_void", LangItemKind::ImplBuiltin(BuiltinType::Void)),
+    ("builtin_bool", LangItemKind::ImplBuiltin(BuiltinType::Bool)),
+    ("builtin_u8", LangItemKind::ImplBuiltin(BuiltinType::U8)),
+    ("builtin_u16", LangItemKind::ImplBuiltin(BuiltinType::U16)),
+    ("builtin_u32", LangItemKind::ImplBuiltin(BuiltinType::U32)),
+    ("builtin_u64", LangItemKind::ImplBuiltin(BuiltinType::U64)),
+    ("builtin_u128", LangItemKind::ImplBuiltin(BuiltinType::U128)),
+    (
+        "builtin_usize",
+        LangItemKind::ImplBuiltin(BuiltinType::USize),
+    ),
+    ("builtin_i8", LangItemKind::ImplBuiltin(BuiltinType::I8)),
+    ("builtin_i16", LangItemKind::ImplBuiltin(BuiltinType::I16)),
+    ("builtin_i32", LangItemKind::ImplBuiltin(BuiltinType::I32)),
+    ("builtin_i64", LangItemKind::ImplBuiltin(BuiltinType::I64)),
+    ("builtin_i128", LangItemKind::ImplBuiltin(BuiltinType::I128)),
+    (
+        "builtin_isize",
+        LangItemKind::ImplBuiltin(BuiltinType::ISize),
+    ),
+    ("builtin_f32", LangItemKind::ImplBuiltin(BuiltinType::F32)),
+    ("builtin_f64", LangItemKind::ImplBuiltin(BuiltinType::F64)),
+    ("builtin_array", LangItemKind::ImplArray),
+    ("operator_eq", LangItemKind::Operator(BinOp::Eq)),
+    ("operator_neq", LangItemKind::Operator(BinOp::Neq)),
+    ("operator_lt", LangItemKind::Operator(BinOp::Lt)),
+    ("operator_lte", LangItemKind::Operator(BinOp::LEq)),
+    ("operator_gt", LangItemKind::Operator(BinOp::Gt)),
+    ("operator_gte", LangItemKind::Operator(BinOp::GEq)),
+    ("typeop_tuple_head_of", LangItemKind::TypeopTupleHeadOf),
+    ("typeop_tuple_tail_of", LangItemKind::TypeopTupleTailOf),
+    ("typeop_return_type_of", LangItemKind::TypeopReturnTypeOf),
+    ("typeop_arguments_of", LangItemKind::TypeopArgumentsOf),
+    (
+        "typeop_pointer_with_mut_of",
+        LangItemKind::TypeopPointerWithMutOf,
+    ),
+    (
+        "typeop_array_with_length_of",
+        LangItemKind::TypeopArrayWithLengthOf,
+    ),
+    ("typeop_generic_args_of", LangItemKind::TypeopGenericArgsOf),
+    ("typeop_enum_type_of", LangItemKind::TypeopEnumTypeOf),
+    ("entrypoint_glue", LangItemKind::EntrypointGlue),
+    ("option", LangItemKind::Option),
+    ("test_case_meta", LangItemKind::TestCaseMeta),
+    ("test_case_meta_new", LangItemKind::TestCaseMetaNew),
+    ("dyn", LangItemKind::Dyn),
+    ("dyn_self", LangItemKind::DynSelf),
+    ("dyn_new", LangItemKind::DynNew),
+    ("dyn_const_coerce", LangItemKind::DynConstCoerce),
+    ("dyn_const_cast", LangItemKind::DynConstCast),
+    ("dyn_data", LangItemKind::DynData),
+    ("dyn_vtable_index", LangItemKind::DynVtableIndex),
+    ("format_arg", LangItemKind::FormatArg),
+    ("format_write_str", LangItemKind::FormatWriteStr),
+    ("format_write_int", LangItemKind::FormatWriteInt),
+    (
+        "format_write_formattable",
+        LangItemKind::FormatWriteFormattable,
+    ),
+    ("proto_formattable", LangItemKind::ProtoFormattable),
+    ("enum_variant_new", LangItemKind::EnumVariantNew),
+    ("drop", LangItemKind::Drop),
+    ("panic", LangItemKind::Panic),
+];
+
+impl Display for LangItemKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let LangItemKind::ImplTuple(n) = self {
+            return write!(f, "builtin_tuple_{}", n);
+        }
+
+        let (name, _) = LANG_ITEM_NAMES
+            .iter()
+            .find(|(_, kind)| kind == self)
+            .expect("lang item without a name");
+
+        write!(f, "{}", name)
+    }
 }
 
 impl TryFrom<&str> for LangItemKind {
     type Error = CodeErrorKind;
 
     fn try_from(s: &str) -> Result<Self, Self::Error> {
-        match s {
-            "slice" => Ok(LangItemKind::Slice),
-            "slice_new" => Ok(LangItemKind::SliceNew),
-            "slice_const_coerce" => Ok(LangItemKind::SliceConstCoerce),
-            "slice_const_cast" => Ok(LangItemKind::SliceConstCast),
-            "slice_index" => Ok(LangItemKind::SliceIndex),
-            "slice_range_index" => Ok(LangItemKind::SliceRangeIndex),
-
-            "range_full" => Ok(LangItemKind::RangeFull),
-            "range_from" => Ok(LangItemKind::RangeFrom),
-            "range_to" => Ok(LangItemKind::RangeTo),
-            "range_to_inclusive" => Ok(LangItemKind::RangeToInclusive),
-            "range" => Ok(LangItemKind::Range),
-            "range_inclusive" => Ok(LangItemKind::RangeInclusive),
-
-            "range_full_new" => Ok(LangItemKind::RangeFullNew),
-            "range_from_new" => Ok(LangItemKind::RangeFromNew),
-            "range_to_new" => Ok(LangItemKind::RangeToNew),
-            "range_new" => Ok(LangItemKind::RangeNew),
-            "range_to_inclusive_new" => Ok(LangItemKind::RangeToInclusiveNew),
-            "range_inclusive_new" => Ok(LangItemKind::RangeInclusiveNew),
-
-            "proto_primitive" => Ok(LangItemKind::ProtoPrimitive),
-            "proto_numeric" => Ok(LangItemKind::ProtoNumeric),
-            "proto_integer" => Ok(LangItemKind::ProtoInteger),
-            "proto_floating_point" => Ok(LangItemKind::ProtoFloatingPoint),
-            "proto_signed" => Ok(LangItemKind::ProtoSigned),
-            "proto_unsigned" => Ok(LangItemKind::ProtoUnsigned),
-            "proto_pointer" => Ok(LangItemKind::ProtoPointer),
-            "proto_zero_sized" => Ok(LangItemKind::ProtoZeroSized),
-            "proto_array" => Ok(LangItemKind::ProtoArray),
-            "proto_struct" => Ok(LangItemKind::ProtoStruct),
-            "proto_enum" => Ok(LangItemKind::ProtoEnum),
-            "proto_union" => Ok(LangItemKind::ProtoUnion),
-            "proto_tuple" => Ok(LangItemKind::ProtoTuple),
-            "proto_range" => Ok(LangItemKind::ProtoRange),
-            "proto_named_function" => Ok(LangItemKind::ProtoNamedFunction),
-            "proto_function_pointer" => Ok(LangItemKind::ProtoFunctionPointer),
-            "proto_callable" => Ok(LangItemKind::ProtoCallable),
-            "proto_array_of" => Ok(LangItemKind::ProtoArrayOf),
-            "proto_pointer_of" => Ok(LangItemKind::ProtoPointerOf),
-            "proto_range_of" => Ok(LangItemKind::ProtoRangeOf),
-            "proto_meta" => Ok(LangItemKind::ProtoMeta),
-
-            "builtin_never" => Ok(LangItemKind::ImplBuiltin(BuiltinType::Never)),
-            "builtin_void" => Ok(LangItemKind::ImplBuiltin(BuiltinType::Void)),
-            "builtin_bool" => Ok(LangItemKind::ImplBuiltin(BuiltinType::Bool)),
-            "builtin_u8" => Ok(LangItemKind::ImplBuiltin(BuiltinType::U8)),
-            "builtin_u16" => Ok(LangItemKind::ImplBuiltin(BuiltinType::U16)),
-            "builtin_u32" => Ok(LangItemKind::ImplBuiltin(BuiltinType::U32)),
-            "builtin_u64" => Ok(LangItemKind::ImplBuiltin(BuiltinType::U64)),
-            "builtin_u128" => Ok(LangItemKind::ImplBuiltin(BuiltinType::U128)),
-            "builtin_usize" => Ok(LangItemKind::ImplBuiltin(BuiltinType::USize)),
-            "builtin_i8" => Ok(LangItemKind::ImplBuiltin(BuiltinType::I8)),
-            "builtin_i16" => Ok(LangItemKind::ImplBuiltin(BuiltinType::I16)),
-            "builtin_i32" => Ok(LangItemKind::ImplBuiltin(BuiltinType::I32)),
-            "builtin_i64" => Ok(LangItemKind::ImplBuiltin(BuiltinType::I64)),
-            "builtin_i128" => Ok(LangItemKind::ImplBuiltin(BuiltinType::I128)),
-            "builtin_isize" => Ok(LangItemKind::ImplBuiltin(BuiltinType::ISize)),
-            "builtin_f32" => Ok(LangItemKind::ImplBuiltin(BuiltinType::F32)),
-            "builtin_f64" => Ok(LangItemKind::ImplBuiltin(BuiltinType::F64)),
-
-            "builtin_array" => Ok(LangItemKind::ImplArray),
-
-            "operator_eq" => Ok(LangItemKind::Operator(BinOp::Eq)),
-            "operator_neq" => Ok(LangItemKind::Operator(BinOp::Neq)),
-            "operator_lt" => Ok(LangItemKind::Operator(BinOp::Lt)),
-            "operator_lte" => Ok(LangItemKind::Operator(BinOp::LEq)),
-            "operator_gt" => Ok(LangItemKind::Operator(BinOp::Gt)),
-            "operator_gte" => Ok(LangItemKind::Operator(BinOp::GEq)),
-
-            "typeop_tuple_head_of" => Ok(LangItemKind::TypeopTupleHeadOf),
-            "typeop_tuple_tail_of" => Ok(LangItemKind::TypeopTupleTailOf),
-            "typeop_return_type_of" => Ok(LangItemKind::TypeopReturnTypeOf),
-            "typeop_arguments_of" => Ok(LangItemKind::TypeopArgumentsOf),
-            "typeop_pointer_with_mut_of" => Ok(LangItemKind::TypeopPointerWithMutOf),
-            "typeop_array_with_length_of" => Ok(LangItemKind::TypeopArrayWithLengthOf),
-            "typeop_generic_args_of" => Ok(LangItemKind::TypeopGenericArgsOf),
-            "typeop_enum_type_of" => Ok(LangItemKind::TypeopEnumTypeOf),
-
-            "entrypoint_glue" => Ok(LangItemKind::EntrypointGlue),
-            "test_case_meta" => Ok(LangItemKind::TestCaseMeta),
-            "test_case_meta_new" => Ok(LangItemKind::TestCaseMetaNew),
-
-            "dyn" => Ok(LangItemKind::Dyn),
-            "dyn_self" => Ok(LangItemKind::DynSelf),
-            "dyn_new" => Ok(LangItemKind::DynNew),
-            "dyn_const_coerce" => Ok(LangItemKind::DynConstCoerce),
-            "dyn_const_cast" => Ok(LangItemKind::DynConstCast),
-            "dyn_data" => Ok(LangItemKind::DynData),
-            "dyn_vtable_index" => Ok(LangItemKind::DynVtableIndex),
-
-            "format_arg" => Ok(LangItemKind::FormatArg),
-            "enum_variant_new" => Ok(LangItemKind::EnumVariantNew),
-
-            t => {
-                if let Some(matches) = regex!(r"^builtin_tuple_(\d+)$").captures(t) {
-                    let n = matches[1].parse::<usize>().unwrap();
-                    Ok(LangItemKind::ImplTuple(n))
-                } else {
-                    Err(CodeErrorKind::UnknownLangItem(Some(t.to_string())))
-                }
-            }
+        if let Some((_, kind)) = LANG_ITEM_NAMES.iter().find(|(name, _)| *name == s) {
+            return Ok(*kind);
+        }
+
+        if let Some(matches) = regex!(r"^builtin_tuple_(\d+)$").captures(s) {
+            let n = matches[1].parse::<usize>().unwrap();
+            Ok(LangItemKind::ImplTuple(n))
+        } else {
+            Err(CodeErrorKind::UnknownLangItem(Some(s.to_string())))
         }
     }
 }
+
+/// Lang items registered with the `#[lang(...)]` attribute, together with the lang items
+/// that the program requires.
+///
+/// Most lang items are looked up lazily during monomorphization. Requiring the ones that a
+/// language feature depends on when the feature is first used means that a standard library
+/// that is missing them is reported all at once, before monomorphization starts.
+#[derive(Default)]
+pub struct LangItemRegistry<'ast> {
+    items: HashMap<LangItemKind, (ItemP<'ast>, String)>,
+    required: HashMap<LangItemKind, Option<Span>>,
+}
+
+impl<'ast> LangItemRegistry<'ast> {
+    pub fn get(&self, kind: LangItemKind) -> Result<ItemP<'ast>, CodeErrorKind> {
+        self.items
+            .get(&kind)
+            .map(|(item, _)| *item)
+            .ok_or(CodeErrorKind::MissingLangItem(kind))
+    }
+
+    pub fn kind_of(&self, item: ItemP<'ast>) -> Option<LangItemKind> {
+        self.items
+            .iter()
+            .find(|(_, (v, _))| *v == item)
+            .map(|(k, _)| *k)
+    }
+
+    /// Registers `item` (called `name` in diagnostics) as the lang item `kind`.
+    pub fn register(
+        &mut self,
+        kind: LangItemKind,
+        item: ItemP<'ast>,
+        name: String,
+    ) -> Result<(), CodeErrorKind> {
+        if let Some((_, existing)) = self.items.get(&kind) {
+            return Err(CodeErrorKind::DuplicateLangItem(
+                kind,
+                existing.clone(),
+                name,
+            ));
+        }
+
+        self.items.insert(kind, (item, name));
+        Ok(())
+    }
+
+    /// Records that the program needs the lang items, `span` being the first place where
+    /// they are needed.
+    pub fn require(&mut self, kinds: &[LangItemKind], span: Option<Span>) {
+        for kind in kinds {
+            self.required.entry(*kind).or_insert(span);
+        }
+    }
+
+    /// Reports all the required lang items that were never registered.
+    pub fn check(&self) -> Result<(), AluminaError> {
+        let mut missing: Vec<_> = self
+            .required
+            .iter()
+            .filter(|(kind, _)| !self.items.contains_key(kind))
+            .collect();
+
+        if missing.is_empty() {
+            return Ok(());
+        }
+
+        missing.sort_by_key(|(kind, _)| kind.to_string());
+        Err(AluminaError::CodeErrors(
+            missing
+                .into_iter()
+                .map(|(kind, span)| {
+                    CodeError::from_kind(CodeErrorKind::MissingLangItem(*kind), *span)
+                })
+                .collect(),
+        ))
+    }
+}
@@ -13,7 +13,8 @@ use crate::{
 };
 
 use super::{
-    expressions::ExpressionVisitor, AstId, Attribute, ExprP, Macro, MacroParameter, Span, Statement,
+    expressions::{parse_string_literal_with_offsets, ExpressionVisitor},
+    AstId, Attribute, ExprP, LetDeclaration, Macro, MacroParameter, Span, Statement, StatementKind,
 };
 
 pub struct MacroMaker<'ast> {
@@ -78,13 +79,7 @@ impl<'ast> MacroMaker<'ast> {
         let mut has_et_cetera = false;
 
         let code = scope.code().unwrap();
-        let span = Span {
-            start: node.start_byte(),
-            end: node.end_byte(),
-            line: node.start_position().row,
-            column: node.start_position().column,
-            file: code.file_id(),
-        };
+        let span = code.span(node);
 
         if attributes.iter().any(|a| matches!(a, Attribute::Builtin)) {
             let kind = match name.unwrap() {
@@ -95,6 +90,7 @@ impl<'ast> MacroMaker<'ast> {
                 "column" => BuiltinMacroKind::Column,
                 "file" => BuiltinMacroKind::File,
                 "format_args" => BuiltinMacroKind::FormatArgs,
+                "format_to" => BuiltinMacroKind::FormatTo,
                 s => {
                     return Err(CodeErrorKind::UnknownBuiltinMacro(s.to_string()))
                         .with_span_from(&scope, node)
@@ -118,13 +114,7 @@ impl<'ast> MacroMaker<'ast> {
                         has_et_cetera = true;
                     }
 
-                    let span = Span {
-                        start: node.start_byte(),
-                        end: node.end_byte(),
-                        line: node.start_position().row,
-                        column: node.start_position().column,
-                        file: code.file_id(),
-                    };
+                    let span = code.span(node);
 
                     parameters.push(MacroParameter {
                         id,
@@ -355,10 +345,26 @@ impl<'ast> MacroExpander<'ast> {
             If(condition, then, els) => {
                 If(self.visit(condition)?, self.visit(then)?, self.visit(els)?)
             }
+            Switch(value, arms, default) => {
+                let arms: Vec<_> = arms
+                    .iter()
+                    .map(|(case, arm)| Ok((self.visit(case)?, self.visit(arm)?)))
+                    .collect::<Result<_, AluminaError>>()?;
+
+                Switch(
+                    self.visit(value)?,
+                    self.ast.arena.alloc_slice_copy(&arms),
+                    self.visit(default)?,
+                )
+            }
             StaticIf(cond, then, els) => StaticIf(cond, self.visit(then)?, self.visit(els)?),
             Cast(inner, typ) => Cast(self.visit(inner)?, typ),
+            FormatPlaceholder(sink, arg, index) => {
+                FormatPlaceholder(self.visit(sink)?, self.visit(arg)?, index)
+            }
             Continue
             | EnumValue(_, _)
+            | InferredEnumValue(_)
             | Lit(_)
             | BoundParam(_, _, _)
             | Void
@@ -389,6 +395,7 @@ impl<'ast> MacroExpander<'ast> {
 
                 LetDeclaration(crate::ast::LetDeclaration {
                     id: replacement,
+                    name: decl.name,
                     typ: decl.typ,
                     value: decl.value.map(|v| self.visit(v)).transpose()?,
                 })
@@ -424,7 +431,8 @@ impl<'ast> MacroExpander<'ast> {
             BuiltinMacroKind::Line | BuiltinMacroKind::Column => {
                 let (line, column) = self
                     .invocation_span
-                    .map(|s| (s.line + 1, s.column + 1))
+                    .and_then(|s| self.global_ctx.diag().line_column(s))
+                    .map(|(line, column)| (line + 1, column + 1))
                     .ok_or(CodeErrorKind::NoSpanInformation)
                     .with_span(self.invocation_span)?;
 
@@ -515,142 +523,305 @@ impl<'ast> MacroExpander<'ast> {
                         .with_span(self.invocation_span);
                 }
 
-                let fmt_string = string_arg!(self, 0);
+                let pieces = self.parse_format_string(0)?;
+
+                let format_arg = self
+                    .ast
+                    .lang_item(LangItemKind::FormatArg)
+                    .with_span(self.invocation_span)?;
+
+                let args: Vec<_> = pieces
+                    .into_iter()
+                    .map(|piece| {
+                        let value = match piece {
+                            FormatPiece::Str(value) => self.string_lit(value),
+                            FormatPiece::Arg(arg, _) => arg,
+                        };
+
+                        self.call(format_arg, [self.expr(ExprKind::Ref(value))])
+                    })
+                    .collect();
 
-                #[derive(PartialEq, Eq, Debug)]
-                enum State {
-                    Normal,
-                    BraceOpen,
-                    BraceClose,
+                Ok(self.expr(ExprKind::Array(args.alloc_on(self.ast))))
+            }
+            BuiltinMacroKind::FormatTo => {
+                if self.args.len() < 2 {
+                    return Err(CodeErrorKind::NotEnoughMacroArguments(2))
+                        .with_span(self.invocation_span);
                 }
 
-                let mut args = Vec::new();
-                let mut string_part = Vec::new();
-
-                let make_arg = |arg: ExprP<'ast>| {
-                    let ret = Expr {
-                        kind: ExprKind::Call(
-                            Expr {
-                                kind: ExprKind::Fn(
-                                    FnKind::Normal(
-                                        self.ast
-                                            .lang_item(LangItemKind::FormatArg)
-                                            .with_span(self.invocation_span)?,
-                                    ),
-                                    None,
-                                ),
-                                span: self.invocation_span,
-                            }
-                            .alloc_on(self.ast),
-                            [Expr {
-                                kind: ExprKind::Ref(arg),
-                                span: self.invocation_span,
-                            }
-                            .alloc_on(self.ast)]
-                            .alloc_on(self.ast),
-                        ),
-                        span: self.invocation_span,
-                    }
-                    .alloc_on(self.ast);
+                let pieces = self.parse_format_string(1)?;
+
+                // Placeholders are resolved to one of these during monomorphization
+                if pieces.iter().any(|p| matches!(p, FormatPiece::Arg(..))) {
+                    self.ast.require_lang_items(
+                        &[
+                            LangItemKind::FormatWriteInt,
+                            LangItemKind::FormatWriteFormattable,
+                            LangItemKind::ProtoFormattable,
+                        ],
+                        self.invocation_span,
+                    );
+                }
 
-                    Ok::<_, AluminaError>(ret)
+                let write_str = self
+                    .ast
+                    .lang_item(LangItemKind::FormatWriteStr)
+                    .with_span(self.invocation_span)?;
+
+                // {
+                //     let sink = <sink>;
+                //     let arg0 = <argument 0>;
+                //     ...
+                //     let result = format_write_str(sink, "...");
+                //     if result.is_ok() {
+                //         result = <placeholder 0 written into sink>;
+                //     }
+                //     ...
+                //     result
+                // }
+                let sink = self.ast.make_id();
+                let result = self.ast.make_id();
+
+                let let_statement = |id, value| Statement {
+                    kind: StatementKind::LetDeclaration(LetDeclaration {
+                        id,
+                        name: None,
+                        typ: None,
+                        value: Some(value),
+                    }),
+                    span: self.invocation_span,
                 };
 
-                let mut state = State::Normal;
-                let mut arg_index = 0;
+                // The arguments are all evaluated (in order) before anything is written, even
+                // if writing fails halfway through.
+                let mut statements = vec![let_statement(sink, self.args[0])];
+                let mut steps = Vec::new();
+                for piece in pieces {
+                    match piece {
+                        FormatPiece::Str(value) => steps.push(self.call(
+                            write_str,
+                            [self.expr(ExprKind::Local(sink)), self.string_lit(value)],
+                        )),
+                        FormatPiece::Arg(arg, index) => {
+                            let id = self.ast.make_id();
+                            statements.push(let_statement(id, arg));
+                            steps.push(self.expr(ExprKind::FormatPlaceholder(
+                                self.expr(ExprKind::Local(sink)),
+                                self.expr(ExprKind::Ref(self.expr(ExprKind::Local(id)))),
+                                index,
+                            )));
+                        }
+                    }
+                }
+
+                let mut steps = steps.into_iter();
+                statements.push(let_statement(
+                    result,
+                    steps.next().unwrap_or_else(|| {
+                        self.call(
+                            write_str,
+                            [self.expr(ExprKind::Local(sink)), self.string_lit(&[])],
+                        )
+                    }),
+                ));
+
+                for step in steps {
+                    let is_ok = self.expr(ExprKind::Call(
+                        self.expr(ExprKind::Field(
+                            self.expr(ExprKind::Local(result)),
+                            "is_ok",
+                            None,
+                        )),
+                        [].alloc_on(self.ast),
+                    ));
+
+                    statements.push(Statement {
+                        kind: StatementKind::Expression(self.expr(ExprKind::If(
+                            is_ok,
+                            self.expr(ExprKind::Assign(self.expr(ExprKind::Local(result)), step)),
+                            self.expr(ExprKind::Void),
+                        ))),
+                        span: self.invocation_span,
+                    });
+                }
+
+                Ok(self.expr(ExprKind::Block(
+                    statements.alloc_on(self.ast),
+                    self.expr(ExprKind::Local(result)),
+                )))
+            }
+        }
+    }
+
+    fn expr(&self, kind: ExprKind<'ast>) -> ExprP<'ast> {
+        Expr {
+            kind,
+            span: self.invocation_span,
+        }
+        .alloc_on(self.ast)
+    }
+
+    fn string_lit(&self, value: &[u8]) -> ExprP<'ast> {
+        self.expr(ExprKind::Lit(Lit::Str(
+            self.ast.arena.alloc_slice_copy(value),
+        )))
+    }
+
+    fn call<const N: usize>(&self, item: ItemP<'ast>, args: [ExprP<'ast>; N]) -> ExprP<'ast> {
+        self.expr(ExprKind::Call(
+            self.expr(ExprKind::Fn(FnKind::Normal(item), None)),
+            args.alloc_on(self.ast),
+        ))
+    }
+
+    /// Splits the format string in the argument `index` into string parts and placeholders,
+    /// pairing each placeholder with one of the arguments that follow it.
+    fn parse_format_string(&self, index: usize) -> Result<Vec<FormatPiece<'ast>>, AluminaError> {
+        use crate::common::CodeErrorBuilder;
+
+        #[derive(PartialEq, Eq, Debug)]
+        enum State {
+            Normal,
+            BraceOpen,
+            BraceClose,
+        }
 
-                macro_rules! push_string_part {
-                    () => {
+        let fmt_string = string_arg!(self, index);
+        let literal_span = self.args[index].span;
+        let offsets = self.literal_offsets(literal_span, fmt_string);
+        let span_at = |offset, len| span_in_literal(literal_span, offsets.as_deref(), offset, len);
+        let mut arguments = self.args[index + 1..].iter().copied();
+
+        let mut pieces = Vec::new();
+        let mut string_part = Vec::new();
+        let mut state = State::Normal;
+        let mut placeholder_index = 0;
+
+        for (offset, ch) in fmt_string.iter().copied().enumerate() {
+            let unexpected = || {
+                Err(CodeErrorKind::InvalidFormatString(format!(
+                    "unexpected {:?}",
+                    ch as char
+                )))
+                .with_span(span_at(offset, 1))
+            };
+
+            state = match state {
+                State::Normal => match ch {
+                    b'{' => State::BraceOpen,
+                    b'}' => State::BraceClose,
+                    _ => {
+                        string_part.push(ch);
+                        State::Normal
+                    }
+                },
+                State::BraceClose => match ch {
+                    b'}' => {
+                        string_part.push(ch);
+                        State::Normal
+                    }
+                    _ => return unexpected(),
+                },
+                State::BraceOpen => match ch {
+                    b'{' => {
+                        string_part.push(ch);
+                        State::Normal
+                    }
+                    b'}' => {
                         if !string_part.is_empty() {
-                            args.push(make_arg(
-                                Expr {
-                                    kind: ExprKind::Lit(Lit::Str(
-                                        self.ast.arena.alloc_slice_copy(string_part.as_slice()),
-                                    )),
-                                    span: self.invocation_span,
-                                }
-                                .alloc_on(self.ast),
-                            )?);
+                            pieces.push(FormatPiece::Str(
+                                self.ast.arena.alloc_slice_copy(string_part.as_slice()),
+                            ));
                             string_part.clear();
                         }
-                    };
-                }
 
-                for ch in fmt_string.iter().copied() {
-                    state = match state {
-                        State::Normal => match ch {
-                            b'{' => State::BraceOpen,
-                            b'}' => State::BraceClose,
-                            _ => {
-                                string_part.push(ch);
-                                State::Normal
-                            }
-                        },
-                        State::BraceClose => match ch {
-                            b'}' => {
-                                string_part.push(ch);
-                                State::Normal
-                            }
-                            _ => {
-                                return Err(CodeErrorKind::InvalidFormatString(format!(
-                                    "unexpected {:?}",
-                                    ch as char
-                                )))
-                                .with_span(self.args[0].span);
-                            }
-                        },
-                        State::BraceOpen => match ch {
-                            b'{' => {
-                                string_part.push(ch);
-                                State::Normal
-                            }
-                            b'}' => {
-                                push_string_part!();
-
-                                if self.args.len() <= arg_index + 1 {
-                                    return Err(CodeErrorKind::InvalidFormatString(
-                                        "not enough arguments".to_string(),
-                                    ))
-                                    .with_span(self.invocation_span);
-                                }
-
-                                args.push(make_arg(self.args[arg_index + 1])?);
-                                arg_index += 1;
-
-                                State::Normal
-                            }
-                            _ => {
-                                return Err(CodeErrorKind::InvalidFormatString(format!(
-                                    "unexpected {:?}",
-                                    ch as char
-                                )))
-                                .with_span(self.invocation_span);
-                            }
-                        },
-                    };
-                }
+                        let arg = arguments.next().ok_or_else(|| {
+                            CodeErrorKind::InvalidFormatString(format!(
+                                "no argument for placeholder {}",
+                                placeholder_index
+                            ))
+                        });
+                        let arg = arg.with_span(span_at(offset - 1, 2))?;
 
-                if state != State::Normal {
-                    return Err(CodeErrorKind::InvalidFormatString(
-                        "unexpected end of format string".to_string(),
-                    ))
-                    .with_span(self.invocation_span);
-                }
+                        pieces.push(FormatPiece::Arg(arg, placeholder_index));
+                        placeholder_index += 1;
 
-                if self.args.len() > arg_index + 1 {
-                    return Err(CodeErrorKind::InvalidFormatString(
-                        "too many arguments".to_string(),
-                    ))
-                    .with_span(self.invocation_span);
-                }
-                push_string_part!();
+                        State::Normal
+                    }
+                    _ => return unexpected(),
+                },
+            };
+        }
 
-                Ok(Expr {
-                    kind: ExprKind::Array(args.alloc_on(self.ast)),
-                    span: self.invocation_span,
-                }
-                .alloc_on(self.ast))
-            }
+        if state != State::Normal {
+            return Err(CodeErrorKind::InvalidFormatString(
+                "unexpected end of format string".to_string(),
+            ))
+            .with_span(literal_span);
+        }
+
+        if let Some(extra) = arguments.next() {
+            return Err(CodeErrorKind::InvalidFormatString(format!(
+                "too many arguments ({} placeholders, {} arguments)",
+                placeholder_index,
+                self.args.len() - index - 1
+            )))
+            .with_span(extra.span.or(self.invocation_span));
+        }
+
+        if !string_part.is_empty() {
+            pieces.push(FormatPiece::Str(
+                self.ast.arena.alloc_slice_copy(string_part.as_slice()),
+            ));
+        }
+
+        Ok(pieces)
+    }
+
+    /// Offsets of the bytes of the format string in its literal in the source. There are none
+    /// if the format string is not a literal, e.g. if it comes from `concat!`.
+    fn literal_offsets(&self, literal: Option<Span>, value: &[u8]) -> Option<Vec<usize>> {
+        let text = self.global_ctx.diag().source_text(literal?)?;
+        if text.len() < 2 || !text.starts_with('"') || !text.ends_with('"') {
+            return None;
+        }
+
+        match parse_string_literal_with_offsets(&text) {
+            Ok((parsed, offsets)) if parsed == value => Some(offsets),
+            _ => None,
         }
     }
 }
+
+enum FormatPiece<'ast> {
+    Str(&'ast [u8]),
+    /// An argument and the index of the placeholder it is formatted into.
+    Arg(ExprP<'ast>, usize),
+}
+
+/// Returns the span of the bytes `offset..offset + len` of a string literal, given the offsets
+/// of its bytes in the source. Without them, this is the span of the whole literal.
+fn span_in_literal(
+    literal: Option<Span>,
+    offsets: Option<&[usize]>,
+    offset: usize,
+    len: usize,
+) -> Option<Span> {
+    let (span, offsets) = match (literal, offsets) {
+        (Some(span), Some(offsets)) => (span, offsets),
+        _ => return literal,
+    };
+
+    // Up to where the next byte starts or the closing quote
+    let end = offsets
+        .get(offset + len)
+        .copied()
+        .unwrap_or((span.end - span.start) as usize - 1);
+
+    Some(Span {
+        file: span.file,
+        start: span.start + offsets[offset] as u32,
+        end: span.start + end as u32,
+    })
+}
@@ -13,19 +13,20 @@ use crate::{
         resolver::NameResolver,
         scope::{NamedItem, NamedItemKind, Scope, ScopeType},
     },
-    parser::AluminaVisitor,
+    parser::{AluminaVisitor, NodeExt},
 };
 
 use super::{
     expressions::ExpressionVisitor, lang::LangItemKind, macros::MacroMaker, types::TypeVisitor,
-    AssociatedFn, Attribute, Enum, EnumMember, Intrinsic, Mixin, MixinCell, Placeholder, Protocol,
-    Span, StaticOrConst, TyP, TypeDef,
+    AssociatedFn, Attribute, Enum, EnumMember, Expr, ExprKind, FnKind, Intrinsic, Lit, Mixin,
+    MixinCell, Placeholder, Protocol, Span, StaticOrConst, TyP, TypeDef,
 };
 
 pub struct AstItemMaker<'ast> {
     ast: &'ast AstCtx<'ast>,
     global_ctx: GlobalCtx,
     symbols: Vec<ItemP<'ast>>,
+    module_items: Vec<(ItemP<'ast>, bool)>,
     ambient_placeholders: Vec<Placeholder<'ast>>,
     in_a_macro: bool,
     local: bool,
@@ -37,6 +38,7 @@ impl<'ast> AstItemMaker<'ast> {
             ast,
             global_ctx,
             symbols: Vec::new(),
+            module_items: Vec::new(),
             ambient_placeholders: Vec::new(),
             in_a_macro,
             local: false,
@@ -48,6 +50,7 @@ impl<'ast> AstItemMaker<'ast> {
             ast,
             global_ctx,
             symbols: Vec::new(),
+            module_items: Vec::new(),
             ambient_placeholders: Vec::new(),
             in_a_macro,
             local: true,
@@ -58,6 +61,12 @@ impl<'ast> AstItemMaker<'ast> {
         self.symbols
     }
 
+    /// Functions, statics and consts declared directly in a module (rather than in an `impl`
+    /// block or a protocol), together with whether the module is a top-level one.
+    pub fn module_items(&self) -> &[(ItemP<'ast>, bool)] {
+        &self.module_items
+    }
+
     pub fn get_placeholders<'src>(
         &self,
         scope: &Scope<'ast, 'src>,
@@ -105,6 +114,59 @@ impl<'ast> AstItemMaker<'ast> {
         Ok(placeholders.alloc_on(self.ast))
     }
 
+    // Bounds from the `where` clause are merged into the bounds of the placeholders they name,
+    // which can also be the placeholders of the enclosing impl block.
+    fn apply_where_clause<'src>(
+        &self,
+        placeholders: &'ast [Placeholder<'ast>],
+        scope: &Scope<'ast, 'src>,
+        node: tree_sitter::Node<'src>,
+    ) -> Result<&'ast [Placeholder<'ast>], AluminaError> {
+        let where_clause = match node.child_by_field_name("where_clause") {
+            Some(where_clause) => where_clause,
+            None => return Ok(placeholders),
+        };
+
+        let mut placeholders = placeholders.to_vec();
+        let mut cursor = where_clause.walk();
+        for predicate in where_clause.children_by_field_name("predicate", &mut cursor) {
+            let mut visitor = TypeVisitor::new(
+                self.global_ctx.clone(),
+                self.ast,
+                scope.clone(),
+                self.in_a_macro,
+            );
+
+            let placeholder_node = predicate.expect_child("placeholder")?;
+            let placeholder = match visitor.visit(placeholder_node)? {
+                Ty::Placeholder(id) => placeholders.iter_mut().find(|p| p.id == *id),
+                _ => None,
+            };
+
+            let placeholder = match placeholder {
+                Some(placeholder) => placeholder,
+                None => {
+                    return Err(CodeErrorKind::NotAGenericParameter(
+                        scope
+                            .code()
+                            .unwrap()
+                            .node_text(placeholder_node)
+                            .to_string(),
+                    ))
+                    .with_span_from(scope, placeholder_node)
+                }
+            };
+
+            let bounds = visitor.parse_protocol_bounds(predicate)?;
+            placeholder.bounds = placeholder
+                .bounds
+                .merge(self.ast, bounds)
+                .with_span_from(scope, predicate)?;
+        }
+
+        Ok(placeholders.alloc_on(self.ast))
+    }
+
     fn resolve_associated_items<'src>(
         &self,
         impl_scopes: &[Scope<'ast, 'src>],
@@ -122,13 +184,7 @@ impl<'ast> AstItemMaker<'ast> {
                             if !names.insert(name) {
                                 self.global_ctx.diag().add_warning(CodeError::from_kind(
                                     CodeErrorKind::DuplicateNameShadow(name.to_string()),
-                                    Some(Span {
-                                        start: node.start_byte(),
-                                        end: node.end_byte(),
-                                        line: node.start_position().row,
-                                        column: node.start_position().column,
-                                        file: impl_scope.code().unwrap().file_id(),
-                                    }),
+                                    Some(impl_scope.code().unwrap().span(*node)),
                                 ));
                             }
                         }
@@ -149,16 +205,9 @@ impl<'ast> AstItemMaker<'ast> {
                             scope.clone(),
                             self.in_a_macro,
                         );
-                        let protocol_type =
-                            visitor.visit(node.child_by_field_name("protocol").unwrap())?;
+                        let protocol_type = visitor.visit(node.expect_child("protocol")?)?;
 
-                        let span = Span {
-                            start: node.start_byte(),
-                            end: node.end_byte(),
-                            line: node.start_position().row,
-                            column: node.start_position().column,
-                            file: scope.code().unwrap().file_id(),
-                        };
+                        let span = scope.code().unwrap().span(*node);
 
                         mixins.push(Mixin {
                             placeholders,
@@ -202,15 +251,9 @@ impl<'ast> AstItemMaker<'ast> {
                         scope.clone(),
                         self.in_a_macro,
                     );
-                    let field_type = visitor.visit(node.child_by_field_name("type").unwrap())?;
+                    let field_type = visitor.visit(node.expect_child("type")?)?;
 
-                    let span = Span {
-                        start: node.start_byte(),
-                        end: node.end_byte(),
-                        line: node.start_position().row,
-                        column: node.start_position().column,
-                        file: code.file_id(),
-                    };
+                    let span = code.span(node);
 
                     fields.push(Field {
                         id: self.ast.make_id(),
@@ -224,21 +267,17 @@ impl<'ast> AstItemMaker<'ast> {
         }
 
         let placeholders = self.get_placeholders(&scope)?;
-        let is_union = match code.node_text(node.child_by_field_name("kind").unwrap()) {
+        let placeholders = self.apply_where_clause(placeholders, &scope, node)?;
+        let kind_node = node.expect_child("kind")?;
+        let is_union = match code.node_text(kind_node) {
             "struct" => false,
             "union" => true,
-            _ => unimplemented!(),
+            _ => return kind_node.unexpected(),
         };
 
         let (associated_fns, mixins) = self.resolve_associated_items(impl_scopes)?;
 
-        let span = Span {
-            start: node.start_byte(),
-            end: node.end_byte(),
-            line: node.start_position().row,
-            column: node.start_position().column,
-            file: code.file_id(),
-        };
+        let span = code.span(node);
 
         let result = Item::StructLike(StructLike {
             name,
@@ -270,13 +309,7 @@ impl<'ast> AstItemMaker<'ast> {
         let code = scope.code().unwrap();
         let placeholders = self.get_placeholders(&scope)?;
 
-        let span = Span {
-            start: node.start_byte(),
-            end: node.end_byte(),
-            line: node.start_position().row,
-            column: node.start_position().column,
-            file: code.file_id(),
-        };
+        let span = code.span(node);
 
         let (associated_fns, _) = self.resolve_associated_items(&[scope])?;
 
@@ -329,13 +362,7 @@ impl<'ast> AstItemMaker<'ast> {
                         })
                         .transpose()?;
 
-                    let span = Span {
-                        start: node.start_byte(),
-                        end: node.end_byte(),
-                        line: node.start_position().row,
-                        column: node.start_position().column,
-                        file: scope.code().unwrap().file_id(),
-                    };
+                    let span = scope.code().unwrap().span(node);
 
                     members.push(EnumMember {
                         name: name.unwrap(),
@@ -350,12 +377,17 @@ impl<'ast> AstItemMaker<'ast> {
 
         let (associated_fns, mixins) = self.resolve_associated_items(impl_scopes)?;
 
-        let span = Span {
-            start: node.start_byte(),
-            end: node.end_byte(),
-            line: node.start_position().row,
-            column: node.start_position().column,
-            file: scope.code().unwrap().file_id(),
+        let span = scope.code().unwrap().span(node);
+
+        // A user-defined `name` function takes precedence over the derived one.
+        let associated_fns = if attributes.contains(&Attribute::DeriveDebug)
+            && !associated_fns.iter().any(|f| f.name == "name")
+        {
+            let mut associated_fns = associated_fns.to_vec();
+            associated_fns.push(self.make_enum_name_fn(symbol, name, &members, Some(span))?);
+            associated_fns.alloc_on(self.ast)
+        } else {
+            associated_fns
         };
 
         let result = Item::Enum(Enum {
@@ -375,6 +407,90 @@ impl<'ast> AstItemMaker<'ast> {
         Ok(())
     }
 
+    /// Synthesizes `fn name(self: E) -> &[u8]` for enums with `#[derive(debug)]`, which returns
+    /// the name of the member. Values that do not correspond to any member panic in debug mode
+    /// and return an empty string otherwise.
+    fn make_enum_name_fn(
+        &mut self,
+        symbol: ItemP<'ast>,
+        name: Option<&'ast str>,
+        members: &[EnumMember<'ast>],
+        span: Option<Span>,
+    ) -> Result<AssociatedFn<'ast>, AluminaError> {
+        let ast = self.ast;
+        let expr = |kind| Expr { kind, span }.alloc_on(ast);
+        let string = |value: &str| {
+            expr(ExprKind::Lit(Lit::Str(
+                ast.arena.alloc_slice_copy(value.as_bytes()),
+            )))
+        };
+
+        let self_id = ast.make_id();
+        let value = expr(ExprKind::Local(self_id));
+
+        let fallback = if self.global_ctx.has_flag("debug") {
+            // A missing panic lang item is reported before monomorphization.
+            ast.require_lang_items(&[LangItemKind::Panic], span);
+            match ast.lang_item(LangItemKind::Panic) {
+                Ok(panic) => {
+                    let message =
+                        format!("invalid value for enum `{}`", name.unwrap_or("<anonymous>"));
+
+                    expr(ExprKind::Call(
+                        expr(ExprKind::Fn(FnKind::Normal(panic), None)),
+                        [string(&message)].alloc_on(ast),
+                    ))
+                }
+                Err(_) => string(""),
+            }
+        } else {
+            string("")
+        };
+
+        let arms: Vec<_> = members
+            .iter()
+            .map(|member| {
+                (
+                    expr(ExprKind::EnumValue(symbol, member.id)),
+                    string(member.name),
+                )
+            })
+            .collect();
+
+        let body = expr(ExprKind::Switch(
+            value,
+            ast.arena.alloc_slice_copy(&arms),
+            fallback,
+        ));
+
+        let return_type = ast.intern_type(Ty::Slice(
+            ast.intern_type(Ty::Builtin(BuiltinType::U8)),
+            true,
+        ));
+
+        let item = ast.make_symbol();
+        item.assign(Item::Function(Function {
+            name: Some("name"),
+            attributes: &[],
+            placeholders: &[],
+            args: [Parameter {
+                id: self_id,
+                typ: ast.intern_type(Ty::NamedType(symbol)),
+                span,
+            }]
+            .alloc_on(ast),
+            return_type,
+            body: Some(body),
+            span,
+            is_local: self.local,
+            varargs: false,
+            is_protocol_fn: false,
+        }));
+        self.symbols.push(item);
+
+        Ok(AssociatedFn { name: "name", item })
+    }
+
     fn make_typedef<'src>(
         &mut self,
         name: Option<&'ast str>,
@@ -385,13 +501,7 @@ impl<'ast> AstItemMaker<'ast> {
     ) -> Result<(), AluminaError> {
         let placeholders = self.get_placeholders(&scope)?;
 
-        let span = Span {
-            start: node.start_byte(),
-            end: node.end_byte(),
-            line: node.start_position().row,
-            column: node.start_position().column,
-            file: scope.code().unwrap().file_id(),
-        };
+        let span = scope.code().unwrap().span(node);
 
         let target = node
             .child_by_field_name("inner")
@@ -429,6 +539,7 @@ impl<'ast> AstItemMaker<'ast> {
                     self.global_ctx.diag().add_warning(CodeError {
                         kind: CodeErrorKind::SelfConfusion,
                         backtrace: span.map(Marker::Span).into_iter().collect(),
+                        notes: vec![],
                     })
                 }
             }
@@ -461,15 +572,10 @@ impl<'ast> AstItemMaker<'ast> {
         let is_protocol_fn = matches!(scope.parent().map(|s| s.typ()), Some(ScopeType::Protocol));
 
         let abi = node.child_by_field_name("abi").map(|n| code.node_text(n));
-        let span = Span {
-            start: node.start_byte(),
-            end: node.end_byte(),
-            line: node.start_position().row,
-            column: node.start_position().column,
-            file: scope.code().unwrap().file_id(),
-        };
+        let span = scope.code().unwrap().span(node);
 
         let placeholders = self.get_placeholders(&scope)?;
+        let placeholders = self.apply_where_clause(placeholders, &scope, node)?;
 
         for (_name, item) in scope.inner().all_items() {
             match item.kind {
@@ -480,15 +586,9 @@ impl<'ast> AstItemMaker<'ast> {
                         scope.clone(),
                         self.in_a_macro,
                     )
-                    .visit(node.child_by_field_name("type").unwrap())?;
+                    .visit(node.expect_child("type")?)?;
 
-                    let span = Span {
-                        start: node.start_byte(),
-                        end: node.end_byte(),
-                        line: node.start_position().row,
-                        column: node.start_position().column,
-                        file: code.file_id(),
-                    };
+                    let span = code.span(node);
 
                     self.check_self_confusion(typ, Some(span));
 
@@ -636,13 +736,7 @@ impl<'ast> AstItemMaker<'ast> {
             return Err(CodeErrorKind::ExternStaticMustHaveType).with_span_from(&scope, node);
         }
 
-        let span = Span {
-            start: node.start_byte(),
-            end: node.end_byte(),
-            line: node.start_position().row,
-            column: node.start_position().column,
-            file: scope.code().unwrap().file_id(),
-        };
+        let span = scope.code().unwrap().span(node);
 
         let result = Item::StaticOrConst(StaticOrConst {
             name,
@@ -679,7 +773,7 @@ impl<'ast> AstItemMaker<'ast> {
             "enum_definition" => {
                 self.make_enum(name, symbol, node, scope, impl_scopes, attributes)?
             }
-            _ => unimplemented!(),
+            _ => return node.unexpected(),
         };
 
         Ok(())
@@ -693,9 +787,20 @@ impl<'ast> AstItemMaker<'ast> {
     ) -> Result<(), AluminaError> {
         use NamedItem as NI;
         use NamedItemKind::*;
+        if let [NI {
+            kind: Function(symbol, _, _) | Static(symbol, _, _) | Const(symbol, _, _),
+            ..
+        }] = item_group
+        {
+            if scope.typ() == ScopeType::Module {
+                self.module_items
+                    .push((*symbol, scope.path().segments.len() <= 1));
+            }
+        }
+
         match item_group {
             [NI {
-                kind: Alias(path, node),
+                kind: Alias(path, node, code),
                 ..
             }] => {
                 let mut resolver = NameResolver::new();
@@ -703,7 +808,7 @@ impl<'ast> AstItemMaker<'ast> {
                 // Resolve all aliases to avoid having non-existent uses
                 resolver
                     .resolve_item(scope.clone(), path.clone())
-                    .with_span_from(&scope, *node)?;
+                    .with_span_from_code(code, *node)?;
             }
             [NI {
                 kind: Module(module),
@@ -758,9 +863,10 @@ impl<'ast> AstItemMaker<'ast> {
                 self.make_static_or_const(false, name, *symbol, *node, scope.clone(), attributes)?;
             }
             [NI {
-                kind: Const(symbol, node),
+                kind: Const(symbol, node, code),
                 attributes,
             }] => {
+                let scope = scope.with_code(code);
                 self.make_static_or_const(true, name, *symbol, *node, scope, attributes)?;
             }
             [NI {
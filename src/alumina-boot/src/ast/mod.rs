@@ -5,8 +5,10 @@ pub mod maker;
 pub mod rebind;
 pub mod types;
 
-use self::lang::LangItemKind;
-use crate::common::{Allocatable, ArenaAllocatable, CodeErrorKind, FileId, Incrementable};
+use self::lang::{LangItemKind, LangItemRegistry};
+use crate::common::{
+    Allocatable, AluminaError, ArenaAllocatable, CodeErrorKind, FileId, Incrementable,
+};
 use crate::intrinsics::IntrinsicKind;
 use crate::name_resolution::path::{Path, PathSegment};
 use crate::name_resolution::scope::BoundItemType;
@@ -32,7 +34,7 @@ pub struct AstCtx<'ast> {
     pub arena: Bump,
     pub counter: Cell<usize>,
     types: RefCell<HashSet<TyP<'ast>>>,
-    lang_items: RefCell<HashMap<LangItemKind, ItemP<'ast>>>,
+    lang_items: RefCell<LangItemRegistry<'ast>>,
     test_metadata: RefCell<HashMap<ItemP<'ast>, TestMetadata<'ast>>>,
 }
 
@@ -42,7 +44,7 @@ impl<'ast> AstCtx<'ast> {
             arena: Bump::new(),
             counter: Cell::new(0),
             types: RefCell::new(HashSet::new()),
-            lang_items: RefCell::new(HashMap::new()),
+            lang_items: RefCell::new(LangItemRegistry::default()),
             test_metadata: RefCell::new(HashMap::new()),
         }
     }
@@ -54,24 +56,28 @@ impl<'ast> AstCtx<'ast> {
     }
 
     pub fn lang_item(&self, kind: LangItemKind) -> Result<ItemP<'ast>, CodeErrorKind> {
-        self.lang_items
-            .borrow()
-            .get(&kind)
-            .copied()
-            .ok_or(CodeErrorKind::MissingLangItem(kind))
+        self.lang_items.borrow().get(kind)
     }
 
     pub fn lang_item_kind(&self, item: ItemP<'ast>) -> Option<LangItemKind> {
-        self.lang_items
-            .borrow()
-            .iter()
-            .find(|(_, v)| **v == item)
-            .map(|(k, _)| k)
-            .copied()
+        self.lang_items.borrow().kind_of(item)
     }
 
-    pub fn add_lang_item(&self, kind: LangItemKind, item: ItemP<'ast>) {
-        self.lang_items.borrow_mut().insert(kind, item);
+    pub fn add_lang_item(
+        &self,
+        kind: LangItemKind,
+        item: ItemP<'ast>,
+        name: String,
+    ) -> Result<(), CodeErrorKind> {
+        self.lang_items.borrow_mut().register(kind, item, name)
+    }
+
+    pub fn require_lang_items(&self, kinds: &[LangItemKind], span: Option<Span>) {
+        self.lang_items.borrow_mut().require(kinds, span)
+    }
+
+    pub fn check_lang_items(&self) -> Result<(), AluminaError> {
+        self.lang_items.borrow().check()
     }
 
     pub fn add_test_metadata(&'ast self, item: ItemP<'ast>, metadata: TestMetadata<'ast>) {
@@ -516,6 +522,47 @@ pub struct ProtocolBounds<'ast> {
     pub bounds: &'ast [Bound<'ast>],
 }
 
+impl<'ast> ProtocolBounds<'ast> {
+    /// Combines the bounds on the same placeholder (e.g. inline and from a `where` clause),
+    /// skipping duplicates.
+    pub fn merge(
+        &self,
+        ast: &'ast AstCtx<'ast>,
+        other: ProtocolBounds<'ast>,
+    ) -> Result<ProtocolBounds<'ast>, CodeErrorKind> {
+        if other.bounds.is_empty() {
+            return Ok(*self);
+        }
+
+        if self.bounds.is_empty() {
+            return Ok(other);
+        }
+
+        // A single bound is the same whether it's `+` or `|`, but alternatives cannot be
+        // combined with anything else.
+        let is_any =
+            |b: &ProtocolBounds<'_>| b.kind == ProtocolBoundsKind::Any && b.bounds.len() > 1;
+        if is_any(self) || is_any(&other) {
+            return Err(CodeErrorKind::MixedProtocolBounds);
+        }
+
+        let mut bounds = self.bounds.to_vec();
+        for bound in other.bounds {
+            if !bounds
+                .iter()
+                .any(|b| b.negated == bound.negated && b.typ == bound.typ)
+            {
+                bounds.push(*bound);
+            }
+        }
+
+        Ok(ProtocolBounds {
+            kind: ProtocolBoundsKind::All,
+            bounds: bounds.alloc_on(ast),
+        })
+    }
+}
+
 #[derive(Debug, Clone, Copy)]
 pub struct Placeholder<'ast> {
     pub id: AstId,
@@ -607,6 +654,7 @@ pub enum BuiltinMacroKind {
     File,
     IncludeBytes,
     FormatArgs,
+    FormatTo,
 }
 
 #[derive(Debug)]
@@ -645,6 +693,7 @@ pub struct StaticOrConst<'ast> {
 #[derive(Debug, PartialEq, Eq, Clone, Hash)]
 pub struct LetDeclaration<'ast> {
     pub id: AstId,
+    pub name: Option<&'ast str>,
     pub typ: Option<TyP<'ast>>,
     pub value: Option<ExprP<'ast>>,
 }
@@ -718,6 +767,7 @@ pub enum Attribute {
     StaticConstructor,
     Codegen(CodegenType),
     LinkName(usize, [u8; 255]),
+    DeriveDebug,
 }
 
 #[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
@@ -789,6 +839,8 @@ pub enum ExprKind<'ast> {
     Static(ItemP<'ast>, Option<&'ast [TyP<'ast>]>),
     Const(ItemP<'ast>),
     EnumValue(ItemP<'ast>, AstId),
+    // Enum member referred to by its name only, resolved against the expected type
+    InferredEnumValue(&'ast str),
     Lit(Lit<'ast>),
     Loop(ExprP<'ast>),
     EtCetera(ExprP<'ast>),
@@ -805,19 +857,37 @@ pub enum ExprKind<'ast> {
     Index(ExprP<'ast>, ExprP<'ast>),
     Range(Option<ExprP<'ast>>, Option<ExprP<'ast>>, bool),
     If(ExprP<'ast>, ExprP<'ast>, ExprP<'ast>),
+    // Scrutinee, the arms with their (constant) case values and the default arm. Only
+    // synthesized by the compiler, the `switch` expression is an `if` chain.
+    Switch(ExprP<'ast>, &'ast [(ExprP<'ast>, ExprP<'ast>)], ExprP<'ast>),
     StaticIf(StaticIfCondition<'ast>, ExprP<'ast>, ExprP<'ast>),
     Cast(ExprP<'ast>, TyP<'ast>),
+    // A `format_to!` placeholder: sink, a pointer to the argument and the placeholder index
+    FormatPlaceholder(ExprP<'ast>, ExprP<'ast>, usize),
 
     Void,
 }
 
+/// A range of bytes in a source file.
+///
+/// Every expression carries a span, so it is kept small. The line and column are not stored,
+/// they are looked up in the line table of the file when needed (see
+/// [`DiagnosticContext::line_column`](crate::diagnostics::DiagnosticContext::line_column)).
 #[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
 pub struct Span {
-    pub start: usize,
-    pub end: usize,
-    pub line: usize,
-    pub column: usize,
     pub file: FileId,
+    pub start: u32,
+    pub end: u32,
+}
+
+impl Span {
+    pub fn new(file: FileId, range: std::ops::Range<usize>) -> Self {
+        Span {
+            file,
+            start: range.start as u32,
+            end: range.end as u32,
+        }
+    }
 }
 
 #[derive(Debug, PartialEq, Eq, Clone, Hash)]
@@ -126,6 +126,7 @@ impl<'ast> Rebinder<'ast> {
             Expression(expr) => Expression(self.visit_expr(expr)?),
             LetDeclaration(decl) => LetDeclaration(crate::ast::LetDeclaration {
                 id: decl.id,
+                name: decl.name,
                 typ: decl.typ.map(|t| self.visit_typ(t)).transpose()?,
                 value: decl.value.map(|v| self.visit_expr(v)).transpose()?,
             }),
@@ -212,7 +213,22 @@ impl<'ast> Rebinder<'ast> {
                 self.visit_expr(then)?,
                 self.visit_expr(els)?,
             ),
+            Switch(value, arms, default) => {
+                let arms: Vec<_> = arms
+                    .iter()
+                    .map(|(case, arm)| Ok((self.visit_expr(case)?, self.visit_expr(arm)?)))
+                    .collect::<Result<_, AluminaError>>()?;
+
+                Switch(
+                    self.visit_expr(value)?,
+                    self.ast.arena.alloc_slice_copy(&arms),
+                    self.visit_expr(default)?,
+                )
+            }
             Cast(inner, typ) => Cast(self.visit_expr(inner)?, self.visit_typ(typ)?),
+            FormatPlaceholder(sink, arg, index) => {
+                FormatPlaceholder(self.visit_expr(sink)?, self.visit_expr(arg)?, index)
+            }
             Defered(ref def) => Defered(crate::ast::Defered {
                 typ: self.visit_typ(def.typ)?,
                 name: def.name,
@@ -279,6 +295,7 @@ impl<'ast> Rebinder<'ast> {
             | BoundParam(_, _, _)
             | Continue
             | EnumValue(_, _)
+            | InferredEnumValue(_)
             | Lit(_)
             | Void
             | Const(_) => expr.kind.clone(),
@@ -1,3 +1,4 @@
+use crate::ast::lang::LangItemKind;
 use crate::ast::AstCtx;
 use crate::common::{ArenaAllocatable, CodeErrorKind};
 use crate::global_ctx::GlobalCtx;
@@ -15,7 +16,7 @@ use crate::{
 };
 
 use super::expressions::ExpressionVisitor;
-use super::{Bound, Defered, ProtocolBounds, ProtocolBoundsKind, Span, StaticIfCondition};
+use super::{Bound, Defered, ProtocolBounds, ProtocolBoundsKind, StaticIfCondition};
 
 pub struct TypeVisitor<'ast, 'src> {
     global_ctx: GlobalCtx,
@@ -23,6 +24,8 @@ pub struct TypeVisitor<'ast, 'src> {
     code: &'src ParseCtx<'src>,
     scope: Scope<'ast, 'src>,
     in_a_macro: bool,
+    depth: usize,
+    max_depth: usize,
 }
 
 impl<'ast, 'src> TypeVisitor<'ast, 'src> {
@@ -33,6 +36,7 @@ impl<'ast, 'src> TypeVisitor<'ast, 'src> {
         in_a_macro: bool,
     ) -> Self {
         TypeVisitor {
+            max_depth: global_ctx.max_nesting_depth(),
             global_ctx,
             ast,
             code: scope
@@ -40,6 +44,7 @@ impl<'ast, 'src> TypeVisitor<'ast, 'src> {
                 .expect("cannot run on scope without parse context"),
             scope,
             in_a_macro,
+            depth: 0,
         }
     }
 
@@ -60,16 +65,21 @@ impl<'ast, 'src> TypeVisitor<'ast, 'src> {
 
         let mut cursor = node.walk();
         for bound in node.children_by_field_name("bound", &mut cursor) {
+            let negated = bound.child_by_field_name("negated").is_some();
+            let typ = self.visit(bound.child_by_field_name("type").unwrap())?;
+
+            // Duplicate bounds are harmless, but there is no need to check them twice
+            if bounds
+                .iter()
+                .any(|b: &Bound<'_>| b.negated == negated && b.typ == typ)
+            {
+                continue;
+            }
+
             bounds.push(Bound {
-                span: Some(Span {
-                    start: bound.start_byte(),
-                    end: bound.end_byte(),
-                    line: bound.start_position().row,
-                    column: bound.start_position().column,
-                    file: self.scope.code().unwrap().file_id(),
-                }),
-                negated: bound.child_by_field_name("negated").is_some(),
-                typ: self.visit(bound.child_by_field_name("type").unwrap())?,
+                span: Some(self.scope.code().unwrap().span(bound)),
+                negated,
+                typ,
             });
         }
 
@@ -128,11 +138,29 @@ impl<'ast, 'src> TypeVisitor<'ast, 'src> {
 
         Ok((elements.alloc_on(self.ast), type_node))
     }
+
+    fn require_lang_items(&self, kinds: &[LangItemKind], node: tree_sitter::Node<'src>) {
+        let span = self.code.span(node);
+
+        self.ast.require_lang_items(kinds, Some(span));
+    }
 }
 
 impl<'ast, 'src> AluminaVisitor<'src> for TypeVisitor<'ast, 'src> {
     type ReturnType = Result<TyP<'ast>, AluminaError>;
 
+    fn visit(&mut self, node: tree_sitter::Node<'src>) -> Self::ReturnType {
+        if self.depth >= self.max_depth {
+            return Err(CodeErrorKind::TooDeeplyNested).with_span_from(&self.scope, node);
+        }
+
+        self.depth += 1;
+        let result = self.dispatch(node);
+        self.depth -= 1;
+
+        result
+    }
+
     fn visit_primitive_type(&mut self, node: tree_sitter::Node<'src>) -> Self::ReturnType {
         let builtin = match self.code.node_text(node) {
             "void" => Ty::Builtin(BuiltinType::Void),
@@ -172,6 +200,16 @@ impl<'ast, 'src> AluminaVisitor<'src> for TypeVisitor<'ast, 'src> {
         let ty = self.visit(node.child_by_field_name("inner").unwrap())?;
         let is_mut = node.child_by_field_name("mut").is_some();
 
+        self.require_lang_items(
+            &[
+                LangItemKind::Slice,
+                LangItemKind::SliceNew,
+                LangItemKind::SliceIndex,
+                LangItemKind::SliceConstCoerce,
+            ],
+            node,
+        );
+
         Ok(self.ast.intern_type(Ty::Slice(ty, !is_mut)))
     }
 
@@ -184,6 +222,18 @@ impl<'ast, 'src> AluminaVisitor<'src> for TypeVisitor<'ast, 'src> {
             .map(|child| self.visit(child))
             .collect::<Result<Vec<_>, _>>()?;
 
+        self.require_lang_items(
+            &[
+                LangItemKind::Dyn,
+                LangItemKind::DynNew,
+                LangItemKind::DynSelf,
+                LangItemKind::DynData,
+                LangItemKind::DynVtableIndex,
+                LangItemKind::DynConstCoerce,
+            ],
+            node,
+        );
+
         Ok(self
             .ast
             .intern_type(Ty::Dyn(inner.alloc_on(self.ast), !is_mut)))
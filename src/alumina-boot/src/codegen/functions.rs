@@ -1,6 +1,6 @@
 use crate::{
     ast::{Attribute, BinOp, BuiltinType, CodegenType, UnOp},
-    codegen::CName,
+    codegen::{CName, CodegenDialect},
     common::AluminaError,
     intrinsics::CodegenIntrinsicKind,
     ir::{
@@ -42,12 +42,15 @@ pub fn write_function_signature<'ir, 'gen>(
 
     let mut attributes = if item.attributes.contains(&Attribute::ForceInline) {
         is_inline = true;
-        "__attribute__((always_inline)) inline ".to_string()
+        format!(
+            "__attribute__((always_inline)) {} ",
+            ctx.dialect.keyword("inline")
+        )
     } else if item.attributes.contains(&Attribute::NoInline) {
         "__attribute__((noinline)) ".to_string()
     } else if item.attributes.contains(&Attribute::Inline) {
         is_inline = true;
-        "inline ".to_string()
+        format!("{} ", ctx.dialect.keyword("inline"))
     } else if item.attributes.contains(&Attribute::StaticConstructor) {
         "__attribute__((constructor)) ".to_string()
     } else {
@@ -59,7 +62,7 @@ pub fn write_function_signature<'ir, 'gen>(
     }
 
     if item.return_type.is_never() {
-        attributes = format!("_Noreturn {}", attributes);
+        attributes = format!("{} {}", ctx.dialect.keyword("_Noreturn"), attributes);
     }
 
     let return_type = if item.return_type.is_zero_sized() {
@@ -83,7 +86,13 @@ pub fn write_function_signature<'ir, 'gen>(
         if idx > 0 {
             w!(buf, ", ");
         }
-        w!(buf, "{} {}", ctx.get_type(arg.ty), name);
+        match arg.ty {
+            // Extern functions take raw C arrays, see `write_expr`
+            Ty::Array(inner, len) if item.is_extern => {
+                w!(buf, "{} {}[{}]", ctx.get_type(inner), name, len)
+            }
+            _ => w!(buf, "{} {}", ctx.get_type(arg.ty), name),
+        }
     }
 
     if item.varargs {
@@ -107,7 +116,7 @@ pub fn write_function_signature<'ir, 'gen>(
         .next();
 
     if let Some(link_name) = link_name {
-        w!(buf, " asm({})", link_name);
+        w!(buf, " {}({})", ctx.dialect.keyword("asm"), link_name);
     }
 
     Ok(())
@@ -263,6 +272,23 @@ impl<'ir, 'gen> FunctionWriter<'ir, 'gen> {
         Ok(())
     }
 
+    fn write_switch_arm(&mut self, arm: ExprP<'ir>) -> Result<(), AluminaError> {
+        let (stmts, ret) = match arm.kind {
+            ExprKind::Block(stmts, ret) => (stmts, ret),
+            _ => (&[][..], arm),
+        };
+
+        for stmt in stmts {
+            self.write_stmt(stmt)?;
+        }
+
+        if !ret.is_void() {
+            self.write_stmt(&Statement::Expression(ret))?;
+        }
+
+        Ok(())
+    }
+
     pub fn write_expr(&mut self, expr: &ExprP<'ir>, bare_block: bool) -> Result<(), AluminaError> {
         self.type_writer.add_type(expr.ty)?;
 
@@ -281,6 +307,11 @@ impl<'ir, 'gen> FunctionWriter<'ir, 'gen> {
                 self.write_expr(rhs, false)?;
             }
             ExprKind::Call(callee, args) => {
+                let is_extern = match callee.ty {
+                    Ty::NamedFunction(item) => item.get_function().is_ok_and(|f| f.is_extern),
+                    _ => false,
+                };
+
                 self.write_expr(callee, false)?;
                 w!(self.fn_bodies, "(");
                 for (idx, arg) in args
@@ -292,6 +323,11 @@ impl<'ir, 'gen> FunctionWriter<'ir, 'gen> {
                         w!(self.fn_bodies, ", ");
                     }
                     self.write_expr(arg, false)?;
+                    // The array in the wrapper struct, which decays to a pointer to the first
+                    // element just like in C.
+                    if is_extern && matches!(arg.ty, Ty::Array(_, _)) {
+                        w!(self.fn_bodies, ".__data");
+                    }
                 }
                 w!(self.fn_bodies, ")");
             }
@@ -445,6 +481,33 @@ impl<'ir, 'gen> FunctionWriter<'ir, 'gen> {
 
                 w!(self.fn_bodies, "}}");
             }
+            ExprKind::Switch(value, arms, default) => {
+                w!(self.fn_bodies, "switch (");
+                self.write_expr(value, false)?;
+                w!(self.fn_bodies, ") {{\n");
+                for (case, arm) in arms.iter() {
+                    self.indent();
+                    w!(self.fn_bodies, "case ");
+                    self.write_const_val(*case);
+                    w!(self.fn_bodies, ": {{\n");
+                    self.indent += 2;
+                    self.write_switch_arm(arm)?;
+                    self.indent();
+                    w!(self.fn_bodies, "break;\n");
+                    self.indent -= 2;
+                    self.indent();
+                    w!(self.fn_bodies, "}}\n");
+                }
+                self.indent();
+                w!(self.fn_bodies, "default: {{\n");
+                self.indent += 2;
+                self.write_switch_arm(default)?;
+                self.indent -= 2;
+                self.indent();
+                w!(self.fn_bodies, "}}\n");
+                self.indent();
+                w!(self.fn_bodies, "}}");
+            }
             ExprKind::If(cond, then, els) => {
                 w!(self.fn_bodies, "(");
                 self.write_expr(cond, false)?;
@@ -471,6 +534,19 @@ impl<'ir, 'gen> FunctionWriter<'ir, 'gen> {
                 w!(self.fn_bodies, "__builtin_unreachable()");
             }
             ExprKind::CodegenIntrinsic(kind) => match kind {
+                CodegenIntrinsicKind::SizeOfLike("_Alignof", typ)
+                    if self.ctx.dialect == CodegenDialect::C89 =>
+                {
+                    // GNU `__alignof__` gives the preferred alignment, which is larger than the
+                    // alignment in structs for some types on 32-bit x86, so it is measured in a
+                    // struct like `_Alignof` does instead.
+                    self.type_writer.add_type(typ)?;
+                    w!(
+                        self.fn_bodies,
+                        "offsetof(struct {{ char c; {} t; }}, t)",
+                        self.ctx.get_type(typ)
+                    );
+                }
                 CodegenIntrinsicKind::SizeOfLike(n, typ) => {
                     self.type_writer.add_type(typ)?;
                     w!(self.fn_bodies, "{}({})", n, self.ctx.get_type(typ));
@@ -482,7 +558,12 @@ impl<'ir, 'gen> FunctionWriter<'ir, 'gen> {
                     w!(self.fn_bodies, "{}", n);
                 }
                 CodegenIntrinsicKind::Asm(n) => {
-                    w!(self.fn_bodies, "asm volatile({:?})", *n);
+                    w!(
+                        self.fn_bodies,
+                        "{} volatile({:?})",
+                        self.ctx.dialect.keyword("asm"),
+                        *n
+                    );
                 }
             },
             ExprKind::Void => {}
@@ -544,13 +625,18 @@ impl<'ir, 'gen> FunctionWriter<'ir, 'gen> {
     ) -> Result<(), AluminaError> {
         self.type_writer.add_type(item.typ)?;
 
-        let attributes = if item.attributes.contains(&Attribute::ThreadLocal) {
-            " __thread"
+        // Thread-locals are plain statics in programs compiled without threading support.
+        let thread_local = if item.attributes.contains(&Attribute::ThreadLocal)
+            && self.ctx.global_ctx.cfg("threading").is_some()
+        {
+            format!("{} ", self.ctx.dialect.keyword("_Thread_local"))
         } else {
-            ""
+            String::new()
         };
 
-        if item.r#extern {
+        let should_export = item.attributes.contains(&Attribute::Export);
+
+        if item.r#extern || should_export {
             self.ctx
                 .register_name(id, CName::Native(item.name.unwrap()));
         } else if let Some(name) = item.name {
@@ -558,22 +644,43 @@ impl<'ir, 'gen> FunctionWriter<'ir, 'gen> {
         }
 
         if !item.typ.is_zero_sized() {
-            if item.r#extern {
-                w!(
-                    self.fn_decls,
-                    "\nextern{} {} {};",
-                    attributes,
-                    self.ctx.get_type(item.typ),
-                    self.ctx.get_name(id)
-                );
+            let storage_class = if item.r#extern {
+                "extern "
+            } else if should_export {
+                ""
             } else {
+                "static "
+            };
+
+            w!(
+                self.fn_decls,
+                "\n{}{}{} {};",
+                storage_class,
+                thread_local,
+                self.ctx.get_type(item.typ),
+                self.ctx.get_name(id)
+            );
+
+            // Thread-locals have a constant initializer instead of being initialized in the
+            // static constructor. The declaration above is a tentative definition.
+            if let Some(Expr {
+                kind: ExprKind::ConstValue(value),
+                ..
+            }) = item
+                .init
+                .filter(|_| item.attributes.contains(&Attribute::ThreadLocal))
+            {
                 w!(
-                    self.fn_decls,
-                    "\nstatic{} {} {};",
-                    attributes,
+                    self.fn_bodies,
+                    "{}{}{} {} = (({})",
+                    storage_class,
+                    thread_local,
                     self.ctx.get_type(item.typ),
-                    self.ctx.get_name(id)
+                    self.ctx.get_name(id),
+                    self.ctx.get_type(item.typ)
                 );
+                self.write_const_val(*value);
+                w!(self.fn_bodies, ");\n");
             }
         }
 
@@ -625,6 +732,7 @@ impl<'ir, 'gen> FunctionWriter<'ir, 'gen> {
                 kind: ExprKind::Unreachable,
                 value_type: ValueType::RValue,
                 is_const: false,
+                span: None,
             }))?;
         } else {
             for def in body.local_defs.iter() {
@@ -5,6 +5,7 @@ use std::{
     cell::{Cell, RefCell},
     collections::HashMap,
     fmt::{Display, Write},
+    str::FromStr,
 };
 
 use crate::{
@@ -27,6 +28,49 @@ pub(crate) use w;
 
 use self::{functions::FunctionWriter, types::TypeWriter};
 
+/// Flavor of C that the program is emitted in (`--codegen-dialect`).
+///
+/// In both dialects, all the locals are declared at the top of the function and aggregates
+/// are built in temporaries rather than with compound literals. They differ in the keywords
+/// that were added in C99 and C11, which the C89 dialect replaces with the GNU spellings that
+/// are also accepted in C89 mode. GNU extensions (statement expressions, `__int128`,
+/// attributes) are required in both.
+#[derive(Clone, Debug, Copy, PartialEq, Eq)]
+pub enum CodegenDialect {
+    /// C11 (`c11`), the default
+    C11,
+    /// C89 with GNU extensions (`c89`)
+    C89,
+}
+
+impl CodegenDialect {
+    /// Spelling of a C keyword (or the `asm` extension) in this dialect.
+    pub fn keyword(self, keyword: &str) -> &str {
+        match (self, keyword) {
+            (CodegenDialect::C89, "inline") => "__inline__",
+            (CodegenDialect::C89, "_Noreturn") => "__attribute__((noreturn))",
+            (CodegenDialect::C89, "_Thread_local") => "__thread",
+            (CodegenDialect::C89, "asm") => "__asm__",
+            _ => keyword,
+        }
+    }
+}
+
+impl FromStr for CodegenDialect {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "c11" => Ok(CodegenDialect::C11),
+            "c89" => Ok(CodegenDialect::C89),
+            _ => Err(format!(
+                "unknown codegen dialect `{}` (expected `c11` or `c89`)",
+                s
+            )),
+        }
+    }
+}
+
 #[derive(Clone, Debug, Copy, PartialEq, Eq)]
 pub enum CName<'gen> {
     Native(&'gen str),
@@ -52,6 +96,7 @@ impl<'gen> CName<'gen> {
 
 pub struct CodegenCtx<'ir, 'gen> {
     global_ctx: GlobalCtx,
+    dialect: CodegenDialect,
     id_map: RefCell<HashMap<IrId, CName<'gen>>>,
     type_map: RefCell<HashMap<TyP<'ir>, CName<'gen>>>,
     counter: Cell<usize>,
@@ -59,9 +104,10 @@ pub struct CodegenCtx<'ir, 'gen> {
 }
 
 impl<'ir, 'gen> CodegenCtx<'ir, 'gen> {
-    pub fn new(global_ctx: GlobalCtx) -> Self {
+    pub fn new(global_ctx: GlobalCtx, dialect: CodegenDialect) -> Self {
         Self {
             global_ctx,
+            dialect,
             arena: Bump::new(),
             id_map: RefCell::new(HashMap::new()),
             type_map: RefCell::new(HashMap::new()),
@@ -124,8 +170,12 @@ impl Display for CName<'_> {
     }
 }
 
-pub fn codegen(global_ctx: GlobalCtx, items: &[IRItemP<'_>]) -> Result<String, AluminaError> {
-    let ctx = CodegenCtx::new(global_ctx);
+pub fn codegen(
+    global_ctx: GlobalCtx,
+    dialect: CodegenDialect,
+    items: &[IRItemP<'_>],
+) -> Result<String, AluminaError> {
+    let ctx = CodegenCtx::new(global_ctx, dialect);
     let type_writer = TypeWriter::new(&ctx);
 
     type_writer.add_type(&Ty::Builtin(BuiltinType::Void))?;
@@ -266,6 +266,10 @@ impl<'ir, 'gen> TypeWriterInner<'ir, 'gen> {
 
                 self.write_type_body(inner)?;
 
+                // Arrays are wrapped in a struct so that they can be assigned, passed to and
+                // returned from functions by value. A struct with a single array member has the
+                // same size, alignment and layout as the array itself, so struct fields of array
+                // type still match the C definition of the struct.
                 w!(self.type_bodies, "struct {} {{\n", name);
                 w!(self.type_bodies, "  {} __data[{}];\n", inner_name, len);
                 w!(self.type_bodies, "}};\n");
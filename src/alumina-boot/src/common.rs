@@ -21,6 +21,15 @@ macro_rules! ice {
 
 pub(crate) use ice;
 
+/// Stack space reserved for each level of nesting of expressions and types. The visitors and
+/// the monomorphizer are recursive, so the nesting depth is limited to what fits on the stack of
+/// the compilation thread, which keeps pathological inputs from overflowing it. The deepest
+/// constructs (e.g. nested references) use about 28 KiB per level in debug builds.
+pub const STACK_PER_NESTING_LEVEL: usize = 32 * 1024;
+
+/// Default stack size of the compilation thread in MiB
+pub const DEFAULT_STACK_SIZE: usize = 64;
+
 #[derive(Debug, Error)]
 pub enum AluminaError {
     #[error("code errors: {0:?}")]
@@ -40,6 +49,12 @@ pub enum CodeErrorKind {
     // Errors
     #[error("syntax error: unexpected `{}`", .0)]
     ParseError(String),
+    #[error("syntax error: expected {}, found `{}`", .0, .1)]
+    UnexpectedSyntax(String, String),
+    #[error("syntax error: expected {}", .0)]
+    MissingSyntax(String),
+    #[error("too many syntax errors, {} more not shown", .0)]
+    TooManySyntaxErrors(usize),
     #[error("unexpected `{}` here", .0)]
     Unexpected(String),
     #[error("could not resolve the path `{}`", .0)]
@@ -72,6 +87,8 @@ pub enum CodeErrorKind {
     TypeMismatch(String, String),
     #[error("branches have incompatible types (`{}`, `{}`)", .0, .1)]
     MismatchedBranchTypes(String, String),
+    #[error("`if` without an `else` cannot produce a value (the branch has type `{}`)", .0)]
+    IfWithoutElse(String),
     #[error("invalid escape sequence")]
     InvalidEscapeSequence,
     #[error("invalid `#[cfg(...)]` attribute")]
@@ -116,8 +133,10 @@ pub enum CodeErrorKind {
     CannotReferenceLocal(String),
     #[error("local items cannot bind ambient generic placeholders (yet)")]
     LocalItemsCannotBindGenericPlaceholders,
-    #[error("missing lang item: {:?}", .0)]
+    #[error("missing lang item `{}`", .0)]
     MissingLangItem(LangItemKind),
+    #[error("lang item `{}` is already registered as `{}` (also registered as `{}`)", .0, .1, .2)]
+    DuplicateLangItem(LangItemKind, String, String),
     #[error("only slices can be range-indexed")]
     RangeIndexNonSlice,
     #[error("internal error: {}", .0)]
@@ -130,7 +149,11 @@ pub enum CodeErrorKind {
     UnsupportedABI(String),
     #[error("unknown intrinsic `{}`", .0)]
     UnknownIntrinsic(String),
-    #[error("unknown lang item {:?}", .0)]
+    #[error(
+        "unknown lang item {}(valid lang items are: {})",
+        .0.as_ref().map(|n| format!("`{}` ", n)).unwrap_or_default(),
+        LangItemKind::names().collect::<Vec<_>>().join(", ")
+    )]
     UnknownLangItem(Option<String>),
     #[error("this cannot be a lang item")]
     CannotBeALangItem,
@@ -227,6 +250,12 @@ pub enum CodeErrorKind {
     ClosuresAreNotFns,
     #[error("thread local storage is not supported")]
     ThreadLocalNotSupported,
+    #[error("thread-local statics can only have a constant initializer")]
+    NonConstThreadLocalInit,
+    #[error("extern functions cannot return arrays (`{}`), return a pointer to the first element instead", .0)]
+    ExternReturnsArray(String),
+    #[error("`{}` takes raw C arrays, so it can only be called directly and not through a function pointer", .0)]
+    ExternArraysInFnPointer(String),
     #[error("dyn requires a protocol")]
     NonProtocolDyn,
     #[error("builtin protocols cannot be used with `dyn`")]
@@ -243,10 +272,36 @@ pub enum CodeErrorKind {
     NonDynnableFunction(String),
     #[error("invalid format string ({})", .0)]
     InvalidFormatString(String),
+    #[error("type `{}` cannot be formatted (placeholder {})", .0, .1)]
+    NotFormattable(String, usize),
     #[error("cannot read file `{}`", .0)]
     CannotReadFile(String),
     #[error("type alias must have a target")] // unless it is a blessed builtin :)
     TypedefWithoutTarget,
+    #[error("not all control paths return a value")]
+    NotAllPathsReturn,
+    #[error("use of possibly uninitialized variable `{}`", .0)]
+    PossiblyUninitialized(String),
+    #[error("`{}` is not assigned on this branch", .0)]
+    NotAssignedOnBranch(String),
+    #[error("cannot move out of a field of a variable that is dropped at the end of its scope")]
+    PartialMove,
+    #[error("`{}` is not a generic parameter of this item", .0)]
+    NotAGenericParameter(String),
+    #[error("alternative (`|`) bounds cannot be combined with other bounds on the same parameter")]
+    MixedProtocolBounds,
+    #[error("no field named `{}` in `{}` (available fields: {})", .0, .1, .2)]
+    NoFieldNamed(String, String, String),
+    #[error("layout of `{}` is not known for this target", .0)]
+    UnknownLayout(String),
+    #[error("program too deeply nested")]
+    TooDeeplyNested,
+    #[error("unknown derive `{}` (only `debug` is supported)", .0)]
+    UnknownDerive(String),
+    #[error("`derive({})` can only be used on enums", .0)]
+    DeriveOnNonEnum(String),
+    #[error("{} expression in `{}` has no span", .0, .1)]
+    ExpressionWithoutSpan(String, String),
 
     // Warnings
     #[error("defer inside a loop: this defered statement will only be executed once")]
@@ -257,6 +312,18 @@ pub enum CodeErrorKind {
     UninitializedField(String),
     #[error("This is `std::typing::Self`, did you mean the enclosing type?")]
     SelfConfusion,
+    #[error("unreachable code")]
+    UnreachableCode,
+    #[error("{} `{}` is never used", .0, .1)]
+    DeadCode(String, String),
+
+    // Notes
+    #[error("the other branch has type `{}`", .0)]
+    OtherBranchType(String),
+    #[error("generic {} `{}` is never used, so it was not checked", .0, .1)]
+    UnusedGeneric(String, String),
+    #[error("`{}` is first defined here", .0)]
+    PreviousDefinition(String),
 }
 
 #[derive(Debug, Clone)]
@@ -270,6 +337,9 @@ pub enum Marker {
 pub struct CodeError {
     pub kind: CodeErrorKind,
     pub backtrace: Vec<Marker>,
+    /// Secondary messages that point at other relevant locations, such as a previous
+    /// definition of a name
+    pub notes: Vec<CodeError>,
     //pub code_backtrace: Backtrace,
 }
 
@@ -278,6 +348,7 @@ impl CodeError {
         Self {
             kind,
             backtrace: span.into_iter().map(Marker::Span).collect(),
+            notes: vec![],
         }
     }
 
@@ -285,13 +356,19 @@ impl CodeError {
         Self {
             kind: CodeErrorKind::UserDefined(s.to_string()),
             backtrace: vec![],
+            notes: vec![],
         }
     }
+
+    pub fn with_note(mut self, kind: CodeErrorKind, span: Option<Span>) -> Self {
+        self.notes.push(CodeError::from_kind(kind, span));
+        self
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub struct FileId {
-    pub id: usize,
+    pub id: u32,
 }
 
 pub trait WithSpanDuringParsing<T> {
@@ -300,6 +377,12 @@ pub trait WithSpanDuringParsing<T> {
         scope: &Scope<'ast, 'src>,
         node: Node<'src>,
     ) -> Result<T, AluminaError>;
+
+    fn with_span_from_code<'src>(
+        self,
+        code: &'src ParseCtx<'src>,
+        node: Node<'src>,
+    ) -> Result<T, AluminaError>;
 }
 
 impl<T, E> WithSpanDuringParsing<T> for Result<T, E>
@@ -311,18 +394,21 @@ where
         scope: &Scope<'ast, 'src>,
         node: Node<'src>,
     ) -> Result<T, AluminaError> {
-        let span = Span {
-            start: node.start_byte(),
-            end: node.end_byte(),
-            line: node.start_position().row,
-            column: node.start_position().column,
-            file: scope.code().unwrap().file_id(),
-        };
+        self.with_span_from_code(scope.code().unwrap(), node)
+    }
+
+    fn with_span_from_code<'src>(
+        self,
+        code: &'src ParseCtx<'src>,
+        node: Node<'src>,
+    ) -> Result<T, AluminaError> {
+        let span = code.span(node);
 
         self.map_err(|e| {
             AluminaError::CodeErrors(vec![CodeError {
                 kind: e.into(),
                 backtrace: vec![Marker::Span(span)],
+                notes: vec![],
             }])
         })
     }
@@ -375,6 +461,7 @@ where
             AluminaError::CodeErrors(vec![CodeError {
                 kind: e.into(),
                 backtrace: vec![],
+                notes: vec![],
             }])
         })
     }
@@ -384,6 +471,7 @@ where
             AluminaError::CodeErrors(vec![CodeError {
                 kind: e.into(),
                 backtrace: span.iter().map(|s| Marker::Span(*s)).collect(),
+                notes: vec![],
             }])
         })
     }
@@ -447,6 +535,7 @@ use crate::ast::lang::LangItemKind;
 use crate::ast::Span;
 use crate::ir::const_eval::ConstEvalError;
 use crate::name_resolution::scope::Scope;
+use crate::parser::ParseCtx;
 
 pub trait Incrementable<T> {
     fn increment(&self) -> T;
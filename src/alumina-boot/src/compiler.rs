@@ -1,27 +1,39 @@
 use std::collections::HashSet;
 use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
 
 use crate::codegen;
-use crate::common::AluminaError;
+pub use crate::codegen::CodegenDialect;
+use crate::common::{AluminaError, CodeError};
 
 use crate::common::ArenaAllocatable;
 use crate::common::CodeErrorKind;
-use crate::global_ctx::GlobalCtx;
+use crate::common::FileId;
+use crate::common::DEFAULT_STACK_SIZE;
+use crate::diagnostics::Diagnostic;
+use crate::global_ctx::{CfgSet, GlobalCtx, OutputType};
+use crate::incremental::IncrementalCache;
 use crate::ir::dce::DeadCodeEliminator;
 use crate::ir::mono::MonoCtx;
 use crate::ir::mono::Monomorphizer;
+use crate::ir::verify_spans::SpanVerifier;
 use crate::ir::IrCtx;
 
+use crate::ast::lang::LangItemKind;
 use crate::ast::maker::AstItemMaker;
-use crate::ast::AstCtx;
+use crate::ast::{AstCtx, Attribute, Item, ItemP};
 
 use crate::common::CodeErrorBuilder;
 
-use crate::name_resolution::pass1::FirstPassVisitor;
+use crate::name_resolution::pass1::{Declaration, DeclarationCollector, FirstPassVisitor};
+use crate::name_resolution::path::Path;
 use crate::name_resolution::scope::Scope;
-use crate::parser::{AluminaVisitor, ParseCtx};
+use crate::parser::ParseCtx;
 
 use std::time::{Duration, Instant};
+use walkdir::WalkDir;
 
 #[derive(Debug, Clone)]
 pub enum Stage {
@@ -34,17 +46,262 @@ pub enum Stage {
     Codegen,
 }
 
+/// Warnings that are only reported when enabled with `--warn`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OptionalWarning {
+    /// Functions, statics and consts that are never used (`dead-code`)
+    DeadCode,
+}
+
+impl FromStr for OptionalWarning {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "dead-code" => Ok(OptionalWarning::DeadCode),
+            _ => Err(format!("unknown warning `{}` (expected `dead-code`)", s)),
+        }
+    }
+}
+
+/// Options for a compilation, corresponding to the command line arguments of `alumina-boot`.
+#[derive(Debug, Clone)]
+pub struct CompilerOptions {
+    pub output_type: OutputType,
+    /// Conditional compilation options (`--cfg key` or `--cfg key=value`)
+    pub cfg: Vec<(String, Option<String>)>,
+    /// Compile in debug mode (sets the `debug` cfg flag)
+    pub debug: bool,
+    /// Unstable compiler options (`-Z`)
+    pub options: Vec<String>,
+    /// Number of threads to use for parsing (defaults to the number of CPUs)
+    pub jobs: Option<usize>,
+    /// Directory for caching the declarations collected from source files between runs. Files
+    /// are still parsed on every run, the cache only skips collecting their declarations.
+    pub incremental: Option<PathBuf>,
+    /// Report the time taken by each stage of the compilation as notes
+    pub timings: bool,
+    /// Warnings that are off by default (`--warn`)
+    pub warnings: Vec<OptionalWarning>,
+    /// Fold constant expressions and remove branches with constant conditions (`--opt`)
+    pub optimize: bool,
+    /// Flavor of C to emit (`--codegen-dialect`)
+    pub dialect: CodegenDialect,
+    /// Stack size of the thread that runs the compilation in MiB (`--stack-size`)
+    pub stack_size: usize,
+}
+
+impl Default for CompilerOptions {
+    fn default() -> Self {
+        Self {
+            output_type: OutputType::Executable,
+            cfg: Vec::new(),
+            debug: false,
+            options: Vec::new(),
+            jobs: None,
+            incremental: None,
+            timings: false,
+            warnings: Vec::new(),
+            optimize: false,
+            dialect: CodegenDialect::C11,
+            stack_size: DEFAULT_STACK_SIZE,
+        }
+    }
+}
+
+/// Result of a successful compilation.
+#[derive(Debug)]
+pub struct CompilationOutput {
+    /// The generated C code
+    pub program: String,
+    /// Warnings and notes reported during the compilation
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+/// The Alumina compiler.
+///
+/// Sources are added with [`Compiler::add_file`], [`Compiler::add_source`] (for sources
+/// that do not come from disk) and [`Compiler::add_sysroot`], and then compiled into a
+/// single C file with [`Compiler::compile`].
+///
+/// ```no_run
+/// use alumina_boot::compiler::{Compiler, CompilerOptions};
+///
+/// let mut compiler = Compiler::new(CompilerOptions::default());
+/// compiler.add_sysroot("./sysroot".as_ref()).unwrap();
+/// compiler.add_source("main", "fn main() { println!(\"Hello, world!\"); }");
+///
+/// match compiler.compile() {
+///     Ok(output) => print!("{}", output.program),
+///     Err(_) => compiler.print_diagnostics(),
+/// }
+/// ```
 pub struct Compiler {
     global_ctx: GlobalCtx,
+    sources: Vec<Source>,
+    created: Instant,
+    collect_timings: bool,
     timings: Vec<(Stage, Duration)>,
+    jobs: usize,
+    incremental_dir: Option<PathBuf>,
+    incremental_stats: Option<(usize, usize)>,
+    warnings: Vec<OptionalWarning>,
+    optimize: bool,
+    dialect: CodegenDialect,
+    stack_size: usize,
 }
 
+/// A source file on disk, together with its path in the module tree.
 #[derive(Debug)]
 pub struct SourceFile {
     pub filename: PathBuf,
     pub path: String,
 }
 
+struct Source {
+    filename: PathBuf,
+    path: String,
+    /// Contents of the source if it was given directly, otherwise it is read from `filename`.
+    contents: Option<String>,
+    /// Whether the source is a part of the standard library
+    sysroot: bool,
+}
+
+/// Collects the source files of the standard library. Module paths follow the directory
+/// structure, with `__root__.alu` files mapping to the module of the enclosing directory.
+pub fn sysroot_files(sysroot: &std::path::Path) -> Result<Vec<SourceFile>, AluminaError> {
+    let mut result = Vec::new();
+
+    for maybe_entry in WalkDir::new(sysroot)
+        .follow_links(true)
+        .sort_by_file_name()
+        .into_iter()
+    {
+        use std::fmt::Write;
+        let entry = maybe_entry?;
+        if entry.file_type().is_dir() {
+            continue;
+        }
+
+        let filename = entry.file_name().to_string_lossy();
+        if !filename.ends_with(".alu") {
+            continue;
+        }
+
+        let path_segments: Vec<_> = entry
+            .path()
+            .strip_prefix(sysroot)
+            .unwrap()
+            .iter()
+            .map(|s| s.to_string_lossy())
+            .collect();
+
+        let mut module_path = String::new();
+        for (index, segment) in path_segments.iter().enumerate() {
+            if index < path_segments.len() - 1 {
+                write!(module_path, "::{}", segment).unwrap();
+            } else {
+                let module_name = segment.strip_suffix(".alu").unwrap();
+                if module_name != "__root__" {
+                    write!(module_path, "::{}", module_name).unwrap();
+                }
+            }
+        }
+        if module_path.is_empty() {
+            module_path.push_str("::");
+        }
+        result.push(SourceFile {
+            filename: entry.into_path(),
+            path: module_path,
+        });
+    }
+
+    Ok(result)
+}
+
+/// A parsed file, the items declared in it and its syntax errors. The items that contain
+/// syntax errors are skipped, but the rest of the file is still processed so that other
+/// errors can be reported along with the syntax errors.
+type ParsedFile<'src> = (ParseCtx<'src>, Vec<Declaration>, Vec<CodeError>);
+
+/// The contents of the file (if it could be read), so that the location of the error can be
+/// resolved, and the error.
+type ParseFailure = (Option<String>, AluminaError);
+
+/// Hands the compiler (and the result) over to the compilation thread and back. The contexts
+/// use `Rc`s, so the compiler cannot be sent to another thread in general.
+struct OnCompilerThread<T>(T);
+
+// SAFETY: The thread that calls `Compiler::compile` is blocked until the compilation thread
+// finishes, so the contexts are never used by two threads at the same time, and joining the
+// thread orders all the accesses. Nothing that is created on the compilation thread outlives it,
+// other than what is stored in the compiler itself and the result.
+unsafe impl<T> Send for OnCompilerThread<T> {}
+
+impl<T> OnCompilerThread<T> {
+    fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+/// Moves the code errors of a result into `errors`, so that they can be reported together
+/// with the errors of the other files. Other kinds of errors are returned as they are.
+fn collect_errors(
+    errors: &mut Vec<CodeError>,
+    result: Result<(), AluminaError>,
+) -> Result<(), AluminaError> {
+    match result {
+        Err(AluminaError::CodeErrors(e)) => errors.extend(e),
+        result => result?,
+    }
+
+    Ok(())
+}
+
+/// Reads and parses a single source file and collects the items declared in it. This runs on
+/// the worker threads, so it must not touch any of the shared (single-threaded) contexts.
+fn parse_source_file<'src>(
+    cfg: &CfgSet,
+    cache: Option<&IncrementalCache>,
+    file_id: FileId,
+    source: &Source,
+) -> Result<ParsedFile<'src>, ParseFailure> {
+    let source = match &source.contents {
+        Some(contents) => contents.clone(),
+        None => std::fs::read_to_string(&source.filename).map_err(|e| (None, e.into()))?,
+    };
+    let cached = cache.and_then(|cache| cache.load(&source, file_id));
+    let parse_tree = ParseCtx::from_source(file_id, source);
+
+    let (declarations, syntax_errors) = {
+        let code = &parse_tree;
+        match cached {
+            Some(declarations) => {
+                // Only files without syntax errors are ever cached, so a hit skips both the
+                // syntax error check and declaration collection. The tree itself is still
+                // needed by the later passes, so it is built here while we are running in
+                // parallel rather than lazily on the main thread.
+                code.root_node();
+                (declarations, Vec::new())
+            }
+            None => {
+                let syntax_errors = code.check_syntax_errors(code.root_node());
+                let declarations = DeclarationCollector::new(cfg, code)
+                    .collect(code.root_node())
+                    .map_err(|e| (Some(code.source().to_string()), e))?;
+
+                if let (Some(cache), true) = (cache, syntax_errors.is_empty()) {
+                    cache.store(code.source(), &declarations);
+                }
+
+                (declarations, syntax_errors)
+            }
+        }
+    };
+
+    Ok((parse_tree, declarations, syntax_errors))
+}
+
 macro_rules! timing {
     ($self:expr, $cur_time:expr, $stage:expr) => {
         let new_time = Instant::now();
@@ -59,80 +316,448 @@ macro_rules! timing {
 }
 
 impl Compiler {
-    pub fn new(global_ctx: GlobalCtx) -> Self {
-        Self {
+    pub fn new(options: CompilerOptions) -> Self {
+        let mut global_ctx = GlobalCtx::new(options.output_type, options.options);
+        for (key, value) in options.cfg {
+            match value {
+                Some(value) => global_ctx.add_cfg(key, value),
+                None => global_ctx.add_flag(key),
+            }
+        }
+
+        if options.debug {
+            global_ctx.add_flag("debug");
+        }
+
+        global_ctx.set_stack_size(options.stack_size * 1024 * 1024);
+
+        let mut result = Self {
             global_ctx,
+            sources: Vec::new(),
+            created: Instant::now(),
+            collect_timings: options.timings,
             timings: Vec::new(),
+            jobs: std::thread::available_parallelism()
+                .map(|n| n.get())
+                .unwrap_or(1),
+            incremental_dir: None,
+            incremental_stats: None,
+            warnings: options.warnings,
+            optimize: options.optimize,
+            dialect: options.dialect,
+            stack_size: options.stack_size,
+        };
+
+        if let Some(jobs) = options.jobs {
+            result.set_jobs(jobs);
+        }
+        if let Some(dir) = options.incremental {
+            result.set_incremental(dir);
+        }
+
+        result
+    }
+
+    /// Adds a source file that will be read from disk when compiling.
+    pub fn add_file(&mut self, path: impl Into<String>, filename: impl Into<PathBuf>) {
+        self.sources.push(Source {
+            filename: filename.into(),
+            path: path.into(),
+            contents: None,
+            sysroot: false,
+        });
+    }
+
+    /// Adds a source from memory. The path in the module tree is also used as the file name
+    /// in diagnostics.
+    pub fn add_source(&mut self, path: impl Into<String>, source: impl Into<String>) {
+        let path = path.into();
+        self.sources.push(Source {
+            filename: PathBuf::from(&path),
+            path,
+            contents: Some(source.into()),
+            sysroot: false,
+        });
+    }
+
+    /// Adds all the source files of the standard library.
+    pub fn add_sysroot(&mut self, sysroot: &std::path::Path) -> Result<(), AluminaError> {
+        for file in sysroot_files(sysroot)? {
+            self.sources.push(Source {
+                filename: file.filename,
+                path: file.path,
+                contents: None,
+                sysroot: true,
+            });
         }
+
+        Ok(())
+    }
+
+    /// Enables caching of the per-file declarations in the given directory.
+    pub fn set_incremental(&mut self, dir: PathBuf) {
+        self.incremental_dir = Some(dir);
+    }
+
+    /// Number of cache hits and misses when compiling with an incremental cache.
+    pub fn incremental_stats(&self) -> Option<(usize, usize)> {
+        self.incremental_stats
+    }
+
+    /// Sets the number of threads used for parsing the source files.
+    pub fn set_jobs(&mut self, jobs: usize) {
+        self.jobs = jobs.max(1);
     }
 
     pub fn timings(&self) -> impl Iterator<Item = (Stage, Duration)> + '_ {
         self.timings.iter().cloned()
     }
 
-    pub fn compile(
+    /// Prints all the errors, warnings and notes of the compilation to stderr.
+    pub fn print_diagnostics(&self) {
+        // Only fails for errors that are not code errors, and these are never added.
+        self.global_ctx.diag().print_error_report().unwrap();
+    }
+
+    fn parse_files<'src>(
         &mut self,
-        source_files: Vec<SourceFile>,
-        start_time: Instant,
-    ) -> Result<String, AluminaError> {
-        let mut cur_time = start_time;
-        timing!(self, cur_time, Stage::Init);
+        files: &[(FileId, &Source)],
+    ) -> Vec<Result<ParsedFile<'src>, ParseFailure>> {
+        let cfg = self.global_ctx.cfg_set();
+        let cache = self
+            .incremental_dir
+            .clone()
+            .map(|dir| IncrementalCache::new(dir, &cfg));
+
+        let results = Self::parse_files_with(self.jobs, &cfg, cache.as_ref(), files);
+        self.incremental_stats = cache.map(|cache| (cache.hits(), cache.misses()));
+
+        results
+    }
 
-        let ast = AstCtx::new();
-        let root_scope = Scope::new_root();
+    fn parse_files_with<'src>(
+        jobs: usize,
+        cfg: &CfgSet,
+        cache: Option<&IncrementalCache>,
+        files: &[(FileId, &Source)],
+    ) -> Vec<Result<ParsedFile<'src>, ParseFailure>> {
+        let workers = jobs.min(files.len());
+
+        if workers <= 1 {
+            return files
+                .iter()
+                .map(|(file_id, source)| parse_source_file(cfg, cache, *file_id, source))
+                .collect();
+        }
 
-        let mut main_candidate = None;
-        let source_files: Vec<_> = source_files
-            .iter()
-            .map(|source_file| {
-                let file_id = self
-                    .global_ctx
-                    .diag()
-                    .add_file(source_file.filename.clone());
-                let source = std::fs::read_to_string(&source_file.filename)?;
+        let next = AtomicUsize::new(0);
+        let results: Vec<_> = files.iter().map(|_| Mutex::new(None)).collect();
+
+        std::thread::scope(|s| {
+            for _ in 0..workers {
+                s.spawn(|| loop {
+                    let index = next.fetch_add(1, Ordering::Relaxed);
+                    let (file_id, source) = match files.get(index) {
+                        Some(file) => file,
+                        None => break,
+                    };
+
+                    let result = parse_source_file(cfg, cache, *file_id, source);
+                    *results[index].lock().unwrap() = Some(result);
+                });
+            }
+        });
 
-                let parse_tree = ParseCtx::from_source(file_id, source);
-                parse_tree.check_syntax_errors(parse_tree.root_node())?;
+        results
+            .into_iter()
+            .map(|result| result.into_inner().unwrap().unwrap())
+            .collect()
+    }
 
-                Ok((parse_tree, ast.parse_path(&source_file.path)))
-            })
-            .collect::<Result<_, AluminaError>>()?;
+    /// Compiles all the added sources into a C program. On failure, all the reported
+    /// diagnostics are returned, errors as well as warnings.
+    ///
+    /// The compiler recurses proportionally to how deeply the program is nested, so the
+    /// compilation runs on a dedicated thread with a larger stack than threads get by default.
+    pub fn compile(&mut self) -> Result<CompilationOutput, Vec<Diagnostic>> {
+        let stack_size = self.stack_size * 1024 * 1024;
+        let compiler = OnCompilerThread(self);
+
+        std::thread::scope(|s| {
+            std::thread::Builder::new()
+                .name("compiler".to_string())
+                .stack_size(stack_size)
+                .spawn_scoped(s, move || {
+                    OnCompilerThread(compiler.into_inner().compile_on_this_thread())
+                })
+                .unwrap()
+                .join()
+        })
+        .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+        .into_inner()
+    }
 
-        timing!(self, cur_time, Stage::Parse);
+    fn compile_on_this_thread(&mut self) -> Result<CompilationOutput, Vec<Diagnostic>> {
+        let sources = std::mem::take(&mut self.sources);
+        let result = self.compile_sources(&sources);
+
+        let diag = self.global_ctx.diag();
+        match result {
+            Ok(program) => {
+                if self.collect_timings {
+                    for (stage, duration) in &self.timings {
+                        diag.add_note(CodeError::freeform(format!(
+                            "compiler timings: stage {:?} took {}ms",
+                            stage,
+                            duration.as_millis()
+                        )));
+                    }
+                    if let Some((hits, misses)) = self.incremental_stats {
+                        diag.add_note(CodeError::freeform(format!(
+                            "compiler timings: incremental cache hit for {} of {} files",
+                            hits,
+                            hits + misses
+                        )));
+                    }
+                }
 
-        for (ctx, path) in source_files.iter() {
-            let scope = root_scope.ensure_module(path.clone()).with_no_span()?;
-            scope.set_code(ctx);
+                Ok(CompilationOutput {
+                    program,
+                    diagnostics: diag.diagnostics(),
+                })
+            }
+            Err(e) => {
+                if let Err(e) = diag.add_from_error(e) {
+                    diag.add_error(CodeError::freeform(e));
+                }
+
+                Err(diag.diagnostics())
+            }
+        }
+    }
+
+    /// Warns about the functions, statics and consts in the program (but not in the standard
+    /// library) that were never monomorphized. Generic items only get a note, as the ones that
+    /// are never used are not type checked at all.
+    fn report_dead_code<'ast>(
+        &self,
+        module_items: &[(ItemP<'ast>, bool)],
+        used: &HashSet<ItemP<'ast>>,
+        sysroot_file_ids: &HashSet<FileId>,
+    ) {
+        // Alumina has no visibility modifiers, so everything at the top level of a library is
+        // considered to be a part of its interface.
+        let is_library = !self.global_ctx.should_generate_main_glue();
+
+        for (item, top_level) in module_items {
+            if used.contains(item) || (is_library && *top_level) {
+                continue;
+            }
+
+            let (kind, name, attributes, span, is_generic, is_extern) = match item.get() {
+                Item::Function(f) => (
+                    "function",
+                    f.name,
+                    f.attributes,
+                    f.span,
+                    !f.placeholders.is_empty(),
+                    f.body.is_none(),
+                ),
+                Item::StaticOrConst(s) => (
+                    if s.is_const { "constant" } else { "static" },
+                    s.name,
+                    s.attributes,
+                    s.span,
+                    !s.placeholders.is_empty(),
+                    s.r#extern,
+                ),
+                _ => continue,
+            };
+
+            let (name, span) = match (name, span) {
+                (Some(name), Some(span)) => (name, span),
+                _ => continue,
+            };
+
+            if is_extern
+                || sysroot_file_ids.contains(&span.file)
+                || attributes
+                    .iter()
+                    .any(|a| matches!(a, Attribute::Export | Attribute::Test))
+            {
+                continue;
+            }
+
+            let diag = self.global_ctx.diag();
+            if is_generic {
+                diag.add_note(CodeError::from_kind(
+                    CodeErrorKind::UnusedGeneric(kind.to_string(), name.to_string()),
+                    Some(span),
+                ));
+            } else {
+                diag.add_warning(CodeError::from_kind(
+                    CodeErrorKind::DeadCode(kind.to_string(), name.to_string()),
+                    Some(span),
+                ));
+            }
+        }
+    }
+
+    /// Registers the declarations of all the files in the scope tree and makes the AST items.
+    /// Returns the item maker along with the entrypoint of the program, if there is one, and
+    /// the `main` function of the program if the entrypoint is the test runner instead.
+    fn make_ast<'ast, 'src>(
+        &mut self,
+        cur_time: &mut Instant,
+        ast: &'ast AstCtx<'ast>,
+        root_scope: Scope<'ast, 'src>,
+        parsed: &'src [(ParseCtx<'src>, Path<'ast>)],
+        declarations: Vec<Vec<Declaration>>,
+    ) -> Result<(AstItemMaker<'ast>, Option<ItemP<'ast>>, Option<ItemP<'ast>>), AluminaError> {
+        let mut main_candidate = None;
+        let mut program_main = None;
+        let mut errors = Vec::new();
+
+        for ((ctx, path), declarations) in parsed.iter().zip(declarations) {
+            // Several files can contribute to the same module, so the scope is entered through a
+            // handle that carries the parse context of this file.
+            let scope = root_scope
+                .ensure_module(path.clone())
+                .with_no_span()?
+                .with_code(ctx);
 
             if self.global_ctx.should_generate_main_glue() {
                 let mut visitor =
-                    FirstPassVisitor::with_main(self.global_ctx.clone(), &ast, scope.clone());
-                visitor.visit(ctx.root_node())?;
+                    FirstPassVisitor::with_main(self.global_ctx.clone(), ast, scope.clone());
+                collect_errors(&mut errors, visitor.register(declarations))?;
 
                 if let Some(candidate) = visitor.main_candidate() {
                     if main_candidate.replace(candidate).is_some() {
                         return Err(CodeErrorKind::MultipleMainFunctions).with_no_span();
                     }
                 }
+
+                program_main = program_main.or(visitor.program_main());
             } else {
                 let mut visitor =
-                    FirstPassVisitor::new(self.global_ctx.clone(), &ast, scope.clone());
-                visitor.visit(ctx.root_node())?;
+                    FirstPassVisitor::new(self.global_ctx.clone(), ast, scope.clone());
+                collect_errors(&mut errors, visitor.register(declarations))?;
             }
         }
 
-        timing!(self, cur_time, Stage::Pass1);
+        // The conflicts between the files contributing to the same module are only reported
+        // once all of them are registered.
+        if !errors.is_empty() {
+            return Err(AluminaError::CodeErrors(errors));
+        }
+
+        timing!(self, *cur_time, Stage::Pass1);
 
-        let mut item_maker = AstItemMaker::new(&ast, self.global_ctx.clone(), false);
+        if self.global_ctx.should_generate_main_glue() {
+            ast.require_lang_items(&[LangItemKind::EntrypointGlue], None);
+        }
+
+        let mut item_maker = AstItemMaker::new(ast, self.global_ctx.clone(), false);
         item_maker.make(root_scope)?;
 
-        timing!(self, cur_time, Stage::Ast);
+        // Report all the lang items the program needs but the standard library does not
+        // provide at once, rather than one by one during monomorphization.
+        ast.check_lang_items()?;
+
+        timing!(self, *cur_time, Stage::Ast);
 
-        drop(source_files);
+        Ok((item_maker, main_candidate, program_main))
+    }
+
+    fn compile_sources(&mut self, source_files: &[Source]) -> Result<String, AluminaError> {
+        let mut cur_time = self.created;
+        timing!(self, cur_time, Stage::Init);
+
+        let ast = AstCtx::new();
+        let root_scope = Scope::new_root();
+
+        let mut sysroot_file_ids = HashSet::new();
+        let files: Vec<_> = source_files
+            .iter()
+            .map(|source_file| {
+                let file_id = self
+                    .global_ctx
+                    .diag()
+                    .add_file(source_file.filename.clone());
+
+                if source_file.sysroot {
+                    sysroot_file_ids.insert(file_id);
+                }
+
+                (file_id, source_file)
+            })
+            .collect();
+
+        let mut parsed = Vec::new();
+        let mut declarations = Vec::new();
+        let mut syntax_errors = Vec::new();
+        let mut failed = Vec::new();
+        for (result, (file_id, source_file)) in self.parse_files(&files).into_iter().zip(&files) {
+            match result {
+                Ok((ctx, file_declarations, file_syntax_errors)) => {
+                    parsed.push((ctx, ast.parse_path(&source_file.path)));
+                    declarations.push(file_declarations);
+                    syntax_errors.extend(file_syntax_errors);
+                }
+                Err((source, e)) => {
+                    if let Some(source) = source {
+                        self.global_ctx.diag().add_source(*file_id, &source);
+                    }
+                    failed.push((&source_file.filename, e));
+                }
+            }
+        }
+
+        for (ctx, _) in &parsed {
+            self.global_ctx
+                .diag()
+                .add_source(ctx.file_id(), ctx.source());
+        }
+
+        // Report errors from all the files that failed to parse at once, ordered by file name
+        // so that the output does not depend on the order in which the workers finished.
+        if !failed.is_empty() {
+            failed.sort_by_key(|(name, _)| *name);
+
+            let mut errors = Vec::new();
+            for (_, e) in failed {
+                match e {
+                    AluminaError::CodeErrors(e) => errors.extend(e),
+                    e => return Err(e),
+                }
+            }
+
+            return Err(AluminaError::CodeErrors(errors));
+        }
+
+        timing!(self, cur_time, Stage::Parse);
+
+        let result = self.make_ast(&mut cur_time, &ast, root_scope, &parsed, declarations);
+
+        // The errors from the later passes are reported after the syntax errors, as they
+        // may well be caused by the items that were skipped.
+        if !syntax_errors.is_empty() {
+            if let Err(AluminaError::CodeErrors(errors)) = result {
+                syntax_errors.extend(errors);
+            }
+
+            return Err(AluminaError::CodeErrors(syntax_errors));
+        }
+
+        let (item_maker, main_candidate, program_main) = result?;
+
+        drop(parsed);
 
         let ir_ctx = IrCtx::new();
+        let module_items = item_maker.module_items().to_vec();
         let items = item_maker.into_inner();
         let mut mono_ctx = MonoCtx::new(&ast, &ir_ctx, self.global_ctx.clone());
+        mono_ctx.set_optimize(self.optimize);
 
         let mut roots = HashSet::new();
 
@@ -161,9 +786,7 @@ impl Compiler {
                 let mut monomorphizer = Monomorphizer::new(&mut mono_ctx, false, None);
                 let user_main = monomorphizer.monomorphize_item(main_candidate, &[])?;
 
-                let glue = ast
-                    .lang_item(crate::ast::lang::LangItemKind::EntrypointGlue)
-                    .with_no_span()?;
+                let glue = ast.lang_item(LangItemKind::EntrypointGlue).with_no_span()?;
                 let mut monomorphizer = Monomorphizer::new(&mut mono_ctx, false, None);
 
                 let main_ty = ir_ctx.intern_type(crate::ir::Ty::NamedFunction(user_main));
@@ -172,8 +795,25 @@ impl Compiler {
             }
         }
 
+        mono_ctx.report_unreachable_code();
         timing!(self, cur_time, Stage::Mono);
 
+        if self.warnings.contains(&OptionalWarning::DeadCode) {
+            // The test runner replaces `main`, but the code that the program uses is not dead
+            // just because the tests do not use it. It is only monomorphized to find out what it
+            // uses, it is not emitted.
+            if let Some(program_main) = program_main {
+                let mut monomorphizer = Monomorphizer::new(&mut mono_ctx, false, None);
+                monomorphizer.monomorphize_item(program_main, &[])?;
+            }
+
+            self.report_dead_code(
+                &module_items,
+                &mono_ctx.monomorphized_items(),
+                &sysroot_file_ids,
+            );
+        }
+
         let mut dce = DeadCodeEliminator::new();
         for item in roots {
             dce.visit_item(item)?;
@@ -186,9 +826,18 @@ impl Compiler {
         let items: Vec<_> = dce.alive_items().iter().copied().collect();
         timing!(self, cur_time, Stage::Optimizations);
 
+        // Check that the spans survived all the lowering, for when they are needed in codegen.
+        if self.global_ctx.has_option("verify-spans") {
+            let mut verifier = SpanVerifier::new();
+            for item in &items {
+                verifier.verify_item(item)?;
+            }
+            verifier.finish()?;
+        }
+
         // Dunno why the borrow checker is not letting me do that, it should be possible.
         // drop(ast);
-        let res = codegen::codegen(self.global_ctx.clone(), &items[..]);
+        let res = codegen::codegen(self.global_ctx.clone(), self.dialect, &items[..]);
         timing!(self, cur_time, Stage::Codegen);
 
         res
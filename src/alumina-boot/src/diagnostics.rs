@@ -1,21 +1,87 @@
-use std::{cell::RefCell, collections::HashMap, path::PathBuf, rc::Rc};
+use std::{cell::RefCell, collections::HashMap, ops::Range, path::PathBuf, rc::Rc};
 
 use colored::Colorize;
 
+use crate::ast::Span;
 use crate::common::{AluminaError, CodeError, CodeErrorKind, FileId, Marker};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
-enum Level {
+pub enum Level {
     Error = 2,
     Warning = 1,
-    #[allow(dead_code)]
     Note = 0,
 }
 
+/// A source location with 1-based line and column numbers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Location {
+    pub file: PathBuf,
+    pub line: usize,
+    pub column: usize,
+    /// Byte offsets of the start and the end of the span in the file
+    pub bytes: Range<usize>,
+}
+
+/// A reported message in a structured form, for consumers other than the terminal.
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub level: Level,
+    pub kind: CodeErrorKind,
+    /// All resolved locations in the backtrace, innermost first.
+    pub locations: Vec<Location>,
+    /// Notes attached to the message, such as the location of a previous definition.
+    pub notes: Vec<Diagnostic>,
+}
+
 struct DiagnosticContextInner {
     file_map: HashMap<FileId, PathBuf>,
+    /// Byte offsets of the start of each line in the source files
+    line_starts: HashMap<FileId, Vec<u32>>,
+    sources: HashMap<FileId, String>,
     messages: Vec<(Level, CodeError)>,
-    counter: usize,
+    counter: u32,
+}
+
+impl DiagnosticContextInner {
+    fn location(&self, span: Span) -> Option<Location> {
+        let file = self.file_map.get(&span.file)?;
+        let (line, column) = self.line_column(span)?;
+
+        Some(Location {
+            file: file.clone(),
+            line: line + 1,
+            column: column + 1,
+            bytes: span.start as usize..span.end as usize,
+        })
+    }
+
+    fn diagnostic(&self, level: Level, error: CodeError) -> Diagnostic {
+        Diagnostic {
+            level,
+            locations: error
+                .backtrace
+                .iter()
+                .filter_map(|m| match m {
+                    Marker::Span(span) => self.location(*span),
+                    _ => None,
+                })
+                .collect(),
+            notes: error
+                .notes
+                .into_iter()
+                .map(|note| self.diagnostic(Level::Note, note))
+                .collect(),
+            kind: error.kind,
+        }
+    }
+
+    fn line_column(&self, span: Span) -> Option<(usize, usize)> {
+        let line_starts = self.line_starts.get(&span.file)?;
+
+        // The first line always starts at 0, so there is at least one line before the offset.
+        let line = line_starts.partition_point(|&start| start <= span.start) - 1;
+        Some((line, (span.start - line_starts[line]) as usize))
+    }
 }
 
 #[derive(Clone)]
@@ -28,6 +94,8 @@ impl DiagnosticContext {
         Self {
             inner: Rc::new(RefCell::new(DiagnosticContextInner {
                 file_map: HashMap::new(),
+                line_starts: HashMap::new(),
+                sources: HashMap::new(),
                 messages: Vec::new(),
                 counter: 0,
             })),
@@ -46,6 +114,34 @@ impl DiagnosticContext {
         file_id
     }
 
+    /// Registers the contents of a file, so that the line and column of the spans in it can
+    /// be resolved.
+    pub fn add_source(&self, file_id: FileId, source: &str) {
+        let line_starts = std::iter::once(0)
+            .chain(source.match_indices('\n').map(|(i, _)| i as u32 + 1))
+            .collect();
+
+        let mut inner = self.inner.borrow_mut();
+        inner.line_starts.insert(file_id, line_starts);
+        inner.sources.insert(file_id, source.to_string());
+    }
+
+    /// The text of the span in the source, if the file was registered with
+    /// [`add_source`](Self::add_source).
+    pub fn source_text(&self, span: Span) -> Option<String> {
+        let inner = self.inner.borrow();
+        let source = inner.sources.get(&span.file)?;
+
+        source
+            .get(span.start as usize..span.end as usize)
+            .map(|s| s.to_string())
+    }
+
+    /// Line and column (in bytes) of the start of the span, both zero-based.
+    pub fn line_column(&self, span: Span) -> Option<(usize, usize)> {
+        self.inner.borrow().line_column(span)
+    }
+
     pub fn add_from_error(&self, err: AluminaError) -> Result<(), AluminaError> {
         match err {
             AluminaError::CodeErrors(errors) => {
@@ -70,9 +166,8 @@ impl DiagnosticContext {
         self.inner.borrow_mut().messages.push((Level::Note, err));
     }
 
-    pub fn print_error_report(&self) -> Result<(), AluminaError> {
-        let inner = self.inner.borrow();
-        let mut all_errors: Vec<_> = inner.messages.clone();
+    fn sorted_messages(&self) -> Vec<(Level, CodeError)> {
+        let mut all_errors: Vec<_> = self.inner.borrow().messages.clone();
         all_errors.sort_by_key(|(level, err)| {
             err.backtrace
                 .iter()
@@ -84,13 +179,24 @@ impl DiagnosticContext {
                 .unwrap_or((*level, None))
         });
 
-        for (level, error) in all_errors {
-            let level = match level {
-                Level::Error => "error".red(),
-                Level::Warning => "warning".yellow(),
-                Level::Note => "note".green(),
-            };
+        all_errors
+    }
+
+    pub fn diagnostics(&self) -> Vec<Diagnostic> {
+        let inner = self.inner.borrow();
+
+        self.sorted_messages()
+            .into_iter()
+            .filter(|(_, error)| !matches!(error.kind, CodeErrorKind::LocalWithUnknownType))
+            .map(|(level, error)| inner.diagnostic(level, error))
+            .collect()
+    }
 
+    pub fn print_error_report(&self) -> Result<(), AluminaError> {
+        let all_errors = self.sorted_messages();
+        let inner = self.inner.borrow();
+
+        for (level, error) in all_errors {
             if let CodeError {
                 kind: CodeErrorKind::LocalWithUnknownType,
                 ..
@@ -100,46 +206,64 @@ impl DiagnosticContext {
                 continue;
             }
 
-            let tagline = format!("{}: {}", level, error.kind).bold();
-            eprintln!("{}", tagline);
-
-            // An error can happen deep inside the code that we didn't write because most of the typechecking
-            // happens during or after monomorphization.
-            let mut skip = false;
-            for frame in error.backtrace {
-                let span = match (frame, skip) {
-                    (Marker::Span(span), false) => {
-                        skip = true;
-                        span
-                    }
-                    (Marker::Span(_), true) => continue,
-                    (Marker::Monomorphization, _) => {
-                        skip = false;
-                        continue;
-                    }
-                };
-
-                if let Some(file_name) = inner.file_map.get(&span.file) {
-                    eprintln!(
-                        " --> {}:{}:{}",
-                        file_name.display(),
-                        span.line + 1,
-                        span.column + 1
-                    );
-                } else {
-                    eprintln!(" --> {{ unresolved location }}");
+            inner.print_message(level, error);
+        }
+
+        Ok(())
+    }
+}
+
+impl DiagnosticContextInner {
+    fn print_message(&self, level: Level, error: CodeError) {
+        let tagline = match level {
+            Level::Error => "error".red(),
+            Level::Warning => "warning".yellow(),
+            Level::Note => "note".green(),
+        };
+
+        let tagline = format!("{}: {}", tagline, error.kind).bold();
+        eprintln!("{}", tagline);
+
+        // An error can happen deep inside the code that we didn't write because most of the typechecking
+        // happens during or after monomorphization.
+        let mut skip = false;
+        for frame in error.backtrace {
+            let span = match (frame, skip) {
+                (Marker::Span(span), false) => {
+                    skip = true;
+                    span
                 }
-            }
+                (Marker::Span(_), true) => continue,
+                (Marker::Monomorphization, _) => {
+                    skip = false;
+                    continue;
+                }
+            };
 
-            if let CodeErrorKind::InternalError(_, backtrace) = error.kind {
-                eprintln!();
-                eprintln!("Compiler backtrace:");
-                eprintln!("{:?}", backtrace);
+            if let Some(location) = self.location(span) {
+                eprintln!(
+                    " --> {}:{}:{}",
+                    location.file.display(),
+                    location.line,
+                    location.column
+                );
+            } else {
+                eprintln!(" --> {{ unresolved location }}");
             }
+        }
+
+        for note in error.notes {
+            self.print_message(Level::Note, note);
+        }
 
+        if let CodeErrorKind::InternalError(_, backtrace) = error.kind {
             eprintln!();
+            eprintln!("Compiler backtrace:");
+            eprintln!("{:?}", backtrace);
         }
 
-        Ok(())
+        if level != Level::Note {
+            eprintln!();
+        }
     }
 }
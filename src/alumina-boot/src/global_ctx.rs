@@ -2,21 +2,49 @@ use std::{
     cell::{Ref, RefCell},
     collections::{HashMap, HashSet},
     rc::Rc,
+    sync::Arc,
 };
 
+use crate::common::{DEFAULT_STACK_SIZE, STACK_PER_NESTING_LEVEL};
 use crate::diagnostics::DiagnosticContext;
 
-#[derive(Copy, Clone)]
+#[derive(Debug, Copy, Clone)]
 pub enum OutputType {
     Library,
     Executable,
 }
 
+/// Conditional compilation options. Unlike the rest of the global context, this can be
+/// shared with the worker threads that parse the source files.
+#[derive(Debug, Clone, Default)]
+pub struct CfgSet {
+    values: Arc<HashMap<String, Option<String>>>,
+}
+
+impl CfgSet {
+    pub fn has_flag(&self, name: &str) -> bool {
+        self.values.contains_key(name)
+    }
+
+    pub fn get(&self, name: &str) -> Option<Option<&str>> {
+        self.values.get(name).map(|v| v.as_deref())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, Option<&str>)> {
+        self.values.iter().map(|(k, v)| (k.as_str(), v.as_deref()))
+    }
+
+    fn insert(&mut self, name: String, value: Option<String>) {
+        Arc::make_mut(&mut self.values).insert(name, value);
+    }
+}
+
 struct GlobalCtxInner {
     pub diag: DiagnosticContext,
-    pub cfg: HashMap<String, Option<String>>,
+    pub cfg: CfgSet,
     pub options: HashSet<String>,
     pub output_type: OutputType,
+    pub max_nesting_depth: usize,
 }
 
 #[derive(Clone)]
@@ -29,9 +57,10 @@ impl GlobalCtx {
         let mut result = Self {
             inner: Rc::new(RefCell::new(GlobalCtxInner {
                 diag: DiagnosticContext::new(),
-                cfg: HashMap::new(),
+                cfg: CfgSet::default(),
                 options: options.into_iter().collect(),
                 output_type,
+                max_nesting_depth: DEFAULT_STACK_SIZE * 1024 * 1024 / STACK_PER_NESTING_LEVEL,
             })),
         };
 
@@ -64,6 +93,16 @@ impl GlobalCtx {
         result
     }
 
+    /// Limits the nesting depth of expressions and types to what fits on a stack of the given
+    /// size (in bytes).
+    pub fn set_stack_size(&mut self, stack_size: usize) {
+        self.inner.borrow_mut().max_nesting_depth = stack_size / STACK_PER_NESTING_LEVEL;
+    }
+
+    pub fn max_nesting_depth(&self) -> usize {
+        self.inner.borrow().max_nesting_depth
+    }
+
     pub fn should_generate_main_glue(&self) -> bool {
         matches!(self.inner.borrow().output_type, OutputType::Executable)
     }
@@ -82,7 +121,7 @@ impl GlobalCtx {
     }
 
     pub fn has_flag(&self, name: &str) -> bool {
-        self.inner.borrow().cfg.contains_key(name)
+        self.inner.borrow().cfg.has_flag(name)
     }
 
     pub fn add_cfg(&mut self, value: impl ToString, value_str: impl ToString) {
@@ -94,6 +133,13 @@ impl GlobalCtx {
 
     pub fn cfg(&self, key: impl ToString) -> Option<Option<String>> {
         let borrowed = self.inner.borrow();
-        borrowed.cfg.get(&key.to_string()).cloned()
+        borrowed
+            .cfg
+            .get(&key.to_string())
+            .map(|v| v.map(|v| v.to_string()))
+    }
+
+    pub fn cfg_set(&self) -> CfgSet {
+        self.inner.borrow().cfg.clone()
     }
 }
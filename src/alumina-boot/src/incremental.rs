@@ -0,0 +1,269 @@
+use std::hash::{Hash, Hasher};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::common::FileId;
+use crate::global_ctx::CfgSet;
+use crate::name_resolution::pass1::Declaration;
+
+const MAGIC: &[u8; 8] = b"ALUDECL\x02";
+
+static TEMP_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// On-disk cache of the declarations collected from each source file, keyed on the hash of
+/// the file contents. Cache entries are only valid for the same grammar and cache format (see
+/// `build.rs`), compiler version and set of `--cfg` options, since cfg attributes are evaluated
+/// while collecting declarations.
+///
+/// The cache is purely an optimization, so any I/O error or a malformed cache file is treated
+/// as a miss (and the offending file is removed).
+///
+/// Only the declaration collection is skipped on a hit. The parse tree is still needed by the
+/// later passes, so every file is parsed regardless.
+pub struct IncrementalCache {
+    dir: PathBuf,
+    key: u64,
+    hits: AtomicUsize,
+    misses: AtomicUsize,
+}
+
+impl IncrementalCache {
+    pub fn new(dir: PathBuf, cfg: &CfgSet) -> Self {
+        let mut hasher = StableHasher::new();
+        env!("ALUMINA_CACHE_KEY").hash(&mut hasher);
+        env!("CARGO_PKG_VERSION").hash(&mut hasher);
+
+        let mut cfg: Vec<_> = cfg.iter().collect();
+        cfg.sort();
+        cfg.hash(&mut hasher);
+
+        Self {
+            dir,
+            key: hasher.finish(),
+            hits: AtomicUsize::new(0),
+            misses: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn hits(&self) -> usize {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    pub fn misses(&self) -> usize {
+        self.misses.load(Ordering::Relaxed)
+    }
+
+    fn content_hash(&self, source: &str) -> u64 {
+        let mut hasher = StableHasher::new();
+        self.key.hash(&mut hasher);
+        source.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    fn entry_path(&self, content_hash: u64) -> PathBuf {
+        self.dir.join(format!("{:016x}.decl", content_hash))
+    }
+
+    pub fn load(&self, source: &str, file_id: FileId) -> Option<Vec<Declaration>> {
+        let content_hash = self.content_hash(source);
+        let path = self.entry_path(content_hash);
+
+        let result = std::fs::read(&path).ok().and_then(|data| {
+            let declarations = decode_entry(&data, self.key, content_hash, file_id);
+            if declarations.is_none() {
+                let _ = std::fs::remove_file(&path);
+            }
+            declarations
+        });
+
+        match result {
+            Some(_) => self.hits.fetch_add(1, Ordering::Relaxed),
+            None => self.misses.fetch_add(1, Ordering::Relaxed),
+        };
+
+        result
+    }
+
+    pub fn store(&self, source: &str, declarations: &[Declaration]) {
+        let content_hash = self.content_hash(source);
+
+        let mut payload = Encoder::new();
+        payload.write_usize(declarations.len());
+        for declaration in declarations {
+            declaration.encode(&mut payload);
+        }
+
+        let mut entry = Encoder::new();
+        entry.write_bytes(MAGIC);
+        entry.write_u64(self.key);
+        entry.write_u64(content_hash);
+        entry.write_u64(checksum(&payload.data));
+        entry.write_bytes(&payload.data);
+
+        // Write to a temporary file first, so that concurrent compilations never observe a
+        // partially written entry.
+        let temp = self.dir.join(format!(
+            "{:016x}.{}.{}.tmp",
+            content_hash,
+            std::process::id(),
+            TEMP_COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+
+        let _ = std::fs::create_dir_all(&self.dir)
+            .and_then(|_| std::fs::write(&temp, &entry.data))
+            .and_then(|_| std::fs::rename(&temp, self.entry_path(content_hash)))
+            .map_err(|_| std::fs::remove_file(&temp));
+    }
+}
+
+fn checksum(data: &[u8]) -> u64 {
+    let mut hasher = StableHasher::new();
+    hasher.write(data);
+    hasher.finish()
+}
+
+/// 64-bit FNV-1a. The hashes end up on disk, so unlike with `DefaultHasher`, they must not
+/// change between builds of the compiler.
+struct StableHasher(u64);
+
+impl StableHasher {
+    fn new() -> Self {
+        Self(0xcbf29ce484222325)
+    }
+}
+
+impl Hasher for StableHasher {
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 = (self.0 ^ *byte as u64).wrapping_mul(0x100000001b3);
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+fn decode_entry(
+    data: &[u8],
+    key: u64,
+    content_hash: u64,
+    file_id: FileId,
+) -> Option<Vec<Declaration>> {
+    let mut decoder = Decoder::new(data, file_id);
+
+    if decoder.read_bytes(MAGIC.len())? != MAGIC
+        || decoder.read_u64()? != key
+        || decoder.read_u64()? != content_hash
+        || decoder.read_u64()? != checksum(decoder.remaining())
+    {
+        return None;
+    }
+
+    let len = decoder.read_len()?;
+    let mut declarations = Vec::with_capacity(len);
+    for _ in 0..len {
+        declarations.push(Declaration::decode(&mut decoder)?);
+    }
+
+    decoder.remaining().is_empty().then_some(declarations)
+}
+
+pub struct Encoder {
+    data: Vec<u8>,
+}
+
+impl Encoder {
+    fn new() -> Self {
+        Self { data: Vec::new() }
+    }
+
+    pub fn write_u8(&mut self, value: u8) {
+        self.data.push(value);
+    }
+
+    pub fn write_u32(&mut self, value: u32) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_u64(&mut self, value: u64) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_usize(&mut self, value: usize) {
+        self.write_u64(value as u64);
+    }
+
+    pub fn write_bytes(&mut self, value: &[u8]) {
+        self.data.extend_from_slice(value);
+    }
+
+    pub fn write_str(&mut self, value: &str) {
+        self.write_usize(value.len());
+        self.write_bytes(value.as_bytes());
+    }
+}
+
+/// Reads back what [`Encoder`] wrote. All methods return `None` on malformed input rather
+/// than panicking.
+pub struct Decoder<'a> {
+    data: &'a [u8],
+    file_id: FileId,
+}
+
+impl<'a> Decoder<'a> {
+    fn new(data: &'a [u8], file_id: FileId) -> Self {
+        Self { data, file_id }
+    }
+
+    /// Spans are stored without the file, since file ids are assigned anew on every run.
+    pub fn file_id(&self) -> FileId {
+        self.file_id
+    }
+
+    fn remaining(&self) -> &'a [u8] {
+        self.data
+    }
+
+    pub fn read_bytes(&mut self, len: usize) -> Option<&'a [u8]> {
+        if self.data.len() < len {
+            return None;
+        }
+
+        let (value, rest) = self.data.split_at(len);
+        self.data = rest;
+        Some(value)
+    }
+
+    fn read_array<const N: usize>(&mut self) -> Option<[u8; N]> {
+        self.read_bytes(N)?.try_into().ok()
+    }
+
+    pub fn read_u8(&mut self) -> Option<u8> {
+        self.read_array::<1>().map(|[value]| value)
+    }
+
+    pub fn read_u32(&mut self) -> Option<u32> {
+        self.read_array().map(u32::from_le_bytes)
+    }
+
+    pub fn read_u64(&mut self) -> Option<u64> {
+        self.read_array().map(u64::from_le_bytes)
+    }
+
+    pub fn read_usize(&mut self) -> Option<usize> {
+        self.read_u64()?.try_into().ok()
+    }
+
+    /// Reads a length prefix, rejecting lengths that could not possibly fit in the remaining
+    /// data (so that a corrupted length does not cause a huge allocation).
+    pub fn read_len(&mut self) -> Option<usize> {
+        let len = self.read_usize()?;
+        (len <= self.data.len()).then_some(len)
+    }
+
+    pub fn read_string(&mut self) -> Option<String> {
+        let len = self.read_len()?;
+        String::from_utf8(self.read_bytes(len)?.to_vec()).ok()
+    }
+}
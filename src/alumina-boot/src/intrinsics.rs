@@ -16,6 +16,7 @@ use once_cell::sync::OnceCell;
 pub enum IntrinsicKind {
     SizeOf,
     AlignOf,
+    OffsetOf,
     ArrayLengthOf,
     TypeId,
     TypeName,
@@ -31,6 +32,7 @@ pub enum IntrinsicKind {
     MakeVtable,
     EnumVariants,
     Asm,
+    Uninitialized,
 }
 
 pub fn intrinsic_kind(name: &str) -> Option<IntrinsicKind> {
@@ -39,6 +41,7 @@ pub fn intrinsic_kind(name: &str) -> Option<IntrinsicKind> {
         let mut map = HashMap::new();
         map.insert("size_of", IntrinsicKind::SizeOf);
         map.insert("align_of", IntrinsicKind::AlignOf);
+        map.insert("offset_of", IntrinsicKind::OffsetOf);
         map.insert("array_length_of", IntrinsicKind::ArrayLengthOf);
         map.insert("type_id", IntrinsicKind::TypeId);
         map.insert("type_name", IntrinsicKind::TypeName);
@@ -54,6 +57,7 @@ pub fn intrinsic_kind(name: &str) -> Option<IntrinsicKind> {
         map.insert("vtable", IntrinsicKind::MakeVtable);
         map.insert("enum_variants", IntrinsicKind::EnumVariants);
         map.insert("asm", IntrinsicKind::Asm);
+        map.insert("uninitialized", IntrinsicKind::Uninitialized);
         map
     })
     .get(name)
@@ -295,9 +299,12 @@ impl<'ir> CompilerIntrinsics<'ir> {
         generic: &[TyP<'ir>],
         args: &[ExprP<'ir>],
     ) -> Result<ExprP<'ir>, AluminaError> {
+        // The expressions produced by the intrinsic are attributed to the call.
+        let previous = self.expressions.set_span(span);
+
         // Fine to panic when indexing here, if someone tried to change the signature
         // of the intrinsic in standard library, they deserve to have the compiler crash.
-        match kind {
+        let result = match kind {
             IntrinsicKind::SizeOf => self.size_of(generic[0]),
             IntrinsicKind::AlignOf => self.align_of(generic[0]),
             IntrinsicKind::TypeId => self.type_id(generic[0]),
@@ -312,6 +319,9 @@ impl<'ir> CompilerIntrinsics<'ir> {
             IntrinsicKind::CodegenFunc => self.codegen_func(args[0], &args[1..], generic[0]),
             IntrinsicKind::CodegenConst => self.codegen_const(args[0], generic[0]),
             _ => unimplemented!(),
-        }
+        };
+
+        self.expressions.set_span(previous);
+        result
     }
 }
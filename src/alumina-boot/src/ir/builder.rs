@@ -1,24 +1,52 @@
 use core::panic;
+use std::cell::Cell;
 
-use crate::{ast::BuiltinType, common::ArenaAllocatable, ir::*};
+use crate::{
+    ast::{BuiltinType, Span},
+    common::ArenaAllocatable,
+    ir::*,
+};
 
 use super::const_eval::Value;
 
 pub struct ExpressionBuilder<'ir> {
     ir: &'ir IrCtx<'ir>,
+    span: Cell<Option<Span>>,
 }
 
 impl<'ir> ExpressionBuilder<'ir> {
     pub fn new(ir: &'ir IrCtx<'ir>) -> Self {
-        Self { ir }
+        Self {
+            ir,
+            span: Cell::new(None),
+        }
+    }
+
+    /// Span that will be attached to all expressions built from now on (unless they
+    /// already have one).
+    pub fn span(&self) -> Option<Span> {
+        self.span.get()
+    }
+
+    /// Sets the current span and returns the previous one.
+    pub fn set_span(&self, span: Option<Span>) -> Option<Span> {
+        self.span.replace(span)
+    }
+
+    fn alloc(&self, mut expr: Expr<'ir>) -> ExprP<'ir> {
+        if expr.span.is_none() {
+            expr.span = self.span.get();
+        }
+
+        expr.alloc_on(self.ir)
     }
 
     pub fn local(&self, id: IrId, typ: TyP<'ir>) -> ExprP<'ir> {
-        Expr::lvalue(ExprKind::Local(id), typ).alloc_on(self.ir)
+        self.alloc(Expr::lvalue(ExprKind::Local(id), typ))
     }
 
     pub fn static_var(&self, item: IRItemP<'ir>, typ: TyP<'ir>) -> ExprP<'ir> {
-        Expr::lvalue(ExprKind::Static(item), typ).alloc_on(self.ir)
+        self.alloc(Expr::lvalue(ExprKind::Static(item), typ))
     }
 
     fn fill_block(
@@ -79,7 +107,10 @@ impl<'ir> ExpressionBuilder<'ir> {
             return ret;
         }
 
-        Expr::rvalue(ExprKind::Block(merged.alloc_on(self.ir), ret), ret.ty).alloc_on(self.ir)
+        self.alloc(Expr::rvalue(
+            ExprKind::Block(merged.alloc_on(self.ir), ret),
+            ret.ty,
+        ))
     }
 
     pub fn call<I>(&self, callee: ExprP<'ir>, args: I, return_ty: TyP<'ir>) -> ExprP<'ir>
@@ -92,11 +123,11 @@ impl<'ir> ExpressionBuilder<'ir> {
             return_ty,
         );
 
-        result.alloc_on(self.ir)
+        self.alloc(result)
     }
 
     pub fn lit(&self, lit: Lit<'ir>, ty: TyP<'ir>) -> ExprP<'ir> {
-        Expr::rvalue(ExprKind::Lit(lit), ty).alloc_on(self.ir)
+        self.alloc(Expr::rvalue(ExprKind::Lit(lit), ty))
     }
 
     pub fn if_then(&self, cond: ExprP<'ir>, then: ExprP<'ir>, els: ExprP<'ir>) -> ExprP<'ir> {
@@ -105,11 +136,25 @@ impl<'ir> ExpressionBuilder<'ir> {
             self.ir.intern_type(Ty::gcd(then.ty, els.ty)),
         );
 
-        result.alloc_on(self.ir)
+        self.alloc(result)
+    }
+
+    pub fn switch(
+        &self,
+        value: ExprP<'ir>,
+        arms: &[(Value<'ir>, ExprP<'ir>)],
+        default: ExprP<'ir>,
+    ) -> ExprP<'ir> {
+        let arms = self.ir.arena.alloc_slice_copy(arms);
+        let ty = arms.iter().fold(default.ty, |acc, (_, arm)| {
+            self.ir.intern_type(Ty::gcd(acc, arm.ty))
+        });
+
+        self.alloc(Expr::rvalue(ExprKind::Switch(value, arms, default), ty))
     }
 
     pub fn codegen_intrinsic(&self, kind: CodegenIntrinsicKind<'ir>, ty: TyP<'ir>) -> ExprP<'ir> {
-        Expr::rvalue(ExprKind::CodegenIntrinsic(kind), ty).alloc_on(self.ir)
+        self.alloc(Expr::rvalue(ExprKind::CodegenIntrinsic(kind), ty))
     }
 
     pub fn diverges(&self, exprs: impl IntoIterator<Item = ExprP<'ir>>) -> ExprP<'ir> {
@@ -129,51 +174,50 @@ impl<'ir> ExpressionBuilder<'ir> {
     }
 
     pub fn assign(&self, lhs: ExprP<'ir>, rhs: ExprP<'ir>) -> ExprP<'ir> {
-        Expr::rvalue(
+        self.alloc(Expr::rvalue(
             ExprKind::Assign(lhs, rhs),
             self.ir.intern_type(Ty::Builtin(BuiltinType::Void)),
-        )
-        .alloc_on(self.ir)
+        ))
     }
 
     pub fn goto(&self, label: IrId) -> ExprP<'ir> {
-        Expr::rvalue(
+        self.alloc(Expr::rvalue(
             ExprKind::Goto(label),
             self.ir.intern_type(Ty::Builtin(BuiltinType::Never)),
-        )
-        .alloc_on(self.ir)
+        ))
     }
 
     pub fn assign_op(&self, op: BinOp, lhs: ExprP<'ir>, rhs: ExprP<'ir>) -> ExprP<'ir> {
-        Expr::rvalue(
+        self.alloc(Expr::rvalue(
             ExprKind::AssignOp(op, lhs, rhs),
             self.ir.intern_type(Ty::Builtin(BuiltinType::Void)),
-        )
-        .alloc_on(self.ir)
+        ))
     }
 
     pub fn void(&self, ty: TyP<'ir>, value_type: ValueType) -> ExprP<'ir> {
-        Expr {
+        self.alloc(Expr {
             kind: ExprKind::Void,
             value_type,
             is_const: true,
             ty,
-        }
-        .alloc_on(self.ir)
+            span: None,
+        })
     }
 
     pub fn function(&self, item: IRItemP<'ir>) -> ExprP<'ir> {
         let ty = Ty::NamedFunction(item);
 
-        Expr::const_lvalue(ExprKind::Fn(item), self.ir.intern_type(ty)).alloc_on(self.ir)
+        self.alloc(Expr::const_lvalue(
+            ExprKind::Fn(item),
+            self.ir.intern_type(ty),
+        ))
     }
 
     pub fn unreachable(&self) -> ExprP<'ir> {
-        Expr::rvalue(
+        self.alloc(Expr::rvalue(
             ExprKind::Unreachable,
             self.ir.intern_type(Ty::Builtin(BuiltinType::Never)),
-        )
-        .alloc_on(self.ir)
+        ))
     }
 
     pub fn tuple_index(&self, tuple: ExprP<'ir>, index: usize, typ: TyP<'ir>) -> ExprP<'ir> {
@@ -182,9 +226,10 @@ impl<'ir> ExpressionBuilder<'ir> {
             value_type: tuple.value_type,
             is_const: tuple.is_const,
             ty: typ,
+            span: None,
         };
 
-        expr.alloc_on(self.ir)
+        self.alloc(expr)
     }
 
     pub fn const_value(&self, val: Value<'ir>) -> ExprP<'ir> {
@@ -194,9 +239,10 @@ impl<'ir> ExpressionBuilder<'ir> {
             value_type: ValueType::RValue,
             is_const: true,
             ty: self.ir.intern_type(value_kind),
+            span: None,
         };
 
-        expr.alloc_on(self.ir)
+        self.alloc(expr)
     }
 
     pub fn deref(&self, inner: ExprP<'ir>) -> ExprP<'ir> {
@@ -206,7 +252,7 @@ impl<'ir> ExpressionBuilder<'ir> {
             _ => panic!("not a pointer"),
         };
 
-        result.alloc_on(self.ir)
+        self.alloc(result)
     }
 
     pub fn binary(
@@ -218,7 +264,7 @@ impl<'ir> ExpressionBuilder<'ir> {
     ) -> ExprP<'ir> {
         let result = Expr::rvalue(ExprKind::Binary(op, lhs, rhs), result_typ);
 
-        result.alloc_on(self.ir)
+        self.alloc(result)
     }
 
     pub fn ret(&self, inner: ExprP<'ir>) -> ExprP<'ir> {
@@ -227,13 +273,13 @@ impl<'ir> ExpressionBuilder<'ir> {
             self.ir.intern_type(Ty::Builtin(BuiltinType::Never)),
         );
 
-        result.alloc_on(self.ir)
+        self.alloc(result)
     }
 
     pub fn cast(&self, expr: ExprP<'ir>, typ: TyP<'ir>) -> ExprP<'ir> {
         let result = Expr::rvalue(ExprKind::Cast(expr), typ);
 
-        result.alloc_on(self.ir)
+        self.alloc(result)
     }
 
     pub fn coerce(&self, expr: ExprP<'ir>, typ: TyP<'ir>) -> ExprP<'ir> {
@@ -242,15 +288,16 @@ impl<'ir> ExpressionBuilder<'ir> {
             kind: expr.kind.clone(),
             value_type: expr.value_type,
             ty: typ,
+            span: expr.span,
         };
 
-        result.alloc_on(self.ir)
+        self.alloc(result)
     }
 
     pub fn unary(&self, op: UnOp, inner: ExprP<'ir>, result_typ: TyP<'ir>) -> ExprP<'ir> {
         let result = Expr::rvalue(ExprKind::Unary(op, inner), result_typ);
 
-        result.alloc_on(self.ir)
+        self.alloc(result)
     }
 
     pub fn r#ref(&self, inner: ExprP<'ir>) -> ExprP<'ir> {
@@ -261,7 +308,7 @@ impl<'ir> ExpressionBuilder<'ir> {
             self.ir.intern_type(Ty::Pointer(inner.ty, inner.is_const)),
         );
 
-        result.alloc_on(self.ir)
+        self.alloc(result)
     }
 
     pub fn index(&self, inner: ExprP<'ir>, index: ExprP<'ir>) -> ExprP<'ir> {
@@ -274,7 +321,7 @@ impl<'ir> ExpressionBuilder<'ir> {
             _ => panic!("cannot index {:?}", inner.ty),
         };
 
-        result.alloc_on(self.ir)
+        self.alloc(result)
     }
 
     pub fn const_index(&self, inner: ExprP<'ir>, index: usize) -> ExprP<'ir> {
@@ -292,9 +339,10 @@ impl<'ir> ExpressionBuilder<'ir> {
             value_type: obj.value_type,
             is_const: obj.is_const,
             ty: typ,
+            span: None,
         };
 
-        expr.alloc_on(self.ir)
+        self.alloc(expr)
     }
 }
 
@@ -76,6 +76,13 @@ impl<'ir> DeadCodeEliminator<'ir> {
                 self.visit_expr(then)?;
                 self.visit_expr(els)?;
             }
+            ExprKind::Switch(value, arms, default) => {
+                self.visit_expr(value)?;
+                for (_, arm) in arms.iter() {
+                    self.visit_expr(arm)?;
+                }
+                self.visit_expr(default)?;
+            }
 
             ExprKind::Block(stmts, ret) => {
                 for s in stmts {
@@ -53,6 +53,7 @@ impl<'ir> ZstElider<'ir> {
     pub fn elide_zst_expr(&mut self, expr: ExprP<'ir>) -> ExprP<'ir> {
         let builder = ExpressionBuilder::new(self.ir);
         let types = TypeBuilder::new(self.ir);
+        builder.set_span(expr.span);
 
         let result = match expr.kind {
             ExprKind::Local(_) if expr.ty.is_zero_sized() => builder.void(expr.ty, expr.value_type),
@@ -132,14 +133,9 @@ impl<'ir> ZstElider<'ir> {
                         }
                     }
 
-                    builder.block(
-                        statements,
-                        Expr::rvalue(ExprKind::Call(callee, arguments.alloc_on(self.ir)), expr.ty)
-                            .alloc_on(self.ir),
-                    )
+                    builder.block(statements, builder.call(callee, arguments, expr.ty))
                 } else {
-                    Expr::rvalue(ExprKind::Call(callee, args.alloc_on(self.ir)), expr.ty)
-                        .alloc_on(self.ir)
+                    builder.call(callee, args, expr.ty)
                 }
             }
             ExprKind::Ref(inner) => {
@@ -205,6 +201,15 @@ impl<'ir> ZstElider<'ir> {
                     _ => builder.if_then(cond, then, els),
                 }
             }
+            ExprKind::Switch(value, arms, default) => {
+                let value = self.elide_zst_expr(value);
+                let arms: Vec<_> = arms
+                    .iter()
+                    .map(|(case, arm)| (*case, self.elide_zst_expr(arm)))
+                    .collect();
+
+                builder.switch(value, &arms, self.elide_zst_expr(default))
+            }
             ExprKind::Cast(inner) => builder.cast(self.elide_zst_expr(inner), expr.ty),
             ExprKind::Index(lhs, rhs) => {
                 let indexee = self.elide_zst_expr(lhs);
@@ -267,6 +272,7 @@ impl<'ir> ZstElider<'ir> {
                 ty: types.pointer(types.builtin(BuiltinType::U8), true),
                 value_type: result.value_type,
                 kind: result.kind.clone(),
+                span: result.span,
             }
             .alloc_on(self.ir);
         }
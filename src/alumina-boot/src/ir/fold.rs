@@ -0,0 +1,318 @@
+use std::collections::HashSet;
+
+use crate::{
+    ast::{BinOp, BuiltinType, UnOp},
+    common::ArenaAllocatable,
+};
+
+use super::{
+    builder::ExpressionBuilder,
+    const_eval::{const_eval, Value},
+    Expr, ExprKind, ExprP, FuncBody, IrCtx, IrId, Lit, Statement, Ty, ValueType,
+};
+
+// Constant folder evaluates arithmetic and comparisons on constant operands and removes the
+// branches of `if`s whose condition is known at compile time (e.g. cfg-dependent checks and
+// constants from generic code). It runs at the end of monomorphization of each function, so
+// the items that are only referenced from the removed branches are not emitted at all.
+//
+// Only the operations whose result is the same in C are folded. Arithmetic on types narrower
+// than `int` is subject to integer promotion in C, signed overflow and oversized shifts are
+// undefined behavior and division by zero traps at runtime, so these are left for the C
+// compiler to deal with.
+pub struct ConstFolder<'ir> {
+    ir: &'ir IrCtx<'ir>,
+    used_ids: HashSet<IrId>,
+}
+
+impl<'ir> ConstFolder<'ir> {
+    pub fn new(ir: &'ir IrCtx<'ir>) -> Self {
+        Self {
+            ir,
+            used_ids: HashSet::new(),
+        }
+    }
+
+    pub fn fold_func_body(mut self, function_body: FuncBody<'ir>) -> FuncBody<'ir> {
+        let mut statements = Vec::new();
+        for stmt in function_body.statements {
+            self.fold_stmt(stmt, &mut statements);
+        }
+
+        // Locals that were only used in the removed branches
+        let local_defs = function_body
+            .local_defs
+            .iter()
+            .copied()
+            .filter(|def| self.used_ids.contains(&def.id))
+            .collect::<Vec<_>>();
+
+        FuncBody {
+            statements: statements.alloc_on(self.ir),
+            local_defs: local_defs.alloc_on(self.ir),
+        }
+    }
+
+    pub fn fold_expr(&mut self, expr: ExprP<'ir>) -> ExprP<'ir> {
+        let builder = ExpressionBuilder::new(self.ir);
+        builder.set_span(expr.span);
+
+        let kind = match expr.kind {
+            ExprKind::Local(id) => {
+                self.used_ids.insert(id);
+                return expr;
+            }
+            ExprKind::Goto(label) => {
+                self.used_ids.insert(label);
+                return expr;
+            }
+            ExprKind::Block(stmts, ret) => {
+                let mut statements = Vec::new();
+                for stmt in stmts {
+                    self.fold_stmt(stmt, &mut statements);
+                }
+
+                let ret = self.fold_expr(ret);
+                statements.retain(|s| match s {
+                    Statement::Label(id) => self.used_ids.contains(id),
+                    _ => true,
+                });
+
+                return builder.block(statements, ret);
+            }
+            ExprKind::Binary(op, lhs, rhs) => {
+                let lhs = self.fold_expr(lhs);
+
+                // The right-hand side of `&&` and `||` is not evaluated if the left-hand side
+                // already determines the result, so it does not matter whether it is pure.
+                match (op, constant(lhs)) {
+                    (BinOp::And, Some(Value::Bool(false)))
+                    | (BinOp::Or, Some(Value::Bool(true))) => return lhs,
+                    (BinOp::And, Some(Value::Bool(true)))
+                    | (BinOp::Or, Some(Value::Bool(false))) => return self.fold_expr(rhs),
+                    _ => {}
+                }
+
+                let rhs = self.fold_expr(rhs);
+                match (constant(lhs), constant(rhs)) {
+                    (Some(a), Some(b)) if can_fold_binary(op, a, b) => {
+                        return self.evaluate(expr, ExprKind::Binary(op, lhs, rhs))
+                    }
+                    _ => ExprKind::Binary(op, lhs, rhs),
+                }
+            }
+            ExprKind::Unary(op, inner) => {
+                let inner = self.fold_expr(inner);
+                match constant(inner) {
+                    Some(value) if can_fold_unary(op, value) => {
+                        return self.evaluate(expr, ExprKind::Unary(op, inner))
+                    }
+                    _ => ExprKind::Unary(op, inner),
+                }
+            }
+            ExprKind::Cast(inner) => {
+                let inner = self.fold_expr(inner);
+                if constant(inner).is_some() && is_foldable_type(expr.ty) {
+                    return self.evaluate(expr, ExprKind::Cast(inner));
+                }
+
+                ExprKind::Cast(inner)
+            }
+            ExprKind::If(cond, then, els) => {
+                let cond = self.fold_expr(cond);
+                match constant(cond) {
+                    // If the branches have different types (e.g. one of them diverges), we keep
+                    // the `if`, since the the type of the expression would change otherwise.
+                    Some(Value::Bool(value)) => {
+                        let taken = if value { then } else { els };
+                        if taken.ty == expr.ty || expr.ty.is_void() {
+                            return self.fold_expr(taken);
+                        }
+
+                        ExprKind::If(cond, self.fold_expr(then), self.fold_expr(els))
+                    }
+                    _ => ExprKind::If(cond, self.fold_expr(then), self.fold_expr(els)),
+                }
+            }
+            ExprKind::Switch(value, arms, default) => {
+                let value = self.fold_expr(value);
+                let arms: Vec<_> = arms
+                    .iter()
+                    .map(|(case, arm)| (*case, self.fold_expr(arm)))
+                    .collect();
+
+                ExprKind::Switch(
+                    value,
+                    self.ir.arena.alloc_slice_copy(&arms),
+                    self.fold_expr(default),
+                )
+            }
+            ExprKind::AssignOp(op, lhs, rhs) => {
+                ExprKind::AssignOp(op, self.fold_expr(lhs), self.fold_expr(rhs))
+            }
+            ExprKind::Assign(lhs, rhs) => {
+                ExprKind::Assign(self.fold_expr(lhs), self.fold_expr(rhs))
+            }
+            ExprKind::Index(lhs, rhs) => ExprKind::Index(self.fold_expr(lhs), self.fold_expr(rhs)),
+            ExprKind::Call(callee, args) => {
+                let callee = self.fold_expr(callee);
+                let args = args
+                    .iter()
+                    .map(|arg| self.fold_expr(arg))
+                    .collect::<Vec<_>>();
+
+                ExprKind::Call(callee, args.alloc_on(self.ir))
+            }
+            ExprKind::Ref(inner) => ExprKind::Ref(self.fold_expr(inner)),
+            ExprKind::Deref(inner) => ExprKind::Deref(self.fold_expr(inner)),
+            ExprKind::Return(inner) => ExprKind::Return(self.fold_expr(inner)),
+            ExprKind::Field(inner, id) => ExprKind::Field(self.fold_expr(inner), id),
+            ExprKind::TupleIndex(inner, idx) => ExprKind::TupleIndex(self.fold_expr(inner), idx),
+            ExprKind::Fn(_)
+            | ExprKind::Static(_)
+            | ExprKind::Lit(_)
+            | ExprKind::ConstValue(_)
+            | ExprKind::CodegenIntrinsic(_)
+            | ExprKind::Unreachable
+            | ExprKind::Void => return expr,
+        };
+
+        self.rebuild(expr, kind)
+    }
+
+    // Type, value type and span stay the same, only the operands change.
+    fn rebuild(&self, expr: ExprP<'ir>, kind: ExprKind<'ir>) -> ExprP<'ir> {
+        Expr {
+            kind,
+            ..expr.clone()
+        }
+        .alloc_on(self.ir)
+    }
+
+    // Replaces the expression with its value if it can be evaluated, which may not be the
+    // case even if all the operands are constant (e.g. signed overflow).
+    fn evaluate(&self, expr: ExprP<'ir>, kind: ExprKind<'ir>) -> ExprP<'ir> {
+        let rebuilt = self.rebuild(expr, kind);
+        match const_eval(rebuilt) {
+            Ok(value) if value.type_kind() == *expr.ty => Expr {
+                kind: ExprKind::ConstValue(value),
+                value_type: ValueType::RValue,
+                is_const: true,
+                ty: expr.ty,
+                span: expr.span,
+            }
+            .alloc_on(self.ir),
+            _ => rebuilt,
+        }
+    }
+
+    fn fold_stmt(&mut self, stmt: &Statement<'ir>, statements: &mut Vec<Statement<'ir>>) {
+        match stmt {
+            Statement::Expression(expr) => {
+                let expr = self.fold_expr(expr);
+                if expr.pure() {
+                    return;
+                }
+
+                // Reduce nesting of the blocks that remain of the removed branches
+                if let ExprKind::Block(stmts, ret) = expr.kind {
+                    for stmt in stmts {
+                        statements.push(stmt.clone());
+                    }
+                    statements.push(Statement::Expression(ret));
+                } else {
+                    statements.push(Statement::Expression(expr));
+                }
+            }
+            _ => statements.push(stmt.clone()),
+        }
+    }
+}
+
+/// Value of the expression if it is an integer or a boolean constant.
+fn constant(expr: ExprP<'_>) -> Option<Value<'_>> {
+    match expr.kind {
+        ExprKind::Lit(Lit::Bool(_) | Lit::Int(_)) | ExprKind::ConstValue(_)
+            if is_foldable_type(expr.ty) =>
+        {
+            const_eval(expr).ok()
+        }
+        _ => None,
+    }
+}
+
+fn is_foldable_type(ty: &Ty<'_>) -> bool {
+    match ty {
+        Ty::Builtin(b) => b.is_integer() || matches!(b, BuiltinType::Bool),
+        _ => false,
+    }
+}
+
+/// Whether the operation on values of this type behaves the same in C, i.e. the operands
+/// are not promoted to `int`.
+fn is_wide(value: Value<'_>) -> bool {
+    matches!(
+        value,
+        Value::U32(_)
+            | Value::U64(_)
+            | Value::U128(_)
+            | Value::USize(_)
+            | Value::I32(_)
+            | Value::I64(_)
+            | Value::I128(_)
+            | Value::ISize(_)
+    )
+}
+
+/// Width of an unsigned integer type in bits.
+fn unsigned_bits(value: Value<'_>) -> Option<u32> {
+    match value {
+        Value::U32(_) => Some(u32::BITS),
+        Value::U64(_) => Some(u64::BITS),
+        Value::U128(_) => Some(u128::BITS),
+        Value::USize(_) => Some(usize::BITS),
+        _ => None,
+    }
+}
+
+fn is_min_value(value: Value<'_>) -> bool {
+    match value {
+        Value::I32(a) => a == i32::MIN,
+        Value::I64(a) => a == i64::MIN,
+        Value::I128(a) => a == i128::MIN,
+        Value::ISize(a) => a == isize::MIN,
+        _ => false,
+    }
+}
+
+fn can_fold_binary(op: BinOp, lhs: Value<'_>, rhs: Value<'_>) -> bool {
+    match op {
+        // Comparisons and bitwise operations give the same result with promoted operands
+        BinOp::And
+        | BinOp::Or
+        | BinOp::BitAnd
+        | BinOp::BitOr
+        | BinOp::BitXor
+        | BinOp::Eq
+        | BinOp::Neq
+        | BinOp::Lt
+        | BinOp::LEq
+        | BinOp::Gt
+        | BinOp::GEq => true,
+        // Signed overflow and division by zero are reported as errors by const_eval, in which
+        // case the expression is left as it is.
+        BinOp::Plus | BinOp::Minus | BinOp::Mul | BinOp::Div | BinOp::Mod => is_wide(lhs),
+        BinOp::LShift | BinOp::RShift => match (unsigned_bits(lhs), rhs) {
+            (Some(bits), Value::USize(amount)) => amount < bits as usize,
+            _ => false,
+        },
+    }
+}
+
+fn can_fold_unary(op: UnOp, value: Value<'_>) -> bool {
+    match op {
+        UnOp::Not => matches!(value, Value::Bool(_)),
+        UnOp::BitNot => is_wide(value),
+        UnOp::Neg => is_wide(value) && !is_min_value(value),
+    }
+}
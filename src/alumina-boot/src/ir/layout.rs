@@ -0,0 +1,178 @@
+use crate::ast::{Attribute, BuiltinType};
+use crate::global_ctx::GlobalCtx;
+
+use super::{IRItem, IrId, Ty, TyP};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Layout {
+    pub size: usize,
+    pub align: usize,
+}
+
+impl Layout {
+    const ZERO_SIZED: Layout = Layout { size: 0, align: 1 };
+
+    fn scalar(size: usize) -> Self {
+        Layout { size, align: size }
+    }
+}
+
+fn align_to(offset: usize, align: usize) -> usize {
+    offset.div_ceil(align) * align
+}
+
+// Computes the layout of types the same way the C compiler will lay out the generated code.
+// Structs and tuples are emitted as C structs with the fields in declaration order, each
+// at the next offset that is a multiple of its alignment, and zero-sized fields omitted.
+//
+// Layouts are only computed for targets where all scalar types are aligned to their size,
+// which is the case for all 64-bit targets and most 32-bit ones (but not for 32-bit x86,
+// where 64-bit scalars are only 4-byte aligned). For other targets `None` is returned.
+pub struct Layouter {
+    pointer_size: Option<usize>,
+}
+
+impl Layouter {
+    pub fn new(global_ctx: &GlobalCtx) -> Self {
+        let pointer_width = global_ctx.cfg("target_pointer_width").flatten();
+        let target_arch = global_ctx.cfg("target_arch").flatten();
+
+        let pointer_size = match (pointer_width.as_deref(), target_arch.as_deref()) {
+            (_, Some("x86")) => None,
+            (Some("64"), _) => Some(8),
+            (Some("32"), _) => Some(4),
+            _ => None,
+        };
+
+        Self { pointer_size }
+    }
+
+    pub fn layout_of(&self, ty: TyP<'_>) -> Option<Layout> {
+        let pointer_size = self.pointer_size?;
+        if ty.is_zero_sized() {
+            return Some(Layout::ZERO_SIZED);
+        }
+
+        match ty {
+            Ty::Builtin(builtin) => match builtin {
+                BuiltinType::Bool | BuiltinType::U8 | BuiltinType::I8 => Some(Layout::scalar(1)),
+                BuiltinType::U16 | BuiltinType::I16 => Some(Layout::scalar(2)),
+                BuiltinType::U32 | BuiltinType::I32 | BuiltinType::F32 => Some(Layout::scalar(4)),
+                BuiltinType::U64 | BuiltinType::I64 | BuiltinType::F64 => Some(Layout::scalar(8)),
+                // __int128 is only available on 64-bit targets
+                BuiltinType::U128 | BuiltinType::I128 if pointer_size == 8 => {
+                    Some(Layout::scalar(16))
+                }
+                BuiltinType::USize | BuiltinType::ISize => Some(Layout::scalar(pointer_size)),
+                _ => None,
+            },
+            Ty::Pointer(_, _) | Ty::FunctionPointer(_, _) => Some(Layout::scalar(pointer_size)),
+            Ty::Array(inner, len) => {
+                let inner = self.layout_of(inner)?;
+                Some(Layout {
+                    size: inner.size * len,
+                    align: inner.align,
+                })
+            }
+            Ty::Tuple(elems) => self.struct_layout(elems.iter().copied(), false, 1),
+            Ty::Closure(item) => match item.get().ok()? {
+                IRItem::Closure(c) => self.struct_layout(c.fields.iter().map(|f| f.ty), false, 1),
+                _ => None,
+            },
+            Ty::NamedType(item) => match item.get().ok()? {
+                IRItem::Alias(inner) => self.layout_of(inner),
+                IRItem::Enum(e) => self.layout_of(e.underlying_type),
+                IRItem::StructLike(s) => {
+                    let min_align = s
+                        .attributes
+                        .iter()
+                        .find_map(|a| match a {
+                            Attribute::Align(align) => Some(*align as usize),
+                            _ => None,
+                        })
+                        .unwrap_or(1);
+
+                    self.struct_layout(s.fields.iter().map(|f| f.ty), s.is_union, min_align)
+                }
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// Offset of the field with the given id in a struct, union or closure.
+    pub fn field_offset(&self, ty: TyP<'_>, field: IrId) -> Option<usize> {
+        let (fields, is_union) = match ty {
+            Ty::NamedType(item) => match item.get().ok()? {
+                IRItem::StructLike(s) => (s.fields, s.is_union),
+                _ => return None,
+            },
+            Ty::Closure(item) => match item.get().ok()? {
+                IRItem::Closure(c) => (c.fields, false),
+                _ => return None,
+            },
+            _ => return None,
+        };
+
+        let index = fields.iter().position(|f| f.id == field)?;
+        self.field_offsets(fields.iter().map(|f| f.ty), is_union)?
+            .get(index)
+            .copied()
+    }
+
+    /// Offset of the element with the given index in a tuple.
+    pub fn tuple_offset(&self, ty: TyP<'_>, index: usize) -> Option<usize> {
+        match ty {
+            Ty::Tuple(elems) => self
+                .field_offsets(elems.iter().copied(), false)?
+                .get(index)
+                .copied(),
+            _ => None,
+        }
+    }
+
+    fn field_offsets<'ir>(
+        &self,
+        fields: impl Iterator<Item = TyP<'ir>>,
+        is_union: bool,
+    ) -> Option<Vec<usize>> {
+        let mut offsets = Vec::new();
+        let mut size = 0;
+
+        for field in fields {
+            let layout = self.layout_of(field)?;
+            let offset = if is_union {
+                0
+            } else {
+                align_to(size, layout.align)
+            };
+
+            offsets.push(offset);
+            size = size.max(offset + layout.size);
+        }
+
+        Some(offsets)
+    }
+
+    fn struct_layout<'ir>(
+        &self,
+        fields: impl Iterator<Item = TyP<'ir>> + Clone,
+        is_union: bool,
+        min_align: usize,
+    ) -> Option<Layout> {
+        let offsets = self.field_offsets(fields.clone(), is_union)?;
+
+        let mut size = 0;
+        let mut align = min_align;
+        for (field, offset) in fields.zip(offsets) {
+            let layout = self.layout_of(field)?;
+            size = size.max(offset + layout.size);
+            align = align.max(layout.align);
+        }
+
+        Some(Layout {
+            size: align_to(size, align),
+            align,
+        })
+    }
+}
@@ -2,12 +2,16 @@ pub mod builder;
 pub mod const_eval;
 pub mod dce;
 pub mod elide_zst;
+pub mod fold;
 pub mod infer;
 pub mod lang;
+pub mod layout;
 pub mod mono;
+pub mod uninit;
+pub mod verify_spans;
 
 use crate::{
-    ast::{Attribute, BinOp, BuiltinType, UnOp},
+    ast::{Attribute, BinOp, BuiltinType, Span, UnOp},
     common::{impl_allocatable, Allocatable, ArenaAllocatable, CodeErrorKind, Incrementable},
     intrinsics::CodegenIntrinsicKind,
 };
@@ -246,6 +250,10 @@ impl<'ir> Ty<'ir> {
         matches!(self, Ty::Builtin(BuiltinType::Never))
     }
 
+    pub fn is_void(&self) -> bool {
+        matches!(self, Ty::Builtin(BuiltinType::Void))
+    }
+
     pub fn is_zero_sized(&self) -> bool {
         match self {
             Ty::Builtin(BuiltinType::Void) => true,
@@ -314,6 +322,16 @@ pub struct Function<'ir> {
     pub return_type: TyP<'ir>,
     pub body: OnceCell<FuncBody<'ir>>,
     pub varargs: bool,
+    /// Extern functions take their array parameters as raw C arrays rather than by value.
+    /// This is known before the body is, so it cannot be derived from `body`.
+    pub is_extern: bool,
+}
+
+impl<'ir> Function<'ir> {
+    /// Whether the array arguments of calls to this function are passed as raw C arrays.
+    pub fn takes_raw_c_arrays(&self) -> bool {
+        self.is_extern && self.args.iter().any(|p| matches!(p.ty, Ty::Array(_, _)))
+    }
 }
 
 #[derive(Debug)]
@@ -566,6 +584,13 @@ pub enum ExprKind<'ir> {
     Field(ExprP<'ir>, IrId),
     TupleIndex(ExprP<'ir>, usize),
     If(ExprP<'ir>, ExprP<'ir>, ExprP<'ir>),
+    // Scrutinee, the arms with their case values and the default arm. The arms do not produce
+    // a value, the result is assigned to a temporary in each arm instead.
+    Switch(
+        ExprP<'ir>,
+        &'ir [(const_eval::Value<'ir>, ExprP<'ir>)],
+        ExprP<'ir>,
+    ),
     Cast(ExprP<'ir>),
     CodegenIntrinsic(CodegenIntrinsicKind<'ir>),
     Unreachable,
@@ -584,6 +609,7 @@ pub struct Expr<'ir> {
     pub is_const: bool,
     pub kind: ExprKind<'ir>,
     pub ty: TyP<'ir>,
+    pub span: Option<Span>,
 }
 
 impl<'ir> Expr<'ir> {
@@ -593,6 +619,7 @@ impl<'ir> Expr<'ir> {
             value_type: ValueType::LValue,
             is_const: false,
             ty: typ,
+            span: None,
         }
     }
 
@@ -602,6 +629,7 @@ impl<'ir> Expr<'ir> {
             value_type: ValueType::RValue,
             is_const: false,
             ty: typ,
+            span: None,
         }
     }
 
@@ -611,6 +639,7 @@ impl<'ir> Expr<'ir> {
             value_type: ValueType::LValue,
             is_const: true,
             ty: typ,
+            span: None,
         }
     }
 
@@ -638,6 +667,9 @@ impl<'ir> Expr<'ir> {
             ExprKind::Unary(_, inner) => inner.pure(),
             ExprKind::Index(a, b) => a.pure() && b.pure(),
             ExprKind::If(a, b, c) => a.pure() && b.pure() && c.pure(),
+            ExprKind::Switch(a, arms, b) => {
+                a.pure() && arms.iter().all(|(_, e)| e.pure()) && b.pure()
+            }
             ExprKind::Cast(inner) => inner.pure(),
             ExprKind::Field(inner, _) => inner.pure(),
             ExprKind::TupleIndex(inner, _) => inner.pure(),
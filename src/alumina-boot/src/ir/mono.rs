@@ -5,14 +5,17 @@ use std::collections::{HashMap, HashSet};
 
 use std::iter::{once, repeat};
 
-use indexmap::IndexMap;
+use indexmap::{IndexMap, IndexSet};
 use once_cell::unsync::OnceCell;
 
 use super::builder::{ExpressionBuilder, TypeBuilder};
 use super::const_eval::{const_eval, numeric_of_kind, Value};
 use super::elide_zst::ZstElider;
+use super::fold::ConstFolder;
 use super::infer::TypeInferer;
 use super::lang::LangTypeKind;
+use super::layout::Layouter;
+use super::uninit::UninitChecker;
 use super::{FuncBody, IRItemP, Lit, LocalDef, UnqualifiedKind};
 use crate::ast::lang::LangItemKind;
 use crate::ast::rebind::Rebinder;
@@ -59,9 +62,17 @@ pub struct MonoCtx<'ast, 'ir> {
     reverse_map: HashMap<ir::IRItemP<'ir>, MonoKey<'ast, 'ir>>,
     tests: HashMap<ir::IRItemP<'ir>, TestMetadata<'ast>>,
     intrinsics: CompilerIntrinsics<'ir>,
+    layouter: Layouter,
     static_local_defs: HashMap<ir::IRItemP<'ir>, Vec<LocalDef<'ir>>>,
     test_cases_statics: Option<TestCasesStatics<'ir>>,
     vtable_layouts: HashMap<&'ir [ir::TyP<'ir>], ir::VtableLayout<'ir>>,
+    drop_fns: HashMap<ir::TyP<'ir>, Option<ir::IRItemP<'ir>>>,
+    needs_drop: HashMap<ir::TyP<'ir>, bool>,
+    // Generic functions are lowered once per instance, so unreachable code is only reported once
+    // all of them have been lowered, and only if it was not reachable in any of them.
+    unreachable_spans: IndexSet<ast::Span>,
+    reachable_spans: HashSet<ast::Span>,
+    optimize: bool,
 }
 
 enum BoundCheckResult {
@@ -83,19 +94,52 @@ impl<'ast, 'ir> MonoCtx<'ast, 'ir> {
             id_map: HashMap::new(),
             finished: IndexMap::new(),
             reverse_map: HashMap::new(),
+            layouter: Layouter::new(&global_ctx),
             intrinsics: CompilerIntrinsics::new(global_ctx, ir),
             static_local_defs: HashMap::new(),
             cycle_guardian: CycleGuardian::new(),
             tests: HashMap::new(),
             test_cases_statics: None,
             vtable_layouts: HashMap::new(),
+            drop_fns: HashMap::new(),
+            needs_drop: HashMap::new(),
+            unreachable_spans: IndexSet::new(),
+            reachable_spans: HashSet::new(),
+            optimize: false,
         }
     }
 
+    /// Fold constant expressions and remove the branches with constant conditions in the
+    /// function bodies.
+    pub fn set_optimize(&mut self, optimize: bool) {
+        self.optimize = optimize;
+    }
+
     fn map_id(&mut self, id: ast::AstId) -> ir::IrId {
         *self.id_map.entry(id).or_insert_with(|| self.ir.make_id())
     }
 
+    /// AST items that were monomorphized (with any generic arguments) during the compilation.
+    pub fn monomorphized_items(&self) -> HashSet<ast::ItemP<'ast>> {
+        self.finished
+            .keys()
+            .filter(|MonoKey(_, _, _, tentative)| !tentative)
+            .map(|MonoKey(item, _, _, _)| *item)
+            .collect()
+    }
+
+    /// Warns about the code that was unreachable in every instance it was lowered in.
+    pub fn report_unreachable_code(&self) {
+        for span in &self.unreachable_spans {
+            if !self.reachable_spans.contains(span) {
+                self.global_ctx.diag().add_warning(CodeError::from_kind(
+                    CodeErrorKind::UnreachableCode,
+                    Some(*span),
+                ));
+            }
+        }
+    }
+
     pub fn reverse_lookup(&self, item: ir::IRItemP<'ir>) -> MonoKey<'ast, 'ir> {
         self.reverse_map
             .get(&item)
@@ -296,6 +340,14 @@ pub struct LoopContext<'ir> {
     loop_result: ir::IrId,
     break_label: ir::IrId,
     continue_label: ir::IrId,
+    drop_depth: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct DropLocal<'ir> {
+    local: ir::IrId,
+    flag: ir::IrId,
+    typ: ir::TyP<'ir>,
 }
 
 #[derive(Debug, Clone)]
@@ -330,7 +382,16 @@ pub struct Monomorphizer<'a, 'ast, 'ir> {
     local_types: HashMap<ir::IrId, ir::TyP<'ir>>,
     local_type_hints: HashMap<ir::IrId, ir::TyP<'ir>>,
     local_defs: Vec<ir::LocalDef<'ir>>,
+    uninitialized_locals: HashMap<ir::IrId, &'ast str>,
     defer_context: Option<DeferContext<'ir>>,
+    drop_scopes: Vec<Vec<DropLocal<'ir>>>,
+    drop_flags: HashMap<ir::IrId, ir::IrId>,
+    drop_prologue: Vec<ir::Statement<'ir>>,
+    depth: usize,
+    // Number of `when` expressions lowered so far in which the selected branch diverges. Code
+    // after them is only unreachable for some generic arguments (or cfg options), so it is not
+    // reported.
+    diverging_whens: usize,
 
     tentative: bool,
 }
@@ -371,7 +432,13 @@ impl<'a, 'ast, 'ir> Monomorphizer<'a, 'ast, 'ir> {
             loop_contexts: Vec::new(),
             local_type_hints: HashMap::new(),
             local_defs: Vec::new(),
+            uninitialized_locals: HashMap::new(),
             defer_context: None,
+            drop_scopes: Vec::new(),
+            drop_flags: HashMap::new(),
+            drop_prologue: Vec::new(),
+            depth: 0,
+            diverging_whens: 0,
             tentative,
             current_item: parent_item,
         }
@@ -393,8 +460,14 @@ impl<'a, 'ast, 'ir> Monomorphizer<'a, 'ast, 'ir> {
             return_type: None,
             loop_contexts: Vec::new(),
             local_defs: Vec::new(),
+            uninitialized_locals: HashMap::new(),
             local_type_hints: HashMap::new(),
             defer_context: None,
+            drop_scopes: Vec::new(),
+            drop_flags: HashMap::new(),
+            drop_prologue: Vec::new(),
+            depth: 0,
+            diverging_whens: 0,
             tentative,
             current_item: parent_item,
         }
@@ -447,6 +520,8 @@ impl<'a, 'ast, 'ir> Monomorphizer<'a, 'ast, 'ir> {
                 return Err(CodeErrorKind::DuplicateEnumMember).with_span(m.span);
             }
 
+            // Every use of the member shares the value, so it points to the declaration
+            child.exprs.set_span(m.span);
             members.push(ir::EnumMember {
                 id: child.mono_ctx.map_id(m.id),
                 name: m.name.alloc_on(child.mono_ctx.ir),
@@ -476,10 +551,11 @@ impl<'a, 'ast, 'ir> Monomorphizer<'a, 'ast, 'ir> {
                 .unwrap();
             };
 
+            child.exprs.set_span(m.span);
             members.push(ir::EnumMember {
                 id: child.mono_ctx.map_id(m.id),
                 name: m.name.alloc_on(child.mono_ctx.ir),
-                value: self.exprs.const_value(next_non_taken),
+                value: child.exprs.const_value(next_non_taken),
             });
         }
 
@@ -1104,6 +1180,9 @@ impl<'a, 'ast, 'ir> Monomorphizer<'a, 'ast, 'ir> {
                 .append_span(s.span)?;
         }
 
+        // Coercions and the constant initializers of thread-locals are attributed to the static.
+        child.exprs.set_span(s.span);
+
         let typ = s.typ.map(|t| child.lower_type_for_value(t)).transpose()?;
         let mut init = s.init.map(|t| child.lower_expr(t, typ)).transpose()?;
 
@@ -1132,6 +1211,21 @@ impl<'a, 'ast, 'ir> Monomorphizer<'a, 'ast, 'ir> {
             let typ = child.try_qualify_type(typ)?;
             if let Some(init) = &mut init {
                 *init = child.try_coerce(typ, init)?;
+
+                // Static initializers run once in the static constructor, so they would only
+                // initialize the main thread's copy of a thread-local. Instead, thread-locals
+                // are initialized by the C initializer, which must be a constant.
+                if s.attributes.contains(&Attribute::ThreadLocal) {
+                    let value = match ir::const_eval::const_eval(init) {
+                        Ok(Value::Str(_) | Value::Void) | Err(_) => {
+                            return Err(CodeErrorKind::NonConstThreadLocalInit)
+                                .with_span(s.init.unwrap().span)
+                        }
+                        Ok(value) => value,
+                    };
+
+                    *init = child.exprs.const_value(value);
+                }
             }
 
             let res = ir::IRItem::Static(ir::Static {
@@ -1152,6 +1246,18 @@ impl<'a, 'ast, 'ir> Monomorphizer<'a, 'ast, 'ir> {
         Ok(())
     }
 
+    /// Arrays are passed by value in Alumina, which is not possible in C, so they are wrapped
+    /// in a struct. Extern functions take raw C arrays instead (i.e. a pointer to the first
+    /// element), but C functions cannot return arrays at all.
+    fn check_extern_return_type(&self, ty: ir::TyP<'ir>) -> Result<(), CodeErrorKind> {
+        match ty {
+            ir::Ty::Array(_, _) if !ty.is_zero_sized() => Err(CodeErrorKind::ExternReturnsArray(
+                self.mono_ctx.type_name(ty).unwrap(),
+            )),
+            _ => Ok(()),
+        }
+    }
+
     fn monomorphize_function(
         &mut self,
         item: ir::IRItemP<'ir>,
@@ -1193,11 +1299,17 @@ impl<'a, 'ast, 'ir> Monomorphizer<'a, 'ast, 'ir> {
             .collect::<Result<Vec<_>, AluminaError>>()?;
 
         let return_type = child.lower_type_for_value(func.return_type)?;
+        if func.body.is_none() {
+            child
+                .check_extern_return_type(return_type)
+                .with_span(func.span)?;
+        }
         let res = ir::IRItem::Function(ir::Function {
             name: func.name.map(|n| n.alloc_on(child.mono_ctx.ir)),
             attributes: func.attributes.alloc_on(child.mono_ctx.ir),
             args: parameters.alloc_on(child.mono_ctx.ir),
             varargs: func.varargs,
+            is_extern: func.body.is_none(),
             return_type,
             body: OnceCell::new(),
         });
@@ -1220,7 +1332,14 @@ impl<'a, 'ast, 'ir> Monomorphizer<'a, 'ast, 'ir> {
 
         child.return_type = Some(return_type);
         if let Some(body) = func.body {
-            let body = child.lower_function_body(body)?;
+            // Parameters passed by value are owned by the callee, so they are dropped when it returns.
+            child.drop_scopes.push(Vec::new());
+            for (param, ast_param) in item.get_function().unwrap().args.iter().zip(func.args) {
+                child.exprs.set_span(ast_param.span);
+                child.track_drop(param.id, param.ty, true)?;
+            }
+
+            let body = child.lower_function_body(body, func.span)?;
             item.get_function().unwrap().body.set(body).unwrap();
         }
 
@@ -1336,12 +1455,20 @@ impl<'a, 'ast, 'ir> Monomorphizer<'a, 'ast, 'ir> {
     pub fn lower_function_body(
         mut self,
         expr: ast::ExprP<'ast>,
+        function_span: Option<ast::Span>,
     ) -> Result<ir::FuncBody<'ir>, AluminaError> {
         let return_type = self.return_type.unwrap();
+
+        // The code that is generated around the body (coercion of the return value, drops of
+        // the parameters, defer prologue and epilogue) is attributed to the body as a whole.
+        self.exprs.set_span(expr.span);
+
         let body = self
             .lower_expr(expr, Some(return_type))
             .append_span(expr.span)?;
 
+        self.check_all_paths_return(expr, function_span, body, return_type)?;
+
         let body = self.try_coerce(return_type, body).append_span(expr.span)?;
 
         let mut statements = Vec::new();
@@ -1350,6 +1477,8 @@ impl<'a, 'ast, 'ir> Monomorphizer<'a, 'ast, 'ir> {
             self.generate_defer_prologue(&mut statements);
         }
 
+        statements.append(&mut self.drop_prologue);
+
         if let ir::ExprKind::Block(block, ret) = body.kind {
             statements.extend(block.iter().cloned());
             statements.push(ir::Statement::Expression(self.make_return(ret)?));
@@ -1366,12 +1495,61 @@ impl<'a, 'ast, 'ir> Monomorphizer<'a, 'ast, 'ir> {
             local_defs: self.local_defs.alloc_on(self.mono_ctx.ir),
         };
 
+        if !self.uninitialized_locals.is_empty() {
+            UninitChecker::new(&self.uninitialized_locals, &self.local_types)
+                .check(&function_body)?;
+        }
+
         let elider = ZstElider::new(self.mono_ctx.ir);
-        let optimized = elider.elide_zst_func_body(function_body);
+        let mut optimized = elider.elide_zst_func_body(function_body);
+
+        if self.mono_ctx.optimize {
+            optimized = ConstFolder::new(self.mono_ctx.ir).fold_func_body(optimized);
+        }
 
         Ok(optimized)
     }
 
+    fn check_all_paths_return(
+        &self,
+        expr: ast::ExprP<'ast>,
+        function_span: Option<ast::Span>,
+        body: ir::ExprP<'ir>,
+        return_type: ir::TyP<'ir>,
+    ) -> Result<(), AluminaError> {
+        // Void functions are allowed to fall off the end and diverging bodies (including
+        // infinite loops) never reach the end in the first place.
+        if return_type.is_void() || body.diverges() || !body.ty.is_void() {
+            return Ok(());
+        }
+
+        // Only report this if the control can actually fall off the end of the body (implicit void
+        // result or a branch/loop that does not produce a value). Other cases are ordinary
+        // type mismatches and are reported as such by the coercion.
+        let tail = match expr.kind {
+            ast::ExprKind::Block(_, ret) => ret,
+            _ => expr,
+        };
+
+        match tail.kind {
+            ast::ExprKind::Void
+            | ast::ExprKind::If(..)
+            | ast::ExprKind::Switch(..)
+            | ast::ExprKind::StaticIf(..)
+            | ast::ExprKind::Loop(..)
+            | ast::ExprKind::Block(..) => {
+                // Reported at the closing brace of the function, where the control falls off the
+                // end. The body itself does not have one if it is just the tail expression.
+                let closing_brace = function_span.map(|span| ast::Span {
+                    start: span.end.saturating_sub(1),
+                    ..span
+                });
+                Err(CodeErrorKind::NotAllPathsReturn).with_span(closing_brace.or(tail.span))
+            }
+            _ => Ok(()),
+        }
+    }
+
     pub fn get_mono_key(
         &mut self,
         item: ast::ItemP<'ast>,
@@ -1552,14 +1730,21 @@ impl<'a, 'ast, 'ir> Monomorphizer<'a, 'ast, 'ir> {
             .finished
             .iter()
             .filter_map(|(_, v)| match v.get() {
-                Ok(ir::IRItem::Static(s)) if s.init.is_some() && alive.contains(v) => Some((v, s)),
+                Ok(ir::IRItem::Static(s))
+                    if s.init.is_some()
+                        && !s.attributes.contains(&Attribute::ThreadLocal)
+                        && alive.contains(v) =>
+                {
+                    Some((v, s))
+                }
                 _ => None,
             })
             .map(|(v, s)| {
+                let init = s.init.unwrap();
+                self.exprs.set_span(init.span);
                 (
                     ir::Statement::Expression(
-                        self.exprs
-                            .assign(self.exprs.static_var(v, s.typ), s.init.unwrap()),
+                        self.exprs.assign(self.exprs.static_var(v, s.typ), init),
                     ),
                     self.mono_ctx.static_local_defs.get(v).unwrap().clone(),
                 )
@@ -1575,11 +1760,13 @@ impl<'a, 'ast, 'ir> Monomorphizer<'a, 'ast, 'ir> {
                 .void(self.types.builtin(BuiltinType::Void), ir::ValueType::RValue),
         );
 
+        // If there is nothing to initialize, the body is left empty rather than having a
+        // return that could not be attributed to anything.
         let mut statements = Vec::new();
         if let ir::ExprKind::Block(block, ret) = body.kind {
             statements.extend(block.iter().cloned());
             statements.push(ir::Statement::Expression(self.make_return(ret)?));
-        } else {
+        } else if !body.is_void() {
             statements.push(ir::Statement::Expression(self.make_return(body)?));
         };
 
@@ -1589,7 +1776,11 @@ impl<'a, 'ast, 'ir> Monomorphizer<'a, 'ast, 'ir> {
         };
 
         let elider = ZstElider::new(self.mono_ctx.ir);
-        let optimized = elider.elide_zst_func_body(function_body);
+        let mut optimized = elider.elide_zst_func_body(function_body);
+
+        if self.mono_ctx.optimize {
+            optimized = ConstFolder::new(self.mono_ctx.ir).fold_func_body(optimized);
+        }
 
         item.assign(ir::IRItem::Function(ir::Function {
             name: None,
@@ -1597,6 +1788,7 @@ impl<'a, 'ast, 'ir> Monomorphizer<'a, 'ast, 'ir> {
             args: [].alloc_on(self.mono_ctx.ir),
             return_type: self.types.builtin(BuiltinType::Void),
             varargs: false,
+            is_extern: false,
             body: OnceCell::from(optimized),
         }));
 
@@ -2127,6 +2319,22 @@ impl<'a, 'ast, 'ir> Monomorphizer<'a, 'ast, 'ir> {
         Ok(res)
     }
 
+    fn no_field_named(&mut self, item: ir::IRItemP<'ir>, name: &str) -> CodeErrorKind {
+        let MonoKey(ast_item, _, _, _) = self.mono_ctx.reverse_lookup(item);
+        let candidates = ast_item
+            .get_struct_like()
+            .fields
+            .iter()
+            .map(|f| format!("`{}`", f.name))
+            .collect::<Vec<_>>();
+
+        CodeErrorKind::NoFieldNamed(
+            name.to_string(),
+            self.mono_ctx.type_name(self.types.named(item)).unwrap(),
+            candidates.join(", "),
+        )
+    }
+
     fn get_associated_fns(
         &mut self,
         typ: ast::TyP<'ast>,
@@ -2199,8 +2407,14 @@ impl<'a, 'ast, 'ir> Monomorphizer<'a, 'ast, 'ir> {
             return_type: self.return_type,
             loop_contexts: self.loop_contexts.clone(),
             local_defs: self.local_defs.clone(),
+            uninitialized_locals: self.uninitialized_locals.clone(),
             local_type_hints: self.local_type_hints.clone(),
             defer_context: self.defer_context.clone(),
+            drop_scopes: self.drop_scopes.clone(),
+            drop_flags: self.drop_flags.clone(),
+            drop_prologue: Vec::new(),
+            depth: self.depth,
+            diverging_whens: self.diverging_whens,
             current_item: self.current_item,
             tentative: true,
         }
@@ -2226,6 +2440,14 @@ impl<'a, 'ast, 'ir> Monomorphizer<'a, 'ast, 'ir> {
         match (lhs_typ, rhs.ty) {
             (ir::Ty::FunctionPointer(args, ret), ir::Ty::NamedFunction(a)) => {
                 let fun = a.get_function().with_no_span()?;
+                // Function pointers know nothing about raw C arrays, so such functions can only
+                // be called directly.
+                if fun.takes_raw_c_arrays() {
+                    return Err(CodeErrorKind::ExternArraysInFnPointer(
+                        self.mono_ctx.type_name(rhs.ty).unwrap(),
+                    ))
+                    .with_no_span();
+                }
                 if fun.args.len() != args.len() {
                     return Err(mismatch!(self, lhs_typ, rhs.ty)).with_no_span();
                 }
@@ -2702,7 +2924,7 @@ impl<'a, 'ast, 'ir> Monomorphizer<'a, 'ast, 'ir> {
         let result = match &stmt.kind {
             ast::StatementKind::Expression(expr) => {
                 let expr = self.lower_expr(expr, None)?;
-                Some(ir::Statement::Expression(expr))
+                Some(ir::Statement::Expression(self.drop_temporary(expr)?))
             }
             ast::StatementKind::LetDeclaration(decl) => {
                 let id = self.mono_ctx.map_id(decl.id);
@@ -2724,6 +2946,12 @@ impl<'a, 'ast, 'ir> Monomorphizer<'a, 'ast, 'ir> {
                     (Some(ty), None) => {
                         self.local_types.insert(id, ty);
                         self.local_defs.push(ir::LocalDef { id, typ: ty });
+                        if let Some(name) = decl.name {
+                            if !ty.is_zero_sized() {
+                                self.uninitialized_locals.insert(id, name);
+                            }
+                            self.track_drop(id, ty, false)?;
+                        }
                         None
                     }
                     (None, Some(init)) => {
@@ -2737,8 +2965,9 @@ impl<'a, 'ast, 'ir> Monomorphizer<'a, 'ast, 'ir> {
                         }
 
                         let init = self.try_coerce(typ, init)?;
+                        let init = self.consume(init)?;
                         Some(ir::Statement::Expression(
-                            self.exprs.assign(self.exprs.local(id, init.ty), init),
+                            self.initialize_local(id, decl.name, typ, init)?,
                         ))
                     }
                     (Some(ty), Some(init)) => {
@@ -2750,8 +2979,9 @@ impl<'a, 'ast, 'ir> Monomorphizer<'a, 'ast, 'ir> {
                         }
 
                         let init = self.try_coerce(ty, init)?;
+                        let init = self.consume(init)?;
                         Some(ir::Statement::Expression(
-                            self.exprs.assign(self.exprs.local(id, ty), init),
+                            self.initialize_local(id, decl.name, ty, init)?,
                         ))
                     }
                 }
@@ -2792,9 +3022,59 @@ impl<'a, 'ast, 'ir> Monomorphizer<'a, 'ast, 'ir> {
         ret: ast::ExprP<'ast>,
         type_hint: Option<ir::TyP<'ir>>,
     ) -> Result<ir::ExprP<'ir>, AluminaError> {
+        self.drop_scopes.push(Vec::new());
+        let result = self.lower_block_scope(statements, ret, type_hint);
+        let has_drops = !self.drop_scopes.last().unwrap().is_empty();
+
+        let result = match result {
+            Ok((statements, ret)) if has_drops && !ret.diverges() => {
+                // The result is evaluated before the locals of the block are dropped, so it can
+                // still refer to them.
+                let depth = self.drop_scopes.len() - 1;
+                self.consume(ret)
+                    .and_then(|ret| {
+                        let drops = self.drop_scopes_from(depth)?;
+                        self.with_drops(ret, drops)
+                    })
+                    .map(|ret| self.exprs.block(statements, ret))
+            }
+            Ok((statements, ret)) => Ok(self.exprs.block(statements, ret)),
+            Err(e) => Err(e),
+        };
+
+        self.drop_scopes.pop();
+        result
+    }
+
+    fn lower_block_scope(
+        &mut self,
+        statements: &'ast [ast::Statement<'ast>],
+        ret: ast::ExprP<'ast>,
+        type_hint: Option<ir::TyP<'ir>>,
+    ) -> Result<(Vec<ir::Statement<'ir>>, ir::ExprP<'ir>), AluminaError> {
+        let mut diverged = false;
+        let mut unreachable_span = None;
+
         let (statements, errors): (Vec<_>, Vec<_>) = statements
             .iter()
-            .map(|stmt| self.lower_stmt(stmt).append_span(stmt.span))
+            .map(|stmt| {
+                match stmt.span {
+                    Some(span) if !diverged => {
+                        self.mono_ctx.reachable_spans.insert(span);
+                    }
+                    Some(span) if unreachable_span.is_none() => unreachable_span = Some(span),
+                    _ => {}
+                }
+
+                let diverging_whens = self.diverging_whens;
+                let result = self.lower_stmt(stmt).append_span(stmt.span);
+                if let Ok(Some(ir::Statement::Expression(expr))) = &result {
+                    diverged =
+                        diverged || (expr.diverges() && self.diverging_whens == diverging_whens);
+                }
+
+                result
+            })
             .partition(|f| f.is_ok());
 
         if !errors.is_empty() {
@@ -2808,11 +3088,25 @@ impl<'a, 'ast, 'ir> Monomorphizer<'a, 'ast, 'ir> {
             return Err(AluminaError::CodeErrors(combined));
         }
 
+        match ret.span {
+            _ if matches!(ret.kind, ast::ExprKind::Void) => {}
+            Some(span) if !diverged => {
+                self.mono_ctx.reachable_spans.insert(span);
+            }
+            Some(span) if unreachable_span.is_none() => unreachable_span = Some(span),
+            _ => {}
+        }
+
+        if let Some(span) = unreachable_span {
+            self.mono_ctx.unreachable_spans.insert(span);
+        }
+
         let ret = self.lower_expr(ret, type_hint)?;
 
-        Ok(self
-            .exprs
-            .block(statements.into_iter().flat_map(|e| e.unwrap()), ret))
+        Ok((
+            statements.into_iter().flat_map(|e| e.unwrap()).collect(),
+            ret,
+        ))
     }
 
     fn lower_lit(
@@ -2915,9 +3209,78 @@ impl<'a, 'ast, 'ir> Monomorphizer<'a, 'ast, 'ir> {
         let typ = self.try_qualify_type(inner.ty)?;
         let inner = self.try_coerce(typ, inner)?;
 
+        if let Some(address) = self.fold_field_address(inner) {
+            return Ok(address);
+        }
+
         Ok(self.r#ref(inner))
     }
 
+    // Lowers the address of a field projection (e.g. `&a.b.c[1]` or `&ptr.field`) as an offset
+    // from the address of the object, so that nothing about it requires the object to be
+    // initialized (or even properly aligned). All the projections are folded into a single
+    // offset, so this is only done when all of them are statically known.
+    fn fold_field_address(&mut self, place: ir::ExprP<'ir>) -> Option<ir::ExprP<'ir>> {
+        if place.value_type != ValueType::LValue || place.ty.is_zero_sized() {
+            return None;
+        }
+
+        let layouter = &self.mono_ctx.layouter;
+        let mut has_field = false;
+        let mut offset = 0;
+        let mut root = place;
+        loop {
+            match root.kind {
+                ir::ExprKind::Field(obj, id) => {
+                    offset += layouter.field_offset(obj.ty, id)?;
+                    has_field = true;
+                    root = obj;
+                }
+                ir::ExprKind::TupleIndex(obj, index) => {
+                    offset += layouter.tuple_offset(obj.ty, index)?;
+                    has_field = true;
+                    root = obj;
+                }
+                ir::ExprKind::Index(obj, index) if matches!(obj.ty, ir::Ty::Array(_, _)) => {
+                    let index = match const_eval(index) {
+                        Ok(Value::USize(index)) => index,
+                        _ => return None,
+                    };
+                    offset += index * layouter.layout_of(root.ty)?.size;
+                    root = obj;
+                }
+                _ => break,
+            }
+        }
+
+        if !has_field {
+            return None;
+        }
+
+        let base = self.exprs.r#ref(root);
+        let byte_ptr = self
+            .types
+            .pointer(self.types.builtin(BuiltinType::U8), root.is_const);
+
+        let mut address = self.exprs.cast(base, byte_ptr);
+        if offset > 0 {
+            address = self.exprs.binary(
+                ast::BinOp::Plus,
+                address,
+                self.exprs.lit(
+                    Lit::Int(offset as u128),
+                    self.types.builtin(BuiltinType::ISize),
+                ),
+                byte_ptr,
+            );
+        }
+
+        Some(
+            self.exprs
+                .cast(address, self.types.pointer(place.ty, place.is_const)),
+        )
+    }
+
     fn lower_local(
         &mut self,
         id: ast::AstId,
@@ -3167,19 +3530,96 @@ impl<'a, 'ast, 'ir> Monomorphizer<'a, 'ast, 'ir> {
         }
 
         let rhs = self.try_coerce(lhs.ty, rhs)?;
+        let rhs = self.consume(rhs)?;
+
+        if let Some(flag) = self.drop_flag_of_root(lhs) {
+            if self.needs_drop(lhs.ty)? {
+                return self.lower_assign_with_drop(lhs, rhs, flag);
+            }
+        }
 
         Ok(self.exprs.assign(lhs, rhs))
     }
 
+    // Assigning over a value that needs to be dropped drops the old value first. The new value is
+    // evaluated before that, as it may still refer to the old one (e.g. `a = wrap(a.inner)`).
+    fn lower_assign_with_drop(
+        &mut self,
+        lhs: ir::ExprP<'ir>,
+        rhs: ir::ExprP<'ir>,
+        flag: ir::IrId,
+    ) -> Result<ir::ExprP<'ir>, AluminaError> {
+        let bool_type = self.types.builtin(BuiltinType::Bool);
+        let void_type = self.types.builtin(BuiltinType::Void);
+
+        let mut statements = Vec::new();
+
+        // Projections can have side effects (e.g. array indexing), so we only evaluate the place once.
+        let place = match lhs.kind {
+            ir::ExprKind::Local(_) => lhs,
+            _ => {
+                let ptr_type = self.types.pointer(lhs.ty, false);
+                let ptr = self.mono_ctx.ir.make_id();
+                self.local_defs.push(ir::LocalDef {
+                    id: ptr,
+                    typ: ptr_type,
+                });
+                statements.push(ir::Statement::Expression(
+                    self.exprs
+                        .assign(self.exprs.local(ptr, ptr_type), self.exprs.r#ref(lhs)),
+                ));
+                self.exprs.deref(self.exprs.local(ptr, ptr_type))
+            }
+        };
+
+        let temporary = self.mono_ctx.ir.make_id();
+        self.local_defs.push(ir::LocalDef {
+            id: temporary,
+            typ: lhs.ty,
+        });
+        let temporary = self.exprs.local(temporary, lhs.ty);
+        statements.push(ir::Statement::Expression(self.exprs.assign(temporary, rhs)));
+
+        let dropped = self.drop_in_place(place)?.unwrap();
+        statements.push(ir::Statement::Expression(self.exprs.if_then(
+            self.exprs.local(flag, bool_type),
+            dropped,
+            self.exprs.void(void_type, ir::ValueType::RValue),
+        )));
+        statements.push(ir::Statement::Expression(
+            self.exprs.assign(place, temporary),
+        ));
+
+        // Assigning to the whole local (re)initializes it, assigning to a field does not
+        if let ir::ExprKind::Local(_) = lhs.kind {
+            statements.push(ir::Statement::Expression(self.exprs.assign(
+                self.exprs.local(flag, bool_type),
+                self.exprs.lit(Lit::Bool(true), bool_type),
+            )));
+        }
+
+        Ok(self.exprs.block(
+            statements,
+            self.exprs.void(void_type, ir::ValueType::RValue),
+        ))
+    }
+
     fn lower_if(
         &mut self,
         cond_: ast::ExprP<'ast>,
-        then: ast::ExprP<'ast>,
+        then_: ast::ExprP<'ast>,
         els_: ast::ExprP<'ast>,
         type_hint: Option<ir::TyP<'ir>>,
     ) -> Result<ir::ExprP<'ir>, AluminaError> {
         let cond = self.lower_expr(cond_, Some(self.types.builtin(BuiltinType::Bool)))?;
-        let then = self.lower_expr(then, type_hint)?;
+        let then = self.lower_expr(then_, type_hint)?;
+        let unbroken_loops: Vec<_> = self
+            .loop_contexts
+            .iter()
+            .map(|ctx| ctx.loop_result)
+            .filter(|id| !self.local_types.contains_key(id))
+            .collect();
+
         let els = self.lower_expr(
             els_,
             if then.diverges() {
@@ -3203,8 +3643,28 @@ impl<'a, 'ast, 'ir> Monomorphizer<'a, 'ast, 'ir> {
         let then = self.try_coerce(then_typ, then)?;
         let els = self.try_coerce(els_typ, els)?;
 
+        // A missing `else` branch is a void expression, so the other branch cannot produce a
+        // value either. This applies even if the result is not used, e.g. `if c { 1 }` as a
+        // statement.
+        if matches!(els_.kind, ast::ExprKind::Void) && !then.ty.is_void() && !then.ty.is_never() {
+            return Err(CodeErrorKind::IfWithoutElse(
+                self.mono_ctx.type_name(then.ty).unwrap(),
+            ))
+            .with_span(els_.span);
+        }
+
         let gcd = ir::Ty::gcd(then.ty, els.ty);
         if !gcd.assignable_from(then.ty) || !gcd.assignable_from(els.ty) {
+            if !self.tentative {
+                self.mono_ctx
+                    .global_ctx
+                    .diag()
+                    .add_note(CodeError::from_kind(
+                        CodeErrorKind::OtherBranchType(self.mono_ctx.type_name(then.ty).unwrap()),
+                        then_.span,
+                    ));
+            }
+
             return Err(CodeErrorKind::MismatchedBranchTypes(
                 self.mono_ctx.type_name(then.ty).unwrap(),
                 self.mono_ctx.type_name(els.ty).unwrap(),
@@ -3214,15 +3674,131 @@ impl<'a, 'ast, 'ir> Monomorphizer<'a, 'ast, 'ir> {
 
         if let Ok(Value::Bool(v)) = const_eval(cond) {
             if v {
+                // A `break` in the dead branch does not terminate the loop, so e.g. `while true {}`
+                // without any other `break` diverges just like `loop {}` does.
+                for id in unbroken_loops {
+                    self.local_types.remove(&id);
+                }
                 Ok(then)
             } else {
                 Ok(els)
             }
+        } else if !gcd.is_zero_sized()
+            && (matches!(then.kind, ir::ExprKind::Block(..))
+                || matches!(els.kind, ir::ExprKind::Block(..)))
+        {
+            // Branches with statements cannot be operands of the conditional operator, so the
+            // value is assigned to a temporary in each branch instead.
+            let typ = self.mono_ctx.ir.intern_type(gcd);
+            let temporary = self.mono_ctx.ir.make_id();
+            self.local_defs.push(ir::LocalDef { id: temporary, typ });
+            let temporary = self.exprs.local(temporary, typ);
+
+            let assign = |branch: ir::ExprP<'ir>| {
+                if branch.diverges() {
+                    branch
+                } else {
+                    self.exprs.assign(temporary, branch)
+                }
+            };
+
+            Ok(self.exprs.block(
+                [ir::Statement::Expression(self.exprs.if_then(
+                    cond,
+                    assign(then),
+                    assign(els),
+                ))],
+                temporary,
+            ))
         } else {
             Ok(self.exprs.if_then(cond, then, els))
         }
     }
 
+    fn lower_switch(
+        &mut self,
+        value_: ast::ExprP<'ast>,
+        arms_: &[(ast::ExprP<'ast>, ast::ExprP<'ast>)],
+        default_: ast::ExprP<'ast>,
+        type_hint: Option<ir::TyP<'ir>>,
+    ) -> Result<ir::ExprP<'ir>, AluminaError> {
+        let value = self.lower_expr(value_, None)?;
+        if value.diverges() {
+            return Ok(value);
+        }
+
+        let mut cases = HashSet::new();
+        let mut arms = Vec::new();
+        for (case_, arm_) in arms_ {
+            let case = self.lower_expr(case_, Some(value.ty))?;
+            let case = match self
+                .try_coerce(value.ty, case)
+                .append_span(case_.span)?
+                .kind
+            {
+                // Enum values are their member values cast to the enum type, which is the
+                // underlying type in C.
+                ir::ExprKind::Cast(inner) if matches!(value.ty, ir::Ty::NamedType(_)) => inner,
+                _ => case,
+            };
+            let case = ir::const_eval::const_eval(case)
+                .map_err(CodeErrorKind::CannotConstEvaluate)
+                .with_span(case_.span)?;
+
+            let arm = self.lower_expr(arm_, type_hint)?;
+            // Members with the same value are not distinct cases in C, the first one is taken
+            // just like in an `if` chain.
+            if cases.insert(case) {
+                arms.push((case, arm));
+            }
+        }
+
+        let default = self.lower_expr(default_, type_hint)?;
+
+        for (_, arm) in arms.iter_mut() {
+            let arm_typ = self.try_qualify_type(arm.ty)?;
+            *arm = self.try_coerce(arm_typ, arm)?;
+        }
+        let default_typ = self.try_qualify_type(default.ty)?;
+        let default = self.try_coerce(default_typ, default)?;
+
+        let typ = arms
+            .iter()
+            .fold(*default.ty, |acc, (_, arm)| ir::Ty::gcd(&acc, arm.ty));
+        let typ = self.mono_ctx.ir.intern_type(typ);
+
+        if typ.is_zero_sized() {
+            return Ok(self.exprs.switch(value, &arms, default));
+        }
+
+        // The arms are emitted as statements, so the value is assigned to a temporary
+        let temporary = self.mono_ctx.ir.make_id();
+        self.local_defs.push(ir::LocalDef { id: temporary, typ });
+        let temporary = self.exprs.local(temporary, typ);
+
+        let assign = |branch: ir::ExprP<'ir>| {
+            if branch.diverges() {
+                branch
+            } else {
+                self.exprs.assign(temporary, branch)
+            }
+        };
+
+        let arms: Vec<_> = arms
+            .iter()
+            .map(|(case, arm)| (*case, assign(arm)))
+            .collect();
+
+        Ok(self.exprs.block(
+            [ir::Statement::Expression(self.exprs.switch(
+                value,
+                &arms,
+                assign(default),
+            ))],
+            temporary,
+        ))
+    }
+
     fn static_cond_matches(
         &mut self,
         cond: &ast::StaticIfCondition<'ast>,
@@ -3268,11 +3844,17 @@ impl<'a, 'ast, 'ir> Monomorphizer<'a, 'ast, 'ir> {
         els: ast::ExprP<'ast>,
         type_hint: Option<ir::TyP<'ir>>,
     ) -> Result<ir::ExprP<'ir>, AluminaError> {
-        if self.static_cond_matches(cond)? {
-            self.lower_expr(then, type_hint)
+        let result = if self.static_cond_matches(cond)? {
+            self.lower_expr(then, type_hint)?
         } else {
-            self.lower_expr(els, type_hint)
+            self.lower_expr(els, type_hint)?
+        };
+
+        if result.diverges() {
+            self.diverging_whens += 1;
         }
+
+        Ok(result)
     }
 
     fn lower_tuple(
@@ -3293,7 +3875,8 @@ impl<'a, 'ast, 'ir> Monomorphizer<'a, 'ast, 'ir> {
             .map(|(expr, hint)| {
                 let obj = self.lower_expr(expr, hint)?;
                 let obj_type = self.try_qualify_type(obj.ty)?;
-                self.try_coerce(obj_type, obj)
+                let obj = self.try_coerce(obj_type, obj)?;
+                self.consume(obj)
             })
             .collect::<Result<Vec<_>, _>>()?;
 
@@ -3449,6 +4032,7 @@ impl<'a, 'ast, 'ir> Monomorphizer<'a, 'ast, 'ir> {
             type_hint,
             break_label,
             continue_label,
+            drop_depth: self.drop_scopes.len(),
         });
 
         let body = self.lower_expr(body, None);
@@ -3510,17 +4094,21 @@ impl<'a, 'ast, 'ir> Monomorphizer<'a, 'ast, 'ir> {
         };
 
         let expr = expr
-            .map(|expr| self.try_coerce(slot_type, expr))
+            .map(|expr| {
+                let expr = self.try_coerce(slot_type, expr)?;
+                self.consume(expr)
+            })
             .transpose()?
             .unwrap_or_else(|| {
                 self.exprs
                     .void(self.types.builtin(BuiltinType::Void), ir::ValueType::RValue)
             });
 
-        let statements = [ir::Statement::Expression(
+        let mut statements = vec![ir::Statement::Expression(
             self.exprs
                 .assign(self.exprs.local(loop_context.loop_result, slot_type), expr),
         )];
+        statements.extend(self.drop_scopes_from(loop_context.drop_depth)?);
 
         Ok(self
             .exprs
@@ -3531,9 +4119,17 @@ impl<'a, 'ast, 'ir> Monomorphizer<'a, 'ast, 'ir> {
         &mut self,
         _type_hint: Option<ir::TyP<'ir>>,
     ) -> Result<ir::ExprP<'ir>, AluminaError> {
-        let loop_context = self.loop_contexts.last().expect("continue outside of loop");
+        let loop_context = self
+            .loop_contexts
+            .last()
+            .expect("continue outside of loop")
+            .clone();
+
+        let statements = self.drop_scopes_from(loop_context.drop_depth)?;
 
-        Ok(self.exprs.goto(loop_context.continue_label))
+        Ok(self
+            .exprs
+            .block(statements, self.exprs.goto(loop_context.continue_label)))
     }
 
     fn lower_intrinsic(
@@ -3579,6 +4175,15 @@ impl<'a, 'ast, 'ir> Monomorphizer<'a, 'ast, 'ir> {
                 }
             }
             IntrinsicKind::EnumVariants => self.generate_enum_variants(generic_args[0]),
+            IntrinsicKind::OffsetOf => self.generate_offset_of(generic_args[0], args[0]),
+            IntrinsicKind::Uninitialized => {
+                // A local that is not tracked by the uninitialized variable check
+                let typ = generic_args[0];
+                let id = self.mono_ctx.ir.make_id();
+                self.local_defs.push(ir::LocalDef { id, typ });
+
+                Ok(self.exprs.local(id, typ))
+            }
             IntrinsicKind::TypeName => {
                 let typ = generic_args[0];
                 let name = self.mono_ctx.type_name(typ)?;
@@ -3774,7 +4379,9 @@ impl<'a, 'ast, 'ir> Monomorphizer<'a, 'ast, 'ir> {
         let meta_slice_type = self.slice_of(meta_type, true)?;
 
         if self.mono_ctx.test_cases_statics.is_none() {
+            // The statics are attributed to the intrinsic call that needs them.
             let mut child = Self::new(self.mono_ctx, self.tentative, self.current_item);
+            child.exprs.set_span(self.exprs.span());
             child.generate_test_cases_inner()?;
         }
 
@@ -3820,6 +4427,43 @@ impl<'a, 'ast, 'ir> Monomorphizer<'a, 'ast, 'ir> {
         self.array_of(enum_variant_new_func.return_type, exprs)
     }
 
+    fn generate_offset_of(
+        &mut self,
+        typ: ir::TyP<'ir>,
+        field: ir::ExprP<'ir>,
+    ) -> Result<ir::ExprP<'ir>, AluminaError> {
+        let name = match const_eval(field) {
+            Ok(Value::Str(name)) => std::str::from_utf8(name).unwrap(),
+            _ => {
+                return Err(CodeErrorKind::TypeMismatch(
+                    "string literal".to_string(),
+                    self.mono_ctx.type_name(field.ty).unwrap(),
+                ))
+                .with_no_span()
+            }
+        };
+
+        let item = match typ {
+            ir::Ty::NamedType(item) if item.get_struct_like().is_ok() => item,
+            _ => return Err(CodeErrorKind::StructLikeExpectedHere).with_no_span(),
+        };
+
+        let field_map = self.get_struct_field_map(item)?;
+        let field = match field_map.get(name) {
+            Some(field) => field,
+            None => return Err(self.no_field_named(item, name)).with_no_span(),
+        };
+
+        let offset = self
+            .mono_ctx
+            .layouter
+            .field_offset(typ, field.id)
+            .ok_or_else(|| CodeErrorKind::UnknownLayout(self.mono_ctx.type_name(typ).unwrap()))
+            .with_no_span()?;
+
+        Ok(self.exprs.const_value(Value::USize(offset)))
+    }
+
     fn generate_vtable(
         &mut self,
         protocol_types: &'ir [ir::TyP<'ir>],
@@ -4121,7 +4765,8 @@ impl<'a, 'ast, 'ir> Monomorphizer<'a, 'ast, 'ir> {
         }
 
         for (expected, arg) in arg_types.iter().zip(args.iter_mut()) {
-            *arg = self.try_coerce(expected, *arg)?;
+            let coerced = self.try_coerce(expected, *arg)?;
+            *arg = self.consume(coerced)?;
         }
 
         Ok(Some(self.exprs.call(callee, args, return_type)))
@@ -4241,6 +4886,7 @@ impl<'a, 'ast, 'ir> Monomorphizer<'a, 'ast, 'ir> {
 
         let mut varargs = false;
         let mut self_arg = None;
+        let mut raw_c_arrays = false;
 
         let fn_arg_types: Vec<_>;
         let (arg_types, return_type, callee) = match callee.ty {
@@ -4250,6 +4896,7 @@ impl<'a, 'ast, 'ir> Monomorphizer<'a, 'ast, 'ir> {
                 if fun.varargs {
                     varargs = true;
                 }
+                raw_c_arrays = fun.takes_raw_c_arrays();
                 fn_arg_types = fun.args.iter().map(|p| p.ty).collect();
 
                 (&fn_arg_types[..], fun.return_type, callee)
@@ -4302,7 +4949,19 @@ impl<'a, 'ast, 'ir> Monomorphizer<'a, 'ast, 'ir> {
             .collect::<Result<Vec<_>, _>>()?;
 
         for (expected, arg) in arg_types.iter().zip(args.iter_mut()) {
-            *arg = self.try_coerce(expected, *arg)?;
+            let coerced = self.try_coerce(expected, *arg)?;
+            *arg = self.consume(coerced)?;
+
+            // Raw C arrays decay to a pointer to the first element, which is only possible
+            // for lvalues in C89, so other arrays are passed through a temporary.
+            if raw_c_arrays
+                && matches!(arg.ty, ir::Ty::Array(_, _))
+                && arg.value_type != ValueType::LValue
+                && !arg.diverges()
+            {
+                let temporary = self.r#ref(arg);
+                *arg = self.exprs.deref(temporary);
+            }
         }
 
         if callee.diverges() || args.iter().any(|e| e.diverges()) {
@@ -4501,10 +5160,10 @@ impl<'a, 'ast, 'ir> Monomorphizer<'a, 'ast, 'ir> {
         let result = match obj.ty.canonical_type() {
             ir::Ty::NamedType(item) => {
                 let field_map = self.get_struct_field_map(item)?;
-                let field = field_map
-                    .get(field)
-                    .ok_or_else(|| CodeErrorKind::UnresolvedItem(field.to_string()))
-                    .with_no_span()?;
+                let field = match field_map.get(field) {
+                    Some(field) => field,
+                    None => return Err(self.no_field_named(item, field)).with_no_span(),
+                };
 
                 let mut obj = obj;
                 while let ir::Ty::Pointer(_, _) = obj.ty {
@@ -4738,6 +5397,10 @@ impl<'a, 'ast, 'ir> Monomorphizer<'a, 'ast, 'ir> {
             return Ok(inner);
         }
         let inner = self.try_coerce(self.return_type.unwrap(), inner)?;
+        let inner = self.consume(inner)?;
+
+        let drops = self.drop_scopes_from(0)?;
+        let inner = self.with_drops(inner, drops)?;
 
         match self.defer_context.as_ref() {
             None | Some(DeferContext { in_defer: true, .. }) => Ok(self.exprs.ret(inner)),
@@ -4790,6 +5453,7 @@ impl<'a, 'ast, 'ir> Monomorphizer<'a, 'ast, 'ir> {
             self.mono_ctx.global_ctx.diag().add_warning(CodeError {
                 kind: CodeErrorKind::DeferInALoop,
                 backtrace: inner.span.iter().map(|s| Marker::Span(*s)).collect(),
+                notes: vec![],
             })
         }
 
@@ -4829,6 +5493,387 @@ impl<'a, 'ast, 'ir> Monomorphizer<'a, 'ast, 'ir> {
         ))
     }
 
+    fn drop_fn(&mut self, typ: ir::TyP<'ir>) -> Result<Option<ir::IRItemP<'ir>>, AluminaError> {
+        if let Some(drop_fn) = self.mono_ctx.drop_fns.get(typ) {
+            return Ok(*drop_fn);
+        }
+
+        // Only structs can have their own destructor
+        let drop_fn = match typ {
+            ir::Ty::NamedType(item)
+                if matches!(item.get(), Ok(ir::IRItem::StructLike(s)) if !s.is_union)
+                    && self.mono_ctx.ast.lang_item(LangItemKind::Drop).is_ok() =>
+            {
+                let protocol = self.monomorphize_lang_item(LangItemKind::Drop, [typ])?;
+                match self.check_protocol_bound(self.types.protocol(protocol), typ)? {
+                    BoundCheckResult::Matches => {
+                        let ast_type = self.raise_type(typ)?;
+                        let method = self.get_associated_fns(ast_type)?["drop"];
+                        let self_arg = self
+                            .exprs
+                            .void(self.types.pointer(typ, false), ir::ValueType::RValue);
+
+                        Some(self.try_resolve_function(
+                            method,
+                            None,
+                            Some(self_arg),
+                            None,
+                            None,
+                            None,
+                        )?)
+                    }
+                    _ => None,
+                }
+            }
+            _ => None,
+        };
+
+        self.mono_ctx.drop_fns.insert(typ, drop_fn);
+        Ok(drop_fn)
+    }
+
+    fn needs_drop(&mut self, typ: ir::TyP<'ir>) -> Result<bool, AluminaError> {
+        if let Some(needs_drop) = self.mono_ctx.needs_drop.get(typ) {
+            return Ok(*needs_drop);
+        }
+
+        let needs_drop = match typ {
+            ir::Ty::NamedType(item) => match item.get() {
+                Ok(ir::IRItem::StructLike(s)) if !s.is_union => {
+                    let mut needs_drop = self.drop_fn(typ)?.is_some();
+                    for field in s.fields {
+                        needs_drop = needs_drop || self.needs_drop(field.ty)?;
+                    }
+                    needs_drop
+                }
+                Ok(_) => false,
+                // The struct is still being monomorphized, don't cache the result
+                Err(_) => return Ok(false),
+            },
+            ir::Ty::Tuple(elems) => {
+                let mut needs_drop = false;
+                for elem in elems.iter() {
+                    needs_drop = needs_drop || self.needs_drop(elem)?;
+                }
+                needs_drop
+            }
+            ir::Ty::Array(elem, len) => *len > 0 && self.needs_drop(elem)?,
+            _ => false,
+        };
+
+        self.mono_ctx.needs_drop.insert(typ, needs_drop);
+        Ok(needs_drop)
+    }
+
+    // Drop glue: calls the destructor of the value at `place` (if it has one) and then drops its
+    // fields (or elements) in order. Returns `None` if there is nothing to drop.
+    fn drop_in_place(
+        &mut self,
+        place: ir::ExprP<'ir>,
+    ) -> Result<Option<ir::ExprP<'ir>>, AluminaError> {
+        if !self.needs_drop(place.ty)? {
+            return Ok(None);
+        }
+
+        let void_type = self.types.builtin(BuiltinType::Void);
+        let mut statements = Vec::new();
+
+        if let Some(drop_fn) = self.drop_fn(place.ty)? {
+            statements.push(ir::Statement::Expression(self.exprs.call(
+                self.exprs.function(drop_fn),
+                [self.exprs.r#ref(place)],
+                void_type,
+            )));
+        }
+
+        match place.ty {
+            ir::Ty::NamedType(item) => {
+                for field in item.get_struct_like().with_no_span()?.fields {
+                    if let Some(dropped) =
+                        self.drop_in_place(self.exprs.field(place, field.id, field.ty))?
+                    {
+                        statements.push(ir::Statement::Expression(dropped));
+                    }
+                }
+            }
+            ir::Ty::Tuple(elems) => {
+                for (idx, elem) in elems.iter().enumerate() {
+                    if let Some(dropped) =
+                        self.drop_in_place(self.exprs.tuple_index(place, idx, elem))?
+                    {
+                        statements.push(ir::Statement::Expression(dropped));
+                    }
+                }
+            }
+            ir::Ty::Array(_, len) => {
+                let usize_type = self.types.builtin(BuiltinType::USize);
+                let bool_type = self.types.builtin(BuiltinType::Bool);
+
+                let index = self.mono_ctx.ir.make_id();
+                self.local_defs.push(ir::LocalDef {
+                    id: index,
+                    typ: usize_type,
+                });
+                let index = self.exprs.local(index, usize_type);
+                let loop_label = self.mono_ctx.ir.make_id();
+
+                let dropped = self.drop_in_place(self.exprs.index(place, index))?.unwrap();
+
+                statements.extend([
+                    ir::Statement::Expression(
+                        self.exprs
+                            .assign(index, self.exprs.lit(Lit::Int(0), usize_type)),
+                    ),
+                    ir::Statement::Label(loop_label),
+                    ir::Statement::Expression(self.exprs.if_then(
+                        self.exprs.binary(
+                            ast::BinOp::Lt,
+                            index,
+                            self.exprs.lit(Lit::Int(*len as u128), usize_type),
+                            bool_type,
+                        ),
+                        self.exprs.block(
+                            [
+                                ir::Statement::Expression(dropped),
+                                ir::Statement::Expression(self.exprs.assign_op(
+                                    ast::BinOp::Plus,
+                                    index,
+                                    self.exprs.lit(Lit::Int(1), usize_type),
+                                )),
+                            ],
+                            self.exprs.goto(loop_label),
+                        ),
+                        self.exprs.void(void_type, ir::ValueType::RValue),
+                    )),
+                ]);
+            }
+            _ => unreachable!(),
+        }
+
+        Ok(Some(self.exprs.block(
+            statements,
+            self.exprs.void(void_type, ir::ValueType::RValue),
+        )))
+    }
+
+    // Registers a local that needs to be dropped at the end of the current scope. Whether it
+    // still holds a value is tracked at runtime with a flag, since it can be moved out of or
+    // left uninitialized on some paths.
+    fn track_drop(
+        &mut self,
+        id: ir::IrId,
+        typ: ir::TyP<'ir>,
+        initialized: bool,
+    ) -> Result<Option<ir::IrId>, AluminaError> {
+        if self.drop_scopes.is_empty() || !self.needs_drop(typ)? {
+            return Ok(None);
+        }
+
+        let bool_type = self.types.builtin(BuiltinType::Bool);
+        let flag = self.mono_ctx.ir.make_id();
+        self.local_defs.push(ir::LocalDef {
+            id: flag,
+            typ: bool_type,
+        });
+
+        self.drop_prologue
+            .push(ir::Statement::Expression(self.exprs.assign(
+                self.exprs.local(flag, bool_type),
+                self.exprs.lit(Lit::Bool(initialized), bool_type),
+            )));
+
+        self.drop_flags.insert(id, flag);
+        self.drop_scopes.last_mut().unwrap().push(DropLocal {
+            local: id,
+            flag,
+            typ,
+        });
+
+        Ok(Some(flag))
+    }
+
+    fn initialize_local(
+        &mut self,
+        id: ir::IrId,
+        name: Option<&'ast str>,
+        typ: ir::TyP<'ir>,
+        init: ir::ExprP<'ir>,
+    ) -> Result<ir::ExprP<'ir>, AluminaError> {
+        let assign = self.exprs.assign(self.exprs.local(id, typ), init);
+
+        // Synthesized locals (e.g. the temporary in tuple unpacking) are not dropped, the values
+        // are moved out of them instead.
+        let flag = match name {
+            Some(_) => self.track_drop(id, typ, false)?,
+            None => None,
+        };
+
+        let result = match flag {
+            Some(flag) => {
+                let bool_type = self.types.builtin(BuiltinType::Bool);
+                self.exprs.block(
+                    [
+                        ir::Statement::Expression(assign),
+                        ir::Statement::Expression(self.exprs.assign(
+                            self.exprs.local(flag, bool_type),
+                            self.exprs.lit(Lit::Bool(true), bool_type),
+                        )),
+                    ],
+                    self.exprs
+                        .void(self.types.builtin(BuiltinType::Void), ir::ValueType::RValue),
+                )
+            }
+            None => assign,
+        };
+
+        Ok(result)
+    }
+
+    fn drop_flag_of_root(&self, expr: ir::ExprP<'ir>) -> Option<ir::IrId> {
+        match expr.kind {
+            ir::ExprKind::Local(id) => self.drop_flags.get(&id).copied(),
+            ir::ExprKind::Field(inner, _) | ir::ExprKind::TupleIndex(inner, _) => {
+                self.drop_flag_of_root(inner)
+            }
+            ir::ExprKind::Index(inner, _) if matches!(inner.ty, ir::Ty::Array(_, _)) => {
+                self.drop_flag_of_root(inner)
+            }
+            _ => None,
+        }
+    }
+
+    // Called on values that are used by value (passed as an argument, returned, assigned, ...). If
+    // the value is a local that would otherwise be dropped at the end of its scope, it is marked
+    // as moved-from.
+    fn consume(&mut self, expr: ir::ExprP<'ir>) -> Result<ir::ExprP<'ir>, AluminaError> {
+        match expr.kind {
+            ir::ExprKind::Local(id) => {
+                if let Some(flag) = self.drop_flags.get(&id).copied() {
+                    let bool_type = self.types.builtin(BuiltinType::Bool);
+                    return Ok(self.exprs.block(
+                        [ir::Statement::Expression(self.exprs.assign(
+                            self.exprs.local(flag, bool_type),
+                            self.exprs.lit(Lit::Bool(false), bool_type),
+                        ))],
+                        expr,
+                    ));
+                }
+            }
+            ir::ExprKind::Field(..) | ir::ExprKind::TupleIndex(..) | ir::ExprKind::Index(..)
+                if self.drop_flag_of_root(expr).is_some() && self.needs_drop(expr.ty)? =>
+            {
+                return Err(CodeErrorKind::PartialMove).with_span(expr.span);
+            }
+            _ => {}
+        }
+
+        Ok(expr)
+    }
+
+    // Temporaries that are discarded (expression statements) are dropped immediately.
+    fn drop_temporary(&mut self, expr: ir::ExprP<'ir>) -> Result<ir::ExprP<'ir>, AluminaError> {
+        if expr.value_type != ir::ValueType::RValue
+            || expr.diverges()
+            || !self.needs_drop(expr.ty)?
+        {
+            return Ok(expr);
+        }
+
+        let temporary = self.mono_ctx.ir.make_id();
+        self.local_defs.push(ir::LocalDef {
+            id: temporary,
+            typ: expr.ty,
+        });
+
+        let temporary = self.exprs.local(temporary, expr.ty);
+        let dropped = self.drop_in_place(temporary)?.unwrap();
+
+        Ok(self.exprs.block(
+            [
+                ir::Statement::Expression(self.exprs.assign(temporary, expr)),
+                ir::Statement::Expression(dropped),
+            ],
+            self.exprs
+                .void(self.types.builtin(BuiltinType::Void), ir::ValueType::RValue),
+        ))
+    }
+
+    // Drops the locals of all the scopes from `depth` upwards that still hold a value, in reverse
+    // order of declaration. Used at the end of a block and when jumping out of scopes with
+    // `return`, `break` and `continue`.
+    fn drop_scopes_from(&mut self, depth: usize) -> Result<Vec<ir::Statement<'ir>>, AluminaError> {
+        let bool_type = self.types.builtin(BuiltinType::Bool);
+        let void_type = self.types.builtin(BuiltinType::Void);
+
+        let locals: Vec<_> = self.drop_scopes[depth..]
+            .iter()
+            .flatten()
+            .rev()
+            .cloned()
+            .collect();
+
+        let mut statements = Vec::new();
+        for local in locals {
+            let flag = self.exprs.local(local.flag, bool_type);
+            let dropped = self
+                .drop_in_place(self.exprs.local(local.local, local.typ))?
+                .unwrap();
+
+            statements.push(ir::Statement::Expression(
+                self.exprs.if_then(
+                    flag,
+                    self.exprs.block(
+                        [
+                            ir::Statement::Expression(
+                                self.exprs
+                                    .assign(flag, self.exprs.lit(Lit::Bool(false), bool_type)),
+                            ),
+                            ir::Statement::Expression(dropped),
+                        ],
+                        self.exprs.void(void_type, ir::ValueType::RValue),
+                    ),
+                    self.exprs.void(void_type, ir::ValueType::RValue),
+                ),
+            ));
+        }
+
+        Ok(statements)
+    }
+
+    // Evaluates `value` into a temporary before running `drops`, so that the value can still refer
+    // to the locals that are being dropped.
+    fn with_drops(
+        &mut self,
+        value: ir::ExprP<'ir>,
+        drops: Vec<ir::Statement<'ir>>,
+    ) -> Result<ir::ExprP<'ir>, AluminaError> {
+        if drops.is_empty() || value.diverges() {
+            return Ok(value);
+        }
+
+        if value.ty.is_void() {
+            return Ok(self.exprs.block(
+                once(ir::Statement::Expression(value)).chain(drops),
+                self.exprs.void(value.ty, ir::ValueType::RValue),
+            ));
+        }
+
+        let typ = self.try_qualify_type(value.ty)?;
+        let value = self.try_coerce(typ, value)?;
+
+        let temporary = self.mono_ctx.ir.make_id();
+        self.local_defs.push(ir::LocalDef { id: temporary, typ });
+
+        let temporary = self.exprs.local(temporary, typ);
+        Ok(self.exprs.block(
+            once(ir::Statement::Expression(
+                self.exprs.assign(temporary, value),
+            ))
+            .chain(drops),
+            temporary,
+        ))
+    }
+
     fn lower_struct_expression(
         &mut self,
         typ: ast::TyP<'ast>,
@@ -4849,10 +5894,9 @@ impl<'a, 'ast, 'ir> Monomorphizer<'a, 'ast, 'ir> {
                     Some(field) => self
                         .lower_expr(f.value, Some(field.ty))
                         .and_then(|e| self.try_coerce(field.ty, e))
+                        .and_then(|e| self.consume(e))
                         .map(|i| (*field, i)),
-                    None => {
-                        Err(CodeErrorKind::UnresolvedItem(f.name.to_string())).with_span(f.span)
-                    }
+                    None => Err(self.no_field_named(item, f.name)).with_span(f.span),
                 }
             })
             .collect::<Result<Vec<_>, _>>()?;
@@ -4882,6 +5926,7 @@ impl<'a, 'ast, 'ir> Monomorphizer<'a, 'ast, 'ir> {
                 self.mono_ctx.global_ctx.diag().add_warning(CodeError {
                     kind: CodeErrorKind::UninitializedField(u.to_string()),
                     backtrace: span.iter().map(|f| Marker::Span(*f)).collect(),
+                    notes: vec![],
                 });
             }
         }
@@ -4907,7 +5952,8 @@ impl<'a, 'ast, 'ir> Monomorphizer<'a, 'ast, 'ir> {
                 let qualified = self.try_qualify_type(expr.ty)?;
                 first_elem_type = Some(qualified);
             }
-            lowered.push(self.try_coerce(first_elem_type.unwrap(), expr)?);
+            let expr = self.try_coerce(first_elem_type.unwrap(), expr)?;
+            lowered.push(self.consume(expr)?);
         }
 
         if lowered.iter().any(|e| e.diverges()) {
@@ -4944,6 +5990,105 @@ impl<'a, 'ast, 'ir> Monomorphizer<'a, 'ast, 'ir> {
         Ok(result.alloc_on(self.mono_ctx.ir))
     }
 
+    fn lower_inferred_enum_value(
+        &mut self,
+        name: &'ast str,
+        type_hint: Option<ir::TyP<'ir>>,
+    ) -> Result<ir::ExprP<'ir>, AluminaError> {
+        if let Some(ir::Ty::NamedType(item_cell)) = type_hint {
+            if let Ok(ir::IRItem::Enum(item)) = item_cell.get() {
+                if let Some(member) = item.members.iter().find(|m| m.name == name) {
+                    let typ = self.types.named(item_cell);
+                    return Ok(self.exprs.cast(member.value, typ));
+                }
+            }
+        }
+
+        Err(CodeErrorKind::UnresolvedPath(name.to_string())).with_no_span()
+    }
+
+    fn lower_format_placeholder(
+        &mut self,
+        sink: ast::ExprP<'ast>,
+        arg: ast::ExprP<'ast>,
+        index: usize,
+        _type_hint: Option<ir::TyP<'ir>>,
+    ) -> Result<ir::ExprP<'ir>, AluminaError> {
+        let sink = self.lower_expr(sink, None)?;
+        if sink.diverges() {
+            return Ok(sink);
+        }
+
+        let arg = self.lower_expr(arg, None)?;
+        if arg.diverges() {
+            return Ok(arg);
+        }
+
+        let formatter_ty = match sink.ty {
+            ir::Ty::Pointer(inner, false) => *inner,
+            _ => {
+                return Err(CodeErrorKind::TypeMismatch(
+                    "&mut Formatter".to_string(),
+                    self.mono_ctx.type_name(sink.ty).unwrap(),
+                ))
+                .with_no_span()
+            }
+        };
+
+        let arg_ty = match arg.ty {
+            ir::Ty::Pointer(inner, _) => *inner,
+            _ => ice!("format argument is not a pointer"),
+        };
+
+        // Integers are written directly, everything else has to be formattable into the sink.
+        let item =
+            if let ir::Ty::Builtin(builtin) = arg_ty {
+                if builtin.is_integer() {
+                    Some(self.monomorphize_lang_item(
+                        LangItemKind::FormatWriteInt,
+                        [formatter_ty, arg_ty],
+                    )?)
+                } else {
+                    None
+                }
+            } else {
+                None
+            };
+
+        let item = match item {
+            Some(item) => item,
+            None => {
+                let protocol = self.monomorphize_lang_item(
+                    LangItemKind::ProtoFormattable,
+                    [arg_ty, formatter_ty],
+                )?;
+
+                match self.check_protocol_bound(self.types.protocol(protocol), arg_ty)? {
+                    BoundCheckResult::Matches => {}
+                    _ => {
+                        return Err(CodeErrorKind::NotFormattable(
+                            self.mono_ctx.type_name(arg_ty).unwrap(),
+                            index,
+                        ))
+                        .with_no_span()
+                    }
+                }
+
+                self.monomorphize_lang_item(
+                    LangItemKind::FormatWriteFormattable,
+                    [formatter_ty, arg_ty],
+                )?
+            }
+        };
+
+        let func = self.exprs.function(item);
+        Ok(self.exprs.call(
+            func,
+            [sink, arg],
+            item.get_function().with_no_span()?.return_type,
+        ))
+    }
+
     fn lower_defered(
         &mut self,
         spec: &ast::Defered<'ast>,
@@ -4978,6 +6123,18 @@ impl<'a, 'ast, 'ir> Monomorphizer<'a, 'ast, 'ir> {
         expr: ast::ExprP<'ast>,
         type_hint: Option<ir::TyP<'ir>>,
     ) -> Result<ir::ExprP<'ir>, AluminaError> {
+        if self.depth >= self.mono_ctx.global_ctx.max_nesting_depth() {
+            return Err(CodeErrorKind::TooDeeplyNested).with_span(expr.span);
+        }
+        self.depth += 1;
+
+        // All the IR expressions created while lowering this one (including the synthesized ones)
+        // are attributed to it, unless a more specific span is available.
+        let outer_span = self.exprs.span();
+        if expr.span.is_some() {
+            self.exprs.set_span(expr.span);
+        }
+
         let result = match &expr.kind {
             ast::ExprKind::Void => Ok(self
                 .exprs
@@ -4990,6 +6147,9 @@ impl<'a, 'ast, 'ir> Monomorphizer<'a, 'ast, 'ir> {
             ast::ExprKind::Unary(op, inner) => self.lower_unary(*op, inner, type_hint),
             ast::ExprKind::Assign(lhs, rhs) => self.lower_assign(lhs, rhs, type_hint),
             ast::ExprKind::If(cond, then, els) => self.lower_if(cond, then, els, type_hint),
+            ast::ExprKind::Switch(value, arms, default) => {
+                self.lower_switch(value, arms, default, type_hint)
+            }
             ast::ExprKind::Cast(expr, typ) => self.lower_cast(expr, typ, type_hint),
             ast::ExprKind::Loop(body) => self.lower_loop(body, type_hint),
             ast::ExprKind::Binary(op, lhs, rhs) => self.lower_binary(*op, lhs, rhs, type_hint),
@@ -5003,6 +6163,12 @@ impl<'a, 'ast, 'ir> Monomorphizer<'a, 'ast, 'ir> {
             ast::ExprKind::Call(func, args) => self.lower_call(func, args, type_hint),
             ast::ExprKind::Array(elements) => self.lower_array_expression(elements, type_hint),
             ast::ExprKind::EnumValue(typ, id) => self.lower_enum_value(typ, *id, type_hint),
+            ast::ExprKind::InferredEnumValue(name) => {
+                self.lower_inferred_enum_value(name, type_hint)
+            }
+            ast::ExprKind::FormatPlaceholder(sink, arg, index) => {
+                self.lower_format_placeholder(sink, arg, *index, type_hint)
+            }
             ast::ExprKind::Struct(func, initializers) => {
                 self.lower_struct_expression(func, initializers, type_hint, expr.span)
             }
@@ -5026,6 +6192,9 @@ impl<'a, 'ast, 'ir> Monomorphizer<'a, 'ast, 'ir> {
             ast::ExprKind::DeferedMacro(_, _) => ice!("macros should have been expanded by now"),
         };
 
+        self.exprs.set_span(outer_span);
+        self.depth -= 1;
+
         result.append_span(expr.span)
     }
 }
@@ -0,0 +1,419 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::ast::Span;
+use crate::common::{AluminaError, CodeError, CodeErrorKind};
+
+use super::{ExprKind, ExprP, FuncBody, IRItem, IrId, Statement, Ty, TyP};
+
+/// Part of a local that is initialized separately. Fields of structs and elements of tuples
+/// are tracked on their own, everything else (including unions and arrays) as a whole.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Slot {
+    Whole,
+    Field(IrId),
+    Element(usize),
+}
+
+/// Parts of locals that may not have been assigned yet on the current path. `None` means that
+/// the current position is unreachable.
+type State = Option<HashSet<(IrId, Slot)>>;
+
+fn join(lhs: State, rhs: State) -> State {
+    match (lhs, rhs) {
+        (None, other) | (other, None) => other,
+        (Some(mut lhs), Some(rhs)) => {
+            lhs.extend(rhs);
+            Some(lhs)
+        }
+    }
+}
+
+fn slots_of(ty: TyP<'_>) -> Vec<Slot> {
+    let slots = match ty {
+        Ty::NamedType(item) => match item.get() {
+            Ok(IRItem::StructLike(s)) if !s.is_union => s
+                .fields
+                .iter()
+                .filter(|f| !f.ty.is_zero_sized())
+                .map(|f| Slot::Field(f.id))
+                .collect(),
+            _ => vec![],
+        },
+        Ty::Tuple(elems) => elems
+            .iter()
+            .enumerate()
+            .filter(|(_, e)| !e.is_zero_sized())
+            .map(|(idx, _)| Slot::Element(idx))
+            .collect(),
+        _ => vec![],
+    };
+
+    if slots.is_empty() {
+        vec![Slot::Whole]
+    } else {
+        slots
+    }
+}
+
+/// The part of a local that is initialized by assigning to the place. Writing to a member of
+/// a union initializes the whole union, so it is looked through.
+fn written_slot(expr: ExprP<'_>) -> Option<(IrId, Slot)> {
+    match expr.kind {
+        ExprKind::Local(id) => Some((id, Slot::Whole)),
+        ExprKind::Field(inner, field) => match inner.kind {
+            ExprKind::Local(id) => Some((id, slot_for_field(inner.ty, Slot::Field(field)))),
+            _ if is_union(inner.ty) => written_slot(inner),
+            _ => None,
+        },
+        ExprKind::TupleIndex(inner, idx) => match inner.kind {
+            ExprKind::Local(id) => Some((id, Slot::Element(idx))),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn is_union(ty: TyP<'_>) -> bool {
+    match ty {
+        Ty::NamedType(item) => matches!(item.get(), Ok(IRItem::StructLike(s)) if s.is_union),
+        _ => false,
+    }
+}
+
+fn slot_for_field(ty: TyP<'_>, slot: Slot) -> Slot {
+    if is_union(ty) {
+        Slot::Whole
+    } else {
+        slot
+    }
+}
+
+fn assigned_in(expr: ExprP<'_>, id: IrId) -> bool {
+    let assigned_in_stmts = |stmts: &[Statement<'_>]| {
+        stmts.iter().any(|stmt| match stmt {
+            Statement::Expression(expr) => assigned_in(expr, id),
+            Statement::Label(_) => false,
+        })
+    };
+
+    match expr.kind {
+        ExprKind::Assign(lhs, rhs) => {
+            written_slot(lhs).is_some_and(|(local, _)| local == id) || assigned_in(rhs, id)
+        }
+        ExprKind::Ref(inner) => place_local(inner) == Some(id),
+        ExprKind::Block(stmts, ret) => assigned_in_stmts(stmts) || assigned_in(ret, id),
+        ExprKind::If(cond, then, els) => {
+            assigned_in(cond, id) || assigned_in(then, id) || assigned_in(els, id)
+        }
+        ExprKind::Switch(value, arms, default) => {
+            assigned_in(value, id)
+                || arms.iter().any(|(_, arm)| assigned_in(arm, id))
+                || assigned_in(default, id)
+        }
+        ExprKind::Call(callee, args) => {
+            assigned_in(callee, id)
+                || args.iter().any(|a| {
+                    assigned_in(a, id)
+                        || (is_extern(callee)
+                            && matches!(a.ty, Ty::Array(_, _))
+                            && place_local(a) == Some(id))
+                })
+        }
+        ExprKind::Binary(_, lhs, rhs)
+        | ExprKind::AssignOp(_, lhs, rhs)
+        | ExprKind::Index(lhs, rhs) => assigned_in(lhs, id) || assigned_in(rhs, id),
+        ExprKind::Deref(inner)
+        | ExprKind::Return(inner)
+        | ExprKind::Unary(_, inner)
+        | ExprKind::Field(inner, _)
+        | ExprKind::TupleIndex(inner, _)
+        | ExprKind::Cast(inner) => assigned_in(inner, id),
+        _ => false,
+    }
+}
+
+fn is_extern(callee: ExprP<'_>) -> bool {
+    match callee.ty {
+        Ty::NamedFunction(item) => item.get_function().is_ok_and(|f| f.is_extern),
+        _ => false,
+    }
+}
+
+/// The local that a place is a part of, if any.
+fn place_local(expr: ExprP<'_>) -> Option<IrId> {
+    match expr.kind {
+        ExprKind::Local(local) => Some(local),
+        ExprKind::Field(inner, _) | ExprKind::TupleIndex(inner, _) => place_local(inner),
+        ExprKind::Index(inner, _) if matches!(inner.ty, Ty::Array(_, _)) => place_local(inner),
+        _ => None,
+    }
+}
+
+// Forward dataflow pass that checks that the locals declared without an initializer
+// (`let x: T;`) are definitely assigned on every path before they are used.
+//
+// Since the control flow is lowered to labels and gotos at this point, the state at each
+// label is the union of the states at all the gotos that jump to it, which is iterated
+// until it reaches a fixpoint (loops have back-edges). Fields of structs and elements of
+// tuples are tracked separately, so a local can be initialized one field at a time. Taking
+// the address of a local or any of its fields counts as initializing all of it, so that it
+// can be passed as an out-parameter. Values that are deliberately left uninitialized should
+// come from `mem::uninitialized` instead.
+pub struct UninitChecker<'a> {
+    tracked: &'a HashMap<IrId, &'a str>,
+    initial: HashSet<(IrId, Slot)>,
+    labels: HashMap<IrId, HashSet<(IrId, Slot)>>,
+    skipping_branches: HashMap<IrId, Option<Span>>,
+    reported: HashSet<IrId>,
+    errors: Vec<CodeError>,
+    reporting: bool,
+}
+
+impl<'a> UninitChecker<'a> {
+    pub fn new<'ir>(tracked: &'a HashMap<IrId, &'a str>, types: &HashMap<IrId, TyP<'ir>>) -> Self {
+        let initial = tracked
+            .keys()
+            .flat_map(|id| {
+                let slots = types.get(id).map_or(vec![Slot::Whole], |ty| slots_of(ty));
+                slots.into_iter().map(move |slot| (*id, slot))
+            })
+            .collect();
+
+        Self {
+            tracked,
+            initial,
+            labels: HashMap::new(),
+            skipping_branches: HashMap::new(),
+            reported: HashSet::new(),
+            errors: Vec::new(),
+            reporting: false,
+        }
+    }
+
+    pub fn check(mut self, body: &FuncBody<'_>) -> Result<(), AluminaError> {
+        loop {
+            let previous = self.labels.clone();
+            self.visit_body(body);
+
+            if previous == self.labels {
+                break;
+            }
+        }
+
+        self.reporting = true;
+        self.visit_body(body);
+
+        if self.errors.is_empty() {
+            Ok(())
+        } else {
+            Err(AluminaError::CodeErrors(self.errors))
+        }
+    }
+
+    fn visit_body(&mut self, body: &FuncBody<'_>) {
+        let mut state = Some(self.initial.clone());
+        for stmt in body.statements {
+            self.visit_stmt(stmt, &mut state);
+        }
+    }
+
+    fn visit_stmt(&mut self, stmt: &Statement<'_>, state: &mut State) {
+        match stmt {
+            Statement::Expression(expr) => self.visit_expr(expr, state),
+            Statement::Label(id) => {
+                let incoming = self.labels.get(id).cloned();
+                *state = join(state.take(), incoming);
+            }
+        }
+    }
+
+    fn report(&mut self, id: IrId, span: Option<Span>) {
+        if !self.reporting || !self.reported.insert(id) {
+            return;
+        }
+
+        let name = self.tracked[&id].to_string();
+        let mut error =
+            CodeError::from_kind(CodeErrorKind::PossiblyUninitialized(name.clone()), span);
+
+        if let Some(branch) = self.skipping_branches.get(&id).copied().flatten() {
+            error = error.with_note(CodeErrorKind::NotAssignedOnBranch(name), Some(branch));
+        }
+
+        self.errors.push(error);
+    }
+
+    fn initialize(&mut self, id: IrId, slot: Slot, state: &mut State) {
+        if let Some(state) = state {
+            match slot {
+                Slot::Whole => state.retain(|(local, _)| *local != id),
+                _ => {
+                    state.remove(&(id, slot));
+                }
+            }
+        }
+    }
+
+    fn is_uninitialized(state: &State, id: IrId, slot: Option<Slot>) -> bool {
+        state.iter().flatten().any(|(local, s)| {
+            *local == id && slot.is_none_or(|slot| *s == slot || *s == Slot::Whole)
+        })
+    }
+
+    // Visits the operands of a place that is being written to or having its address taken.
+    // The local at the base of the place is not used by this, but indices into arrays and
+    // pointers that are dereferenced are.
+    fn visit_place_operands(&mut self, expr: ExprP<'_>, state: &mut State) {
+        match expr.kind {
+            ExprKind::Local(_) => {}
+            ExprKind::Field(inner, _) | ExprKind::TupleIndex(inner, _) => {
+                self.visit_place_operands(inner, state)
+            }
+            ExprKind::Index(inner, index) if matches!(inner.ty, Ty::Array(_, _)) => {
+                self.visit_expr(index, state);
+                self.visit_place_operands(inner, state);
+            }
+            _ => self.visit_expr(expr, state),
+        }
+    }
+
+    fn visit_expr(&mut self, expr: ExprP<'_>, state: &mut State) {
+        if state.is_none() {
+            return;
+        }
+
+        match expr.kind {
+            ExprKind::Local(id) => {
+                if Self::is_uninitialized(state, id, None) {
+                    self.report(id, expr.span);
+                }
+            }
+            ExprKind::Field(inner, field) if !is_union(inner.ty) => match inner.kind {
+                ExprKind::Local(id) if self.tracked.contains_key(&id) => {
+                    if Self::is_uninitialized(state, id, Some(Slot::Field(field))) {
+                        self.report(id, expr.span);
+                    }
+                }
+                _ => self.visit_expr(inner, state),
+            },
+            ExprKind::TupleIndex(inner, idx) => match inner.kind {
+                ExprKind::Local(id) if self.tracked.contains_key(&id) => {
+                    if Self::is_uninitialized(state, id, Some(Slot::Element(idx))) {
+                        self.report(id, expr.span);
+                    }
+                }
+                _ => self.visit_expr(inner, state),
+            },
+            ExprKind::Assign(lhs, rhs) => {
+                self.visit_expr(rhs, state);
+                self.visit_place_operands(lhs, state);
+                if let Some((id, slot)) = written_slot(lhs) {
+                    self.initialize(id, slot, state);
+                }
+            }
+            ExprKind::Ref(inner) => {
+                self.visit_place_operands(inner, state);
+                if let Some(id) = place_local(inner) {
+                    self.initialize(id, Slot::Whole, state);
+                }
+            }
+            ExprKind::Block(stmts, ret) => {
+                for stmt in stmts {
+                    self.visit_stmt(stmt, state);
+                }
+                self.visit_expr(ret, state);
+            }
+            ExprKind::If(cond, then, els) => {
+                self.visit_expr(cond, state);
+
+                let pending: HashSet<IrId> = state.iter().flatten().map(|(id, _)| *id).collect();
+                for id in pending {
+                    match (assigned_in(then, id), assigned_in(els, id)) {
+                        (true, false) => {
+                            self.skipping_branches
+                                .entry(id)
+                                .or_insert(els.span.or(expr.span));
+                        }
+                        (false, true) => {
+                            self.skipping_branches
+                                .entry(id)
+                                .or_insert(then.span.or(expr.span));
+                        }
+                        _ => {}
+                    }
+                }
+
+                let mut then_state = state.clone();
+                self.visit_expr(then, &mut then_state);
+                self.visit_expr(els, state);
+
+                *state = join(then_state, state.take());
+            }
+            ExprKind::Switch(value, arms, default) => {
+                self.visit_expr(value, state);
+
+                let branches: Vec<_> = arms.iter().map(|(_, arm)| *arm).chain([default]).collect();
+                let pending: HashSet<IrId> = state.iter().flatten().map(|(id, _)| *id).collect();
+                for id in pending {
+                    let assigned = branches.iter().filter(|b| assigned_in(b, id)).count();
+                    if assigned > 0 && assigned < branches.len() {
+                        let skipping = branches.iter().find(|b| !assigned_in(b, id)).unwrap();
+                        self.skipping_branches
+                            .entry(id)
+                            .or_insert(skipping.span.or(expr.span));
+                    }
+                }
+
+                let incoming = state.take();
+                for branch in branches {
+                    let mut branch_state = incoming.clone();
+                    self.visit_expr(branch, &mut branch_state);
+                    *state = join(branch_state, state.take());
+                }
+            }
+            ExprKind::Goto(label) => {
+                let incoming = self.labels.entry(label).or_default();
+                incoming.extend(state.take().unwrap());
+            }
+            ExprKind::Return(inner) => {
+                self.visit_expr(inner, state);
+                *state = None;
+            }
+            ExprKind::Call(callee, args) => {
+                self.visit_expr(callee, state);
+                for arg in args {
+                    match place_local(arg) {
+                        // Array arguments of extern functions are raw C arrays, so the function
+                        // gets a pointer to the elements, just like with a reference.
+                        Some(id) if is_extern(callee) && matches!(arg.ty, Ty::Array(_, _)) => {
+                            self.visit_place_operands(arg, state);
+                            self.initialize(id, Slot::Whole, state);
+                        }
+                        _ => self.visit_expr(arg, state),
+                    }
+                }
+            }
+            ExprKind::Binary(_, lhs, rhs)
+            | ExprKind::AssignOp(_, lhs, rhs)
+            | ExprKind::Index(lhs, rhs) => {
+                self.visit_expr(lhs, state);
+                self.visit_expr(rhs, state);
+            }
+            ExprKind::Deref(inner)
+            | ExprKind::Unary(_, inner)
+            | ExprKind::Field(inner, _)
+            | ExprKind::Cast(inner) => self.visit_expr(inner, state),
+            ExprKind::Fn(_)
+            | ExprKind::Static(_)
+            | ExprKind::Lit(_)
+            | ExprKind::ConstValue(_)
+            | ExprKind::CodegenIntrinsic(_)
+            | ExprKind::Unreachable
+            | ExprKind::Void => {}
+        }
+
+        if expr.diverges() {
+            *state = None;
+        }
+    }
+}
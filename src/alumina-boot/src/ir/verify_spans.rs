@@ -0,0 +1,146 @@
+use crate::{
+    ast::Span,
+    common::{AluminaError, CodeError, CodeErrorBuilder, CodeErrorKind},
+};
+
+use super::{ExprKind, ExprP, IRItem, IRItemP, Statement};
+
+// Checks that every expression that reaches codegen can be attributed to a location in the
+// source (`-Z verify-spans`). The expressions that the compiler synthesizes are expected to
+// carry the span of the construct they were synthesized for, so this points at the closest
+// enclosing expression that has one to help find where the span was lost.
+pub struct SpanVerifier {
+    errors: Vec<CodeError>,
+}
+
+impl SpanVerifier {
+    pub fn new() -> Self {
+        Self { errors: Vec::new() }
+    }
+
+    pub fn verify_item(&mut self, item: IRItemP<'_>) -> Result<(), AluminaError> {
+        let (name, missing) = match item.get().with_no_span()? {
+            IRItem::Function(f) => (
+                f.name,
+                f.body.get().and_then(|body| {
+                    body.statements
+                        .iter()
+                        .find_map(|stmt| missing_in_stmt(stmt, None))
+                }),
+            ),
+            IRItem::Static(s) => (s.name, s.init.and_then(|init| missing_span(init, None))),
+            _ => return Ok(()),
+        };
+
+        // Only the first one in each item is reported, the rest are usually the same problem.
+        if let Some((expr, enclosing)) = missing {
+            self.errors.push(CodeError::from_kind(
+                CodeErrorKind::ExpressionWithoutSpan(
+                    kind_name(&expr.kind).to_string(),
+                    name.unwrap_or("<anonymous>").to_string(),
+                ),
+                enclosing,
+            ));
+        }
+
+        Ok(())
+    }
+
+    pub fn finish(self) -> Result<(), AluminaError> {
+        if self.errors.is_empty() {
+            Ok(())
+        } else {
+            Err(AluminaError::CodeErrors(self.errors))
+        }
+    }
+}
+
+fn kind_name(kind: &ExprKind<'_>) -> &'static str {
+    match kind {
+        ExprKind::Block(_, _) => "Block",
+        ExprKind::Binary(_, _, _) => "Binary",
+        ExprKind::AssignOp(_, _, _) => "AssignOp",
+        ExprKind::Call(_, _) => "Call",
+        ExprKind::Fn(_) => "Fn",
+        ExprKind::Ref(_) => "Ref",
+        ExprKind::Deref(_) => "Deref",
+        ExprKind::Return(_) => "Return",
+        ExprKind::Goto(_) => "Goto",
+        ExprKind::Unary(_, _) => "Unary",
+        ExprKind::Assign(_, _) => "Assign",
+        ExprKind::Index(_, _) => "Index",
+        ExprKind::Local(_) => "Local",
+        ExprKind::Static(_) => "Static",
+        ExprKind::Lit(_) => "Lit",
+        ExprKind::ConstValue(_) => "ConstValue",
+        ExprKind::Field(_, _) => "Field",
+        ExprKind::TupleIndex(_, _) => "TupleIndex",
+        ExprKind::If(_, _, _) => "If",
+        ExprKind::Switch(_, _, _) => "Switch",
+        ExprKind::Cast(_) => "Cast",
+        ExprKind::CodegenIntrinsic(_) => "CodegenIntrinsic",
+        ExprKind::Unreachable => "Unreachable",
+        ExprKind::Void => "Void",
+    }
+}
+
+fn missing_in_stmt<'ir>(
+    stmt: &Statement<'ir>,
+    enclosing: Option<Span>,
+) -> Option<(ExprP<'ir>, Option<Span>)> {
+    match stmt {
+        Statement::Expression(expr) => missing_span(expr, enclosing),
+        Statement::Label(_) => None,
+    }
+}
+
+/// The first expression without a span and the span of the closest enclosing expression.
+fn missing_span<'ir>(
+    expr: ExprP<'ir>,
+    enclosing: Option<Span>,
+) -> Option<(ExprP<'ir>, Option<Span>)> {
+    let enclosing = match expr.span {
+        Some(span) => Some(span),
+        None => return Some((expr, enclosing)),
+    };
+
+    match expr.kind {
+        ExprKind::Block(stmts, ret) => stmts
+            .iter()
+            .find_map(|stmt| missing_in_stmt(stmt, enclosing))
+            .or_else(|| missing_span(ret, enclosing)),
+        ExprKind::Binary(_, a, b)
+        | ExprKind::AssignOp(_, a, b)
+        | ExprKind::Assign(a, b)
+        | ExprKind::Index(a, b) => {
+            missing_span(a, enclosing).or_else(|| missing_span(b, enclosing))
+        }
+        ExprKind::If(cond, then, els) => missing_span(cond, enclosing)
+            .or_else(|| missing_span(then, enclosing))
+            .or_else(|| missing_span(els, enclosing)),
+        ExprKind::Switch(value, arms, default) => missing_span(value, enclosing)
+            .or_else(|| {
+                arms.iter()
+                    .find_map(|(_, arm)| missing_span(arm, enclosing))
+            })
+            .or_else(|| missing_span(default, enclosing)),
+        ExprKind::Call(callee, args) => missing_span(callee, enclosing)
+            .or_else(|| args.iter().find_map(|arg| missing_span(arg, enclosing))),
+        ExprKind::Ref(inner)
+        | ExprKind::Deref(inner)
+        | ExprKind::Return(inner)
+        | ExprKind::Unary(_, inner)
+        | ExprKind::Field(inner, _)
+        | ExprKind::TupleIndex(inner, _)
+        | ExprKind::Cast(inner) => missing_span(inner, enclosing),
+        ExprKind::Fn(_)
+        | ExprKind::Static(_)
+        | ExprKind::Local(_)
+        | ExprKind::Lit(_)
+        | ExprKind::ConstValue(_)
+        | ExprKind::CodegenIntrinsic(_)
+        | ExprKind::Goto(_)
+        | ExprKind::Unreachable
+        | ExprKind::Void => None,
+    }
+}
@@ -0,0 +1,16 @@
+#![allow(clippy::single_match)]
+#![allow(clippy::mutable_key_type)]
+
+mod ast;
+mod codegen;
+pub mod common;
+pub mod compiler;
+pub mod diagnostics;
+pub mod global_ctx;
+mod incremental;
+mod intrinsics;
+mod ir;
+mod name_resolution;
+mod parser;
+mod utils;
+mod visitors;
@@ -1,33 +1,12 @@
-#![allow(clippy::single_match)]
-#![allow(clippy::mutable_key_type)]
-
-mod ast;
-mod codegen;
-mod common;
-mod compiler;
-mod diagnostics;
-mod global_ctx;
-mod intrinsics;
-mod ir;
-mod name_resolution;
-mod parser;
-mod utils;
-mod visitors;
-
+use alumina_boot::common::DEFAULT_STACK_SIZE;
+use alumina_boot::compiler::{CodegenDialect, Compiler, CompilerOptions, OptionalWarning};
 use clap::Parser;
-use common::AluminaError;
-use common::CodeError;
-use compiler::Compiler;
-use compiler::SourceFile;
 
-use global_ctx::GlobalCtx;
-use global_ctx::OutputType;
+use alumina_boot::global_ctx::OutputType;
 
 use std::error::Error;
 
 use std::path::PathBuf;
-use std::time::Instant;
-use walkdir::WalkDir;
 
 /// Parse a single key-value pair
 fn parse_key_val<T, U>(s: &str) -> Result<(T, U), Box<dyn Error + Send + Sync + 'static>>
@@ -83,6 +62,19 @@ struct Args {
     #[clap(long)]
     timings: bool,
 
+    /// Number of threads to use for parsing (defaults to the number of CPUs)
+    #[clap(long, short)]
+    jobs: Option<usize>,
+
+    /// Directory for caching the declarations collected from source files between runs
+    /// (files are still parsed, only the declaration collection is skipped)
+    #[clap(long)]
+    incremental: Option<PathBuf>,
+
+    /// Stack size of the compiler thread in MiB
+    #[clap(long, default_value_t = DEFAULT_STACK_SIZE)]
+    stack_size: usize,
+
     /// Whether a library should be output
     #[clap(long)]
     library: bool,
@@ -94,115 +86,65 @@ struct Args {
     /// Unstable compiler options
     #[clap(long, short('Z'), multiple_occurrences(true))]
     options: Vec<String>,
-}
 
-fn get_sysroot(args: &Args) -> Result<Vec<SourceFile>, AluminaError> {
-    let mut result = Vec::new();
-
-    for maybe_entry in WalkDir::new(args.sysroot.clone())
-        .follow_links(true)
-        .into_iter()
-    {
-        use std::fmt::Write;
-        let entry = maybe_entry?;
-        if entry.file_type().is_dir() {
-            continue;
-        }
-
-        let filename = entry.file_name().to_string_lossy();
-        if !filename.ends_with(".alu") {
-            continue;
-        }
+    /// Fold constant expressions and remove branches with constant conditions
+    #[clap(long)]
+    opt: bool,
 
-        let path_segments: Vec<_> = entry
-            .path()
-            .strip_prefix(args.sysroot.clone())
-            .unwrap()
-            .iter()
-            .map(|s| s.to_string_lossy())
-            .collect();
-
-        let mut module_path = String::new();
-        for (index, segment) in path_segments.iter().enumerate() {
-            if index < path_segments.len() - 1 {
-                write!(module_path, "::{}", segment).unwrap();
-            } else {
-                let module_name = segment.strip_suffix(".alu").unwrap();
-                if module_name != "__root__" {
-                    write!(module_path, "::{}", module_name).unwrap();
-                }
-            }
-        }
-        if module_path.is_empty() {
-            module_path.push_str("::");
-        }
-        result.push(SourceFile {
-            filename: entry.into_path(),
-            path: module_path,
-        });
-    }
+    /// Flavor of C to emit (c11 or c89)
+    #[clap(long, parse(try_from_str), default_value = "c11")]
+    codegen_dialect: CodegenDialect,
 
-    Ok(result)
+    /// Enable warnings that are off by default (dead-code)
+    #[clap(long, parse(try_from_str), multiple_occurrences(true))]
+    warn: Vec<OptionalWarning>,
 }
 
 fn main() {
-    let start_time = Instant::now();
     let args = Args::parse();
 
+    std::process::exit(run(args));
+}
+
+fn run(args: Args) -> i32 {
     let output_type = if args.library {
         OutputType::Library
     } else {
         OutputType::Executable
     };
 
-    let mut global_ctx = GlobalCtx::new(output_type, args.options.clone());
-    let mut compiler = Compiler::new(global_ctx.clone());
-
-    let mut files = get_sysroot(&args).unwrap();
-    for (path, filename) in &args.modules {
-        files.push(SourceFile {
-            filename: filename.clone(),
-            path: path.clone(),
-        });
+    let mut compiler = Compiler::new(CompilerOptions {
+        output_type,
+        cfg: args.cfg,
+        debug: args.debug,
+        options: args.options,
+        jobs: args.jobs,
+        incremental: args.incremental,
+        timings: args.timings,
+        warnings: args.warn,
+        optimize: args.opt,
+        dialect: args.codegen_dialect,
+        stack_size: args.stack_size,
+    });
+
+    compiler.add_sysroot(&args.sysroot).unwrap();
+    for (path, filename) in args.modules {
+        compiler.add_file(path, filename);
     }
 
-    for (key, value) in args.cfg {
-        if let Some(value) = value {
-            global_ctx.add_cfg(key, value)
-        } else {
-            global_ctx.add_flag(key)
-        }
-    }
-
-    if args.debug {
-        global_ctx.add_flag("debug");
-    }
+    let result = compiler.compile();
+    compiler.print_diagnostics();
 
-    match compiler.compile(files, start_time) {
-        Ok(program) => {
-            let diag_ctx = global_ctx.diag();
-            if args.timings {
-                for (stage, duration) in compiler.timings() {
-                    diag_ctx.add_note(CodeError::freeform(format!(
-                        "compiler timings: stage {:?} took {}ms",
-                        stage,
-                        duration.as_millis()
-                    )));
-                }
-            }
-            diag_ctx.print_error_report().unwrap();
+    match result {
+        Ok(output) => {
             match args.output {
-                Some(filename) => std::fs::write(filename, program).unwrap(),
+                Some(filename) => std::fs::write(filename, output.program).unwrap(),
                 None => {
-                    print!("{}", program);
+                    print!("{}", output.program);
                 }
             }
+            0
         }
-        Err(e) => {
-            let diag_ctx = global_ctx.diag();
-            diag_ctx.add_from_error(e).unwrap();
-            diag_ctx.print_error_report().unwrap();
-            std::process::exit(1);
-        }
+        Err(_) => 1,
     }
 }
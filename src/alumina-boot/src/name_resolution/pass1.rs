@@ -1,511 +1,864 @@
-use crate::common::{AluminaError, ArenaAllocatable, CodeErrorKind, WithSpanDuringParsing};
+use crate::common::{
+    AluminaError, ArenaAllocatable, CodeError, CodeErrorKind, WithSpanDuringParsing,
+};
 
 use crate::ast::{AstCtx, Attribute, ItemP};
-use crate::global_ctx::GlobalCtx;
+use crate::global_ctx::{CfgSet, GlobalCtx};
 use crate::name_resolution::scope::{NamedItemKind, Scope, ScopeType};
-use crate::parser::{AluminaVisitor, ParseCtx};
+use crate::parser::{AluminaVisitor, NodeExt, ParseCtx};
 
 use std::result::Result;
 use tree_sitter::Node;
 
-use crate::visitors::{AttributeVisitor, UseClauseVisitor, VisitorExt};
+use crate::incremental::{Decoder, Encoder};
+use crate::visitors::{AttributeVisitor, ParsedAttributes, UseClauseVisitor};
 
 use super::path::Path;
 use super::scope::NamedItem;
 
-pub struct FirstPassVisitor<'ast, 'src> {
-    global_ctx: GlobalCtx,
-    ast: &'ast AstCtx<'ast>,
-    scope: Scope<'ast, 'src>,
-    code: &'src ParseCtx<'src>,
-    enum_item: Option<ItemP<'ast>>,
-
-    in_a_container: bool,
-    main_module_path: Option<Path<'ast>>,
-    main_candidate: Option<ItemP<'ast>>,
+/// Location of a syntax node that, unlike `tree_sitter::Node`, can be sent between threads.
+/// It is resolved back to a node of the same parse tree when the declaration is registered.
+#[derive(Debug, Clone)]
+pub struct NodeRef {
+    /// Index of the child to descend into at each level, starting from the root
+    path: Vec<u32>,
 }
 
-impl<'ast, 'src> FirstPassVisitor<'ast, 'src> {
-    pub fn new(global_ctx: GlobalCtx, ast: &'ast AstCtx<'ast>, scope: Scope<'ast, 'src>) -> Self {
-        Self {
-            global_ctx,
-            ast,
-            code: scope
-                .code()
-                .expect("cannot run on scope without parse context"),
-            scope,
-            in_a_container: false,
-            enum_item: None,
-            main_module_path: None,
-            main_candidate: None,
+impl NodeRef {
+    /// Finds the path of a node by walking up to the root. This looks at all the siblings on
+    /// each level, so the declaration collector only uses it for the node it starts at and
+    /// keeps track of the path of the nodes below it as it descends.
+    fn new(node: Node<'_>) -> Self {
+        let mut path = Vec::new();
+        let mut node = node;
+        while let Some(parent) = node.parent() {
+            let mut cursor = parent.walk();
+            let index = parent
+                .children(&mut cursor)
+                .position(|child| child.id() == node.id())
+                .unwrap();
+
+            path.push(index as u32);
+            node = parent;
         }
+        path.reverse();
+
+        Self { path }
     }
 
-    pub fn with_main(
-        global_ctx: GlobalCtx,
-        ast: &'ast AstCtx<'ast>,
-        scope: Scope<'ast, 'src>,
-    ) -> Self {
-        Self {
-            global_ctx,
-            ast,
-            code: scope
-                .code()
-                .expect("cannot run on scope without parse context"),
-            main_module_path: Some(scope.path()),
-            scope,
-            in_a_container: false,
-            enum_item: None,
-            main_candidate: None,
+    fn resolve<'src>(&self, code: &'src ParseCtx<'src>) -> Node<'src> {
+        self.path.iter().fold(code.root_node(), |node, &index| {
+            node.child(index as usize)
+                .expect("declaration is not in this parse tree")
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeclarationKind {
+    Module,
+    Protocol,
+    StructLike,
+    Impl,
+    Enum,
+    EnumMember,
+    Field,
+    Function,
+    TypeDef,
+    Mixin,
+    Static,
+    Macro,
+    Const,
+    Use,
+    Placeholder,
+    Parameter,
+    MacroParameter,
+}
+
+/// An item declared in a source file, along with the items declared in its own scope (e.g.
+/// generic parameters, fields or associated functions).
+///
+/// Collecting declarations only needs the parse tree and the cfg options, so it can run on
+/// many files in parallel. Registering them into the scope tree (and thus the AST context)
+/// happens afterwards on the main thread, in a deterministic order.
+#[derive(Debug)]
+pub struct Declaration {
+    kind: DeclarationKind,
+    node: NodeRef,
+    attributes: ParsedAttributes,
+    children: Vec<Declaration>,
+}
+
+const DECLARATION_KINDS: &[DeclarationKind] = &[
+    DeclarationKind::Module,
+    DeclarationKind::Protocol,
+    DeclarationKind::StructLike,
+    DeclarationKind::Impl,
+    DeclarationKind::Enum,
+    DeclarationKind::EnumMember,
+    DeclarationKind::Field,
+    DeclarationKind::Function,
+    DeclarationKind::TypeDef,
+    DeclarationKind::Mixin,
+    DeclarationKind::Static,
+    DeclarationKind::Macro,
+    DeclarationKind::Const,
+    DeclarationKind::Use,
+    DeclarationKind::Placeholder,
+    DeclarationKind::Parameter,
+    DeclarationKind::MacroParameter,
+];
+
+impl Declaration {
+    pub fn encode(&self, encoder: &mut Encoder) {
+        let kind = DECLARATION_KINDS
+            .iter()
+            .position(|k| *k == self.kind)
+            .unwrap();
+
+        encoder.write_u8(kind as u8);
+        encoder.write_usize(self.node.path.len());
+        for index in &self.node.path {
+            encoder.write_u32(*index);
+        }
+        self.attributes.encode(encoder);
+
+        encoder.write_usize(self.children.len());
+        for child in &self.children {
+            child.encode(encoder);
         }
     }
 
-    pub fn main_candidate(&self) -> Option<ItemP<'ast>> {
-        self.main_candidate
+    pub fn decode(decoder: &mut Decoder<'_>) -> Option<Self> {
+        let kind = *DECLARATION_KINDS.get(decoder.read_u8()? as usize)?;
+        let len = decoder.read_len()?;
+        let mut path = Vec::with_capacity(len);
+        for _ in 0..len {
+            path.push(decoder.read_u32()?);
+        }
+        let node = NodeRef { path };
+        let attributes = ParsedAttributes::decode(decoder)?;
+
+        let len = decoder.read_len()?;
+        let mut children = Vec::with_capacity(len);
+        for _ in 0..len {
+            children.push(Declaration::decode(decoder)?);
+        }
+
+        Some(Declaration {
+            kind,
+            node,
+            attributes,
+            children,
+        })
     }
 }
 
-macro_rules! with_child_scope {
-    ($self:ident, $scope:expr, $body:block) => {
-        let previous_scope = std::mem::replace(&mut $self.scope, $scope);
-        $body
-        $self.scope = previous_scope;
-    };
+pub struct DeclarationCollector<'a, 'src> {
+    cfg: &'a CfgSet,
+    code: &'src ParseCtx<'src>,
+    declarations: Vec<Declaration>,
+    /// Path of the node that is currently being visited
+    path: Vec<u32>,
 }
 
-macro_rules! with_child_scope_container {
-    ($self:ident, $scope:expr, $body:block) => {
-        let previous_scope = std::mem::replace(&mut $self.scope, $scope);
-        let previous_in_a_container = $self.in_a_container;
-        $body
-        $self.scope = previous_scope;
-        $self.in_a_container = previous_in_a_container;
-    };
-}
+impl<'a, 'src> DeclarationCollector<'a, 'src> {
+    pub fn new(cfg: &'a CfgSet, code: &'src ParseCtx<'src>) -> Self {
+        Self {
+            cfg,
+            code,
+            declarations: Vec::new(),
+            path: Vec::new(),
+        }
+    }
 
-impl<'ast, 'src> FirstPassVisitor<'ast, 'src> {
-    fn parse_name(&self, node: Node<'src>) -> &'ast str {
-        let name_node = node.child_by_field_name("name").unwrap();
-        self.code.node_text(name_node).alloc_on(self.ast)
+    pub fn collect(mut self, node: Node<'src>) -> Result<Vec<Declaration>, AluminaError> {
+        self.path = NodeRef::new(node).path;
+        self.visit(node)?;
+        Ok(self.declarations)
+    }
+
+    /// Children of the node in the given field, along with their index among all the children
+    fn field_children(node: Node<'src>, field: &str) -> Vec<(u32, Node<'src>)> {
+        let mut children = Vec::new();
+        let mut cursor = node.walk();
+        if cursor.goto_first_child() {
+            let mut index = 0;
+            loop {
+                if cursor.field_name() == Some(field) {
+                    children.push((index, cursor.node()));
+                }
+                index += 1;
+                if !cursor.goto_next_sibling() {
+                    break;
+                }
+            }
+        }
+
+        children
+    }
+
+    fn with_child<T>(&mut self, index: u32, f: impl FnOnce(&mut Self) -> T) -> T {
+        self.path.push(index);
+        let result = f(self);
+        self.path.pop();
+        result
+    }
+
+    fn visit_field(&mut self, node: Node<'src>, field: &str) -> Result<(), AluminaError> {
+        for (index, child) in Self::field_children(node, field) {
+            self.with_child(index, |this| this.visit(child))?;
+        }
+
+        Ok(())
+    }
+
+    /// Declares the node that is currently being visited.
+    fn declare(
+        &mut self,
+        kind: DeclarationKind,
+        attributes: ParsedAttributes,
+        children: impl FnOnce(&mut Self) -> Result<(), AluminaError>,
+    ) -> Result<(), AluminaError> {
+        let outer = std::mem::take(&mut self.declarations);
+        children(self)?;
+        let children = std::mem::replace(&mut self.declarations, outer);
+
+        self.declarations.push(Declaration {
+            kind,
+            node: NodeRef {
+                path: self.path.clone(),
+            },
+            attributes,
+            children,
+        });
+
+        Ok(())
+    }
+
+    fn visit_type_arguments(&mut self, node: Node<'src>) -> Result<(), AluminaError> {
+        self.visit_field(node, "type_arguments")
     }
 }
 
 macro_rules! parse_attributes {
-    (@, $self:expr, $node:expr, $item:expr) => {
-        match AttributeVisitor::parse_attributes($self.global_ctx.clone(), $self.ast, $self.scope.clone(), $node, $item)? {
+    ($self:expr, $node:expr) => {
+        match AttributeVisitor::parse($self.cfg, $self.code, $node)? {
             Some(attributes) => attributes,
             None => return Ok(()),
         }
     };
-    ($self:expr, $node:expr, $item:expr) => {
-        parse_attributes!(@, $self, $node, Some($item))
-    };
-    ($self:expr, $node:expr) => {
-        parse_attributes!(@, $self, $node, None)
-    };
 }
 
-pub(crate) use parse_attributes;
+/// Whether the well-formed items inside a node that contains syntax errors can still be
+/// collected. This is only the case for modules (and blocks of items), as long as their own
+/// name and attributes are well-formed. All the other kinds of items are skipped as a whole.
+fn can_recover(node: Node<'_>) -> bool {
+    matches!(
+        node.kind(),
+        "source_file" | "mod_definition" | "top_level_block"
+    ) && ["name", "attributes"].iter().all(|field| {
+        node.child_by_field_name(field)
+            .is_none_or(|child| !child.has_error())
+    })
+}
 
-impl<'ast, 'src> AluminaVisitor<'src> for FirstPassVisitor<'ast, 'src> {
+impl<'a, 'src> AluminaVisitor<'src> for DeclarationCollector<'a, 'src> {
     type ReturnType = Result<(), AluminaError>;
 
+    fn visit(&mut self, node: Node<'src>) -> Self::ReturnType {
+        // The syntax errors themselves are reported by `ParseCtx::check_syntax_errors`.
+        if node.has_error() && !can_recover(node) {
+            return Ok(());
+        }
+
+        self.dispatch(node)
+    }
+
     fn visit_source_file(&mut self, node: Node<'src>) -> Self::ReturnType {
         parse_attributes!(self, node);
-        self.visit_children_by_field(node, "body")
+        self.visit_field(node, "body")
     }
 
     fn visit_mod_definition(&mut self, node: Node<'src>) -> Self::ReturnType {
         let attributes = parse_attributes!(self, node);
-        let name = self.parse_name(node);
-        let child_scope = self.scope.named_child(ScopeType::Module, name);
-
-        self.scope
-            .add_item(
-                Some(name),
-                NamedItem::new(NamedItemKind::Module(child_scope.clone()), attributes),
-            )
-            .with_span_from(&self.scope, node)?;
-
-        with_child_scope!(self, child_scope, {
-            self.visit_children_by_field(node, "body")?;
-        });
-
-        Ok(())
+        self.declare(DeclarationKind::Module, attributes, |this| {
+            this.visit_field(node, "body")
+        })
     }
 
     fn visit_top_level_block(&mut self, node: Node<'src>) -> Self::ReturnType {
-        let _ = parse_attributes!(self, node);
-
-        self.visit_children_by_field(node, "items")
+        parse_attributes!(self, node);
+        self.visit_field(node, "items")
     }
 
     fn visit_protocol_definition(&mut self, node: Node<'src>) -> Self::ReturnType {
-        let item = self.ast.make_symbol();
-        let attributes = parse_attributes!(self, node, item);
+        let attributes = parse_attributes!(self, node);
+        self.declare(DeclarationKind::Protocol, attributes, |this| {
+            this.visit_type_arguments(node)?;
+            this.visit_field(node, "body")
+        })
+    }
 
-        let name = self.parse_name(node);
-        let child_scope = self.scope.named_child(ScopeType::Protocol, name);
+    fn visit_struct_definition(&mut self, node: Node<'src>) -> Self::ReturnType {
+        let attributes = parse_attributes!(self, node);
+        self.declare(DeclarationKind::StructLike, attributes, |this| {
+            this.visit_type_arguments(node)?;
+            this.visit_field(node, "body")
+        })
+    }
 
-        self.scope
-            .add_item(
-                Some(name),
-                NamedItem::new(
-                    NamedItemKind::Protocol(item, node, child_scope.clone()),
-                    attributes,
-                ),
-            )
-            .with_span_from(&self.scope, node)?;
-
-        with_child_scope_container!(self, child_scope, {
-            if let Some(f) = node.child_by_field_name("type_arguments") {
-                self.visit(f)?;
-            }
-            self.visit_children_by_field(node, "body")?;
-        });
+    fn visit_impl_block(&mut self, node: Node<'src>) -> Self::ReturnType {
+        let attributes = parse_attributes!(self, node);
+        self.declare(DeclarationKind::Impl, attributes, |this| {
+            this.visit_type_arguments(node)?;
+            this.visit_field(node, "body")
+        })
+    }
 
-        Ok(())
+    fn visit_enum_definition(&mut self, node: Node<'src>) -> Self::ReturnType {
+        let attributes = parse_attributes!(self, node);
+        self.declare(DeclarationKind::Enum, attributes, |this| {
+            this.visit_field(node, "body")
+        })
     }
 
-    fn visit_struct_definition(&mut self, node: Node<'src>) -> Self::ReturnType {
-        let item = self.ast.make_symbol();
-        let attributes = parse_attributes!(self, node, item);
+    fn visit_enum_item(&mut self, node: Node<'src>) -> Self::ReturnType {
+        let attributes = parse_attributes!(self, node);
+        self.declare(DeclarationKind::EnumMember, attributes, |_| Ok(()))
+    }
 
-        let name = self.parse_name(node);
-        let child_scope = self.scope.named_child(ScopeType::StructLike, name);
+    fn visit_struct_field(&mut self, node: Node<'src>) -> Self::ReturnType {
+        let attributes = parse_attributes!(self, node);
+        self.declare(DeclarationKind::Field, attributes, |_| Ok(()))
+    }
 
-        self.scope
-            .add_item(
-                Some(name),
-                NamedItem::new(
-                    NamedItemKind::Type(item, node, child_scope.clone()),
-                    attributes,
-                ),
-            )
-            .with_span_from(&self.scope, node)?;
-
-        with_child_scope!(self, child_scope, {
-            if let Some(f) = node.child_by_field_name("type_arguments") {
-                self.visit(f)?;
-            }
-            self.visit_children_by_field(node, "body")?;
-        });
+    fn visit_function_definition(&mut self, node: Node<'src>) -> Self::ReturnType {
+        let attributes = parse_attributes!(self, node);
+        self.declare(DeclarationKind::Function, attributes, |this| {
+            this.visit_type_arguments(node)?;
+            this.visit_field(node, "parameters")
+        })
+    }
 
-        Ok(())
+    fn visit_type_definition(&mut self, node: Node<'src>) -> Self::ReturnType {
+        let attributes = parse_attributes!(self, node);
+        self.declare(DeclarationKind::TypeDef, attributes, |this| {
+            this.visit_type_arguments(node)
+        })
     }
 
-    fn visit_impl_block(&mut self, node: Node<'src>) -> Self::ReturnType {
+    fn visit_mixin(&mut self, node: Node<'src>) -> Self::ReturnType {
         let attributes = parse_attributes!(self, node);
+        self.declare(DeclarationKind::Mixin, attributes, |this| {
+            this.visit_type_arguments(node)
+        })
+    }
 
-        let name = self.parse_name(node);
-        let child_scope = self.scope.named_child(ScopeType::Impl, name);
+    fn visit_static_declaration(&mut self, node: Node<'src>) -> Self::ReturnType {
+        let attributes = parse_attributes!(self, node);
+        self.declare(DeclarationKind::Static, attributes, |this| {
+            this.visit_type_arguments(node)
+        })
+    }
 
-        self.scope
-            .add_item(
-                Some(name),
-                NamedItem::new(NamedItemKind::Impl(node, child_scope.clone()), attributes),
-            )
-            .with_span_from(&self.scope, node)?;
-
-        with_child_scope_container!(self, child_scope, {
-            if let Some(f) = node.child_by_field_name("type_arguments") {
-                self.visit(f)?;
-            }
-            self.visit_children_by_field(node, "body")?;
-        });
+    fn visit_generic_argument_list(&mut self, node: Node<'src>) -> Self::ReturnType {
+        for (index, _) in Self::field_children(node, "argument") {
+            self.with_child(index, |this| {
+                this.declare(
+                    DeclarationKind::Placeholder,
+                    ParsedAttributes::default(),
+                    |_| Ok(()),
+                )
+            })?;
+        }
 
         Ok(())
     }
 
-    fn visit_enum_definition(&mut self, node: Node<'src>) -> Self::ReturnType {
-        let item = self.ast.make_symbol();
-        let attributes = parse_attributes!(self, node, item);
+    fn visit_parameter(&mut self, _node: Node<'src>) -> Self::ReturnType {
+        self.declare(
+            DeclarationKind::Parameter,
+            ParsedAttributes::default(),
+            |_| Ok(()),
+        )
+    }
 
-        let name = self.parse_name(node);
-        let child_scope = self.scope.named_child(ScopeType::Enum, name);
+    fn visit_macro_parameter(&mut self, _node: Node<'src>) -> Self::ReturnType {
+        self.declare(
+            DeclarationKind::MacroParameter,
+            ParsedAttributes::default(),
+            |_| Ok(()),
+        )
+    }
 
-        self.scope
-            .add_item(
-                Some(name),
-                NamedItem::new(
-                    NamedItemKind::Type(item, node, child_scope.clone()),
-                    attributes,
-                ),
-            )
-            .with_span_from(&self.scope, node)?;
-
-        with_child_scope!(self, child_scope, {
-            self.enum_item = Some(item);
-            self.visit_children_by_field(node, "body")?;
-        });
+    fn visit_parameter_list(&mut self, node: Node<'src>) -> Self::ReturnType {
+        self.visit_field(node, "parameter")
+    }
 
-        Ok(())
+    fn visit_macro_parameter_list(&mut self, node: Node<'src>) -> Self::ReturnType {
+        self.visit_field(node, "parameter")
     }
 
-    fn visit_enum_item(&mut self, node: Node<'src>) -> Self::ReturnType {
+    fn visit_use_declaration(&mut self, node: Node<'src>) -> Self::ReturnType {
         let attributes = parse_attributes!(self, node);
+        self.declare(DeclarationKind::Use, attributes, |_| Ok(()))
+    }
 
-        let name = self.parse_name(node);
-        self.scope
-            .add_item(
-                Some(name),
-                NamedItem::new(
-                    NamedItemKind::EnumMember(self.enum_item.unwrap(), self.ast.make_id(), node),
-                    attributes,
-                ),
-            )
-            .with_span_from(&self.scope, node)?;
-
-        Ok(())
+    fn visit_macro_definition(&mut self, node: Node<'src>) -> Self::ReturnType {
+        let attributes = parse_attributes!(self, node);
+        self.declare(DeclarationKind::Macro, attributes, |this| {
+            this.visit_field(node, "parameters")
+        })
     }
 
-    fn visit_struct_field(&mut self, node: Node<'src>) -> Self::ReturnType {
+    fn visit_const_declaration(&mut self, node: Node<'src>) -> Self::ReturnType {
         let attributes = parse_attributes!(self, node);
+        self.declare(DeclarationKind::Const, attributes, |_| Ok(()))
+    }
 
-        let name = self.parse_name(node);
-        self.scope
-            .add_item(
-                Some(name),
-                NamedItem::new(NamedItemKind::Field(node), attributes),
-            )
-            .with_span_from(&self.scope, node)?;
+    fn visit_doc_comment(&mut self, _node: tree_sitter::Node<'src>) -> Self::ReturnType {
+        Ok(())
+    }
 
+    fn visit_file_doc_comment(&mut self, _node: tree_sitter::Node<'src>) -> Self::ReturnType {
         Ok(())
     }
+}
 
-    fn visit_function_definition(&mut self, node: Node<'src>) -> Self::ReturnType {
-        let item = self.ast.make_symbol();
-        let attributes = parse_attributes!(self, node, item);
-
-        let name = self.parse_name(node);
-
-        if let Some(path) = self.main_module_path.as_ref() {
-            if self.global_ctx.cfg("test").is_some() {
-                if attributes.contains(&Attribute::TestMain)
-                    && self.main_candidate.replace(item).is_some()
-                {
-                    return Err(CodeErrorKind::MultipleMainFunctions)
-                        .with_span_from(&self.scope, node);
-                }
-            } else if &self.scope.path() == path
-                && name == "main"
-                && self.main_candidate.replace(item).is_some()
-            {
-                return Err(CodeErrorKind::MultipleMainFunctions).with_span_from(&self.scope, node);
-            }
-        }
+pub struct FirstPassVisitor<'ast, 'src> {
+    global_ctx: GlobalCtx,
+    ast: &'ast AstCtx<'ast>,
+    scope: Scope<'ast, 'src>,
+    code: &'src ParseCtx<'src>,
+    enum_item: Option<ItemP<'ast>>,
 
-        let child_scope = self.scope.named_child(ScopeType::Function, name);
+    in_a_container: bool,
+    main_module_path: Option<Path<'ast>>,
+    main_candidate: Option<ItemP<'ast>>,
+    program_main: Option<ItemP<'ast>>,
+}
 
-        self.scope
-            .add_item(
-                Some(name),
-                NamedItem::new(
-                    if self.in_a_container {
-                        NamedItemKind::Method(item, node, child_scope.clone())
-                    } else {
-                        NamedItemKind::Function(item, node, child_scope.clone())
-                    },
-                    attributes,
-                ),
-            )
-            .with_span_from(&self.scope, node)?;
-
-        with_child_scope!(self, child_scope, {
-            if let Some(f) = node.child_by_field_name("type_arguments") {
-                self.visit(f)?;
-            }
-            self.visit_children_by_field(node, "parameters")?;
-        });
+impl<'ast, 'src> FirstPassVisitor<'ast, 'src> {
+    pub fn new(global_ctx: GlobalCtx, ast: &'ast AstCtx<'ast>, scope: Scope<'ast, 'src>) -> Self {
+        Self {
+            global_ctx,
+            ast,
+            code: scope
+                .code()
+                .expect("cannot run on scope without parse context"),
+            scope,
+            in_a_container: false,
+            enum_item: None,
+            main_module_path: None,
+            main_candidate: None,
+            program_main: None,
+        }
+    }
 
-        Ok(())
+    pub fn with_main(
+        global_ctx: GlobalCtx,
+        ast: &'ast AstCtx<'ast>,
+        scope: Scope<'ast, 'src>,
+    ) -> Self {
+        Self {
+            global_ctx,
+            ast,
+            code: scope
+                .code()
+                .expect("cannot run on scope without parse context"),
+            main_module_path: Some(scope.path()),
+            scope,
+            in_a_container: false,
+            enum_item: None,
+            main_candidate: None,
+            program_main: None,
+        }
     }
 
-    fn visit_type_definition(&mut self, node: Node<'src>) -> Self::ReturnType {
-        let item = self.ast.make_symbol();
-        let attributes = parse_attributes!(self, node, item);
+    pub fn main_candidate(&self) -> Option<ItemP<'ast>> {
+        self.main_candidate
+    }
+
+    /// The `main` function of the program when the test runner is the entrypoint instead.
+    pub fn program_main(&self) -> Option<ItemP<'ast>> {
+        self.program_main
+    }
 
-        let name = self.parse_name(node);
+    /// Collects and registers the declarations of a node in one go. Used for items that
+    /// are declared inside function bodies.
+    pub fn visit(&mut self, node: Node<'src>) -> Result<(), AluminaError> {
+        let declarations =
+            DeclarationCollector::new(&self.global_ctx.cfg_set(), self.code).collect(node)?;
 
-        let child_scope = self.scope.named_child(ScopeType::Function, name);
+        self.register(declarations)
+    }
 
-        self.scope
-            .add_item(
-                Some(name),
-                NamedItem::new(
-                    NamedItemKind::TypeDef(item, node, child_scope.clone()),
-                    attributes,
-                ),
-            )
-            .with_span_from(&self.scope, node)?;
-
-        with_child_scope!(self, child_scope, {
-            if let Some(f) = node.child_by_field_name("type_arguments") {
-                self.visit(f)?;
+    /// Registers the declarations into the current scope. A declaration that cannot be
+    /// registered (e.g. because of a name conflict) does not prevent the others from being
+    /// registered, and all the errors are reported together.
+    pub fn register(&mut self, declarations: Vec<Declaration>) -> Result<(), AluminaError> {
+        let mut errors = Vec::new();
+        for declaration in declarations {
+            match self.register_one(declaration) {
+                Ok(()) => {}
+                Err(AluminaError::CodeErrors(e)) => errors.extend(e),
+                Err(e) => return Err(e),
             }
-        });
+        }
 
-        Ok(())
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(AluminaError::CodeErrors(errors))
+        }
     }
+}
 
-    fn visit_mixin(&mut self, node: Node<'src>) -> Self::ReturnType {
-        let attributes = parse_attributes!(self, node);
-        let child_scope = self.scope.anonymous_child(ScopeType::Function);
+// The scope is restored even if the body fails, as the errors do not stop the registration
+// of the sibling declarations.
+macro_rules! with_child_scope {
+    ($self:ident, $scope:expr, $body:block) => {{
+        let previous_scope = std::mem::replace(&mut $self.scope, $scope);
+        let result = $body;
+        $self.scope = previous_scope;
+        result
+    }};
+}
+
+macro_rules! with_child_scope_container {
+    ($self:ident, $scope:expr, $body:block) => {{
+        let previous_scope = std::mem::replace(&mut $self.scope, $scope);
+        let previous_in_a_container = $self.in_a_container;
+        let result = $body;
+        $self.scope = previous_scope;
+        $self.in_a_container = previous_in_a_container;
+        result
+    }};
+}
 
+impl<'ast, 'src> FirstPassVisitor<'ast, 'src> {
+    /// Adds an item to the current scope.
+    fn add_item(
+        &self,
+        name: Option<&'ast str>,
+        item: NamedItem<'ast, 'src>,
+        node: Node<'src>,
+    ) -> Result<(), AluminaError> {
         self.scope
-            .add_item(
-                None,
-                NamedItem::new(NamedItemKind::Mixin(node, child_scope.clone()), attributes),
-            )
-            .with_span_from(&self.scope, node)?;
-
-        with_child_scope!(self, child_scope, {
-            if let Some(f) = node.child_by_field_name("type_arguments") {
-                self.visit(f)?;
+            .add_item(name, item)
+            .map_err(|kind| self.conflict(kind, name, node))
+    }
+
+    /// As modules can be split across several files, the item that a new one conflicts with
+    /// can be declared elsewhere, so the error also points to it.
+    fn conflict(
+        &self,
+        kind: CodeErrorKind,
+        name: Option<&'ast str>,
+        node: Node<'src>,
+    ) -> AluminaError {
+        let mut error = CodeError::from_kind(kind, Some(self.code.span(node)));
+        if let Some(name) = name {
+            if let Some(span) = self.scope.item_span(name) {
+                error = error.with_note(
+                    CodeErrorKind::PreviousDefinition(name.to_string()),
+                    Some(span),
+                );
             }
-        });
+        }
 
-        Ok(())
+        AluminaError::CodeErrors(vec![error])
     }
 
-    fn visit_static_declaration(&mut self, node: Node<'src>) -> Self::ReturnType {
-        let item = self.ast.make_symbol();
-        let attributes = parse_attributes!(self, node, item);
+    fn parse_name(&self, node: Node<'src>) -> Result<&'ast str, AluminaError> {
+        let name_node = node.expect_child("name")?;
+        Ok(self.code.node_text(name_node).alloc_on(self.ast))
+    }
 
-        let name = self.parse_name(node);
-        let child_scope = self.scope.named_child(ScopeType::Function, name);
+    fn register_one(&mut self, declaration: Declaration) -> Result<(), AluminaError> {
+        let Declaration {
+            kind,
+            node,
+            attributes,
+            children,
+        } = declaration;
+
+        let node = node.resolve(self.code);
+
+        match kind {
+            DeclarationKind::Module => {
+                let attributes = attributes.register(self.ast, &self.scope, node, None)?;
+                let name = self.parse_name(node)?;
+
+                // The module may already exist, e.g. if it is also declared in another file.
+                let child_scope = self
+                    .scope
+                    .ensure_child_module(name, attributes)
+                    .map_err(|kind| self.conflict(kind, Some(name), node))?
+                    .with_code(self.code);
+
+                with_child_scope!(self, child_scope, { self.register(children) })?;
+            }
+            DeclarationKind::Protocol => {
+                let item = self.ast.make_symbol();
+                let attributes = attributes.register(self.ast, &self.scope, node, Some(item))?;
 
-        self.scope
-            .add_item(
-                Some(name),
-                NamedItem::new(
-                    NamedItemKind::Static(item, node, child_scope.clone()),
-                    attributes,
-                ),
-            )
-            .with_span_from(&self.scope, node)?;
-
-        with_child_scope!(self, child_scope, {
-            if let Some(f) = node.child_by_field_name("type_arguments") {
-                self.visit(f)?;
+                let name = self.parse_name(node)?;
+                let child_scope = self.scope.named_child(ScopeType::Protocol, name);
+
+                self.add_item(
+                    Some(name),
+                    NamedItem::new(
+                        NamedItemKind::Protocol(item, node, child_scope.clone()),
+                        attributes,
+                    ),
+                    node,
+                )?;
+
+                with_child_scope_container!(self, child_scope, { self.register(children) })?;
             }
-        });
+            DeclarationKind::StructLike => {
+                let item = self.ast.make_symbol();
+                let attributes = attributes.register(self.ast, &self.scope, node, Some(item))?;
 
-        Ok(())
-    }
+                let name = self.parse_name(node)?;
+                let child_scope = self.scope.named_child(ScopeType::StructLike, name);
 
-    fn visit_generic_argument_list(&mut self, node: Node<'src>) -> Self::ReturnType {
-        let mut cursor = node.walk();
-        for argument in node.children_by_field_name("argument", &mut cursor) {
-            let name = self
-                .code
-                .node_text(argument.child_by_field_name("placeholder").unwrap())
-                .alloc_on(self.ast);
-            self.scope
-                .add_item(
+                self.add_item(
                     Some(name),
-                    NamedItem::new_default(NamedItemKind::Placeholder(
-                        self.ast.make_id(),
-                        argument,
-                    )),
-                )
-                .with_span_from(&self.scope, node)?;
-        }
+                    NamedItem::new(
+                        NamedItemKind::Type(item, node, child_scope.clone()),
+                        attributes,
+                    ),
+                    node,
+                )?;
+
+                with_child_scope!(self, child_scope, { self.register(children) })?;
+            }
+            DeclarationKind::Impl => {
+                let attributes = attributes.register(self.ast, &self.scope, node, None)?;
 
-        Ok(())
-    }
+                let name = self.parse_name(node)?;
+                let child_scope = self.scope.named_child(ScopeType::Impl, name);
 
-    fn visit_parameter(&mut self, node: Node<'src>) -> Self::ReturnType {
-        let name = self.parse_name(node);
+                self.add_item(
+                    Some(name),
+                    NamedItem::new(NamedItemKind::Impl(node, child_scope.clone()), attributes),
+                    node,
+                )?;
 
-        self.scope
-            .add_item(
-                Some(name),
-                NamedItem::new_default(NamedItemKind::Parameter(self.ast.make_id(), node)),
-            )
-            .with_span_from(&self.scope, node)?;
+                with_child_scope_container!(self, child_scope, { self.register(children) })?;
+            }
+            DeclarationKind::Enum => {
+                let item = self.ast.make_symbol();
+                let attributes = attributes.register(self.ast, &self.scope, node, Some(item))?;
 
-        Ok(())
-    }
+                let name = self.parse_name(node)?;
+                let child_scope = self.scope.named_child(ScopeType::Enum, name);
 
-    fn visit_macro_parameter(&mut self, node: Node<'src>) -> Self::ReturnType {
-        let name = self.parse_name(node);
+                self.add_item(
+                    Some(name),
+                    NamedItem::new(
+                        NamedItemKind::Type(item, node, child_scope.clone()),
+                        attributes,
+                    ),
+                    node,
+                )?;
+
+                with_child_scope!(self, child_scope, {
+                    self.enum_item = Some(item);
+                    self.register(children)
+                })?;
+            }
+            DeclarationKind::EnumMember => {
+                let attributes = attributes.register(self.ast, &self.scope, node, None)?;
 
-        self.scope
-            .add_item(
-                Some(name),
-                NamedItem::new_default(NamedItemKind::MacroParameter(
-                    self.ast.make_id(),
-                    node.child_by_field_name("et_cetera").is_some(),
-                )),
-            )
-            .with_span_from(&self.scope, node)?;
+                let name = self.parse_name(node)?;
+                self.add_item(
+                    Some(name),
+                    NamedItem::new(
+                        NamedItemKind::EnumMember(
+                            self.enum_item.unwrap(),
+                            self.ast.make_id(),
+                            node,
+                        ),
+                        attributes,
+                    ),
+                    node,
+                )?;
+            }
+            DeclarationKind::Field => {
+                let attributes = attributes.register(self.ast, &self.scope, node, None)?;
 
-        Ok(())
-    }
+                let name = self.parse_name(node)?;
+                self.add_item(
+                    Some(name),
+                    NamedItem::new(NamedItemKind::Field(node), attributes),
+                    node,
+                )?;
+            }
+            DeclarationKind::Function => {
+                let item = self.ast.make_symbol();
+                let attributes = attributes.register(self.ast, &self.scope, node, Some(item))?;
+
+                let name = self.parse_name(node)?;
+
+                if let Some(path) = self.main_module_path.as_ref() {
+                    let is_main = &self.scope.path() == path && name == "main";
+                    if self.global_ctx.cfg("test").is_some() {
+                        if attributes.contains(&Attribute::TestMain) {
+                            if self.main_candidate.replace(item).is_some() {
+                                return Err(CodeErrorKind::MultipleMainFunctions)
+                                    .with_span_from(&self.scope, node);
+                            }
+                        } else if is_main {
+                            self.program_main = Some(item);
+                        }
+                    } else if is_main && self.main_candidate.replace(item).is_some() {
+                        return Err(CodeErrorKind::MultipleMainFunctions)
+                            .with_span_from(&self.scope, node);
+                    }
+                }
 
-    fn visit_parameter_list(&mut self, node: Node<'src>) -> Self::ReturnType {
-        self.visit_children_by_field(node, "parameter")
-    }
+                let child_scope = self.scope.named_child(ScopeType::Function, name);
 
-    fn visit_macro_parameter_list(&mut self, node: Node<'src>) -> Self::ReturnType {
-        self.visit_children_by_field(node, "parameter")
-    }
+                self.add_item(
+                    Some(name),
+                    NamedItem::new(
+                        if self.in_a_container {
+                            NamedItemKind::Method(item, node, child_scope.clone())
+                        } else {
+                            NamedItemKind::Function(item, node, child_scope.clone())
+                        },
+                        attributes,
+                    ),
+                    node,
+                )?;
+
+                with_child_scope!(self, child_scope, { self.register(children) })?;
+            }
+            DeclarationKind::TypeDef => {
+                let item = self.ast.make_symbol();
+                let attributes = attributes.register(self.ast, &self.scope, node, Some(item))?;
 
-    fn visit_use_declaration(&mut self, node: Node<'src>) -> Self::ReturnType {
-        let attributes = parse_attributes!(self, node);
+                let name = self.parse_name(node)?;
 
-        let mut visitor = UseClauseVisitor::new(self.ast, self.scope.clone(), attributes, false);
-        visitor.visit(node.child_by_field_name("argument").unwrap())?;
+                let child_scope = self.scope.named_child(ScopeType::Function, name);
 
-        Ok(())
-    }
+                self.add_item(
+                    Some(name),
+                    NamedItem::new(
+                        NamedItemKind::TypeDef(item, node, child_scope.clone()),
+                        attributes,
+                    ),
+                    node,
+                )?;
+
+                with_child_scope!(self, child_scope, { self.register(children) })?;
+            }
+            DeclarationKind::Mixin => {
+                let attributes = attributes.register(self.ast, &self.scope, node, None)?;
+                let child_scope = self.scope.anonymous_child(ScopeType::Function);
 
-    fn visit_macro_definition(&mut self, node: Node<'src>) -> Self::ReturnType {
-        let item = self.ast.make_symbol();
-        let attributes = parse_attributes!(self, node, item);
+                self.add_item(
+                    None,
+                    NamedItem::new(NamedItemKind::Mixin(node, child_scope.clone()), attributes),
+                    node,
+                )?;
 
-        let name = self.parse_name(node);
-        let child_scope = self.scope.named_child(ScopeType::Macro, name);
+                with_child_scope!(self, child_scope, { self.register(children) })?;
+            }
+            DeclarationKind::Static => {
+                let item = self.ast.make_symbol();
+                let attributes = attributes.register(self.ast, &self.scope, node, Some(item))?;
 
-        self.scope
-            .add_item(
-                Some(name),
-                NamedItem::new(
-                    NamedItemKind::Macro(item, node, child_scope.clone()),
-                    attributes,
-                ),
-            )
-            .with_span_from(&self.scope, node)?;
-
-        with_child_scope!(self, child_scope, {
-            self.visit_children_by_field(node, "parameters")?;
-        });
+                let name = self.parse_name(node)?;
+                let child_scope = self.scope.named_child(ScopeType::Function, name);
 
-        Ok(())
-    }
+                self.add_item(
+                    Some(name),
+                    NamedItem::new(
+                        NamedItemKind::Static(item, node, child_scope.clone()),
+                        attributes,
+                    ),
+                    node,
+                )?;
+
+                with_child_scope!(self, child_scope, { self.register(children) })?;
+            }
+            DeclarationKind::Macro => {
+                let item = self.ast.make_symbol();
+                let attributes = attributes.register(self.ast, &self.scope, node, Some(item))?;
 
-    fn visit_const_declaration(&mut self, node: Node<'src>) -> Self::ReturnType {
-        let item = self.ast.make_symbol();
-        let attributes = parse_attributes!(self, node, item);
+                let name = self.parse_name(node)?;
+                let child_scope = self.scope.named_child(ScopeType::Macro, name);
 
-        let name = self.parse_name(node);
+                self.add_item(
+                    Some(name),
+                    NamedItem::new(
+                        NamedItemKind::Macro(item, node, child_scope.clone()),
+                        attributes,
+                    ),
+                    node,
+                )?;
+
+                with_child_scope!(self, child_scope, { self.register(children) })?;
+            }
+            DeclarationKind::Const => {
+                let item = self.ast.make_symbol();
+                let attributes = attributes.register(self.ast, &self.scope, node, Some(item))?;
 
-        self.scope
-            .add_item(
-                Some(name),
-                NamedItem::new(NamedItemKind::Const(item, node), attributes),
-            )
-            .with_span_from(&self.scope, node)?;
+                let name = self.parse_name(node)?;
 
-        Ok(())
-    }
+                self.add_item(
+                    Some(name),
+                    NamedItem::new(NamedItemKind::Const(item, node, self.code), attributes),
+                    node,
+                )?;
+            }
+            DeclarationKind::Use => {
+                let attributes = attributes.register(self.ast, &self.scope, node, None)?;
 
-    fn visit_doc_comment(&mut self, _node: tree_sitter::Node<'src>) -> Self::ReturnType {
-        Ok(())
-    }
+                let mut visitor =
+                    UseClauseVisitor::new(self.ast, self.scope.clone(), attributes, false);
+                visitor.visit(node.expect_child("argument")?)?;
+            }
+            DeclarationKind::Placeholder => {
+                let name = self
+                    .code
+                    .node_text(node.expect_child("placeholder")?)
+                    .alloc_on(self.ast);
+
+                self.add_item(
+                    Some(name),
+                    NamedItem::new_default(NamedItemKind::Placeholder(self.ast.make_id(), node)),
+                    node,
+                )?;
+            }
+            DeclarationKind::Parameter => {
+                let name = self.parse_name(node)?;
+
+                self.add_item(
+                    Some(name),
+                    NamedItem::new_default(NamedItemKind::Parameter(self.ast.make_id(), node)),
+                    node,
+                )?;
+            }
+            DeclarationKind::MacroParameter => {
+                let name = self.parse_name(node)?;
+
+                self.add_item(
+                    Some(name),
+                    NamedItem::new_default(NamedItemKind::MacroParameter(
+                        self.ast.make_id(),
+                        node.child_by_field_name("et_cetera").is_some(),
+                    )),
+                    node,
+                )?;
+            }
+        }
 
-    fn visit_file_doc_comment(&mut self, _node: tree_sitter::Node<'src>) -> Self::ReturnType {
         Ok(())
     }
 }
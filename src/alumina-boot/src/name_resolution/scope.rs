@@ -5,12 +5,11 @@ use std::{
 };
 
 use crate::{
-    ast::{AstId, Attribute, ItemP},
+    ast::{AstId, Attribute, ItemP, Span},
     common::CodeErrorKind,
     parser::ParseCtx,
 };
 use indexmap::{map::Entry, IndexMap};
-use once_cell::unsync::OnceCell;
 use tree_sitter::Node;
 
 use super::path::{Path, PathSegment};
@@ -23,12 +22,12 @@ pub enum BoundItemType {
 
 #[derive(Debug, Clone)]
 pub enum NamedItemKind<'ast, 'src> {
-    Alias(Path<'ast>, Node<'src>),
+    Alias(Path<'ast>, Node<'src>, &'src ParseCtx<'src>),
     Function(ItemP<'ast>, Node<'src>, Scope<'ast, 'src>),
     Method(ItemP<'ast>, Node<'src>, Scope<'ast, 'src>),
     TypeDef(ItemP<'ast>, Node<'src>, Scope<'ast, 'src>),
     Static(ItemP<'ast>, Node<'src>, Scope<'ast, 'src>),
-    Const(ItemP<'ast>, Node<'src>),
+    Const(ItemP<'ast>, Node<'src>, &'src ParseCtx<'src>),
     Macro(ItemP<'ast>, Node<'src>, Scope<'ast, 'src>),
     Type(ItemP<'ast>, Node<'src>, Scope<'ast, 'src>),
     Mixin(Node<'src>, Scope<'ast, 'src>),
@@ -48,11 +47,11 @@ pub enum NamedItemKind<'ast, 'src> {
 impl Display for NamedItemKind<'_, '_> {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         match self {
-            NamedItemKind::Alias(_, _) => write!(f, "alias"),
+            NamedItemKind::Alias(_, _, _) => write!(f, "alias"),
             NamedItemKind::Function(_, _, _) => write!(f, "function"),
             NamedItemKind::Method(_, _, _) => write!(f, "method"),
             NamedItemKind::Static(_, _, _) => write!(f, "static"),
-            NamedItemKind::Const(_, _) => write!(f, "const"),
+            NamedItemKind::Const(_, _, _) => write!(f, "const"),
             NamedItemKind::Macro(_, _, _) => write!(f, "macro"),
             NamedItemKind::Type(_, _, _) => write!(f, "type"),
             NamedItemKind::Mixin(_, _) => write!(f, "mixin"),
@@ -115,7 +114,9 @@ pub struct ScopeInner<'ast, 'src> {
     pub star_imports: Vec<Path<'ast>>,
     pub parent: Option<Weak<RefCell<ScopeInner<'ast, 'src>>>>,
 
-    code: OnceCell<&'src ParseCtx<'src>>,
+    // Modules can be split across several files, so they do not have a parse context of their
+    // own. Items declared in a module get it from the `Scope` they were declared through.
+    code: Option<&'src ParseCtx<'src>>,
 }
 
 impl<'ast, 'src> ScopeInner<'ast, 'src> {
@@ -160,8 +161,14 @@ impl<'ast, 'src> Debug for ScopeInner<'ast, 'src> {
     }
 }
 
+/// A handle to a scope. For modules, which do not have a parse context of their own, the
+/// handle can carry the parse context of the file that the module is being visited in (see
+/// [`Scope::with_code`]). All handles to the same scope compare equal.
 #[derive(Clone)]
-pub struct Scope<'ast, 'src>(pub Rc<RefCell<ScopeInner<'ast, 'src>>>);
+pub struct Scope<'ast, 'src>(
+    pub Rc<RefCell<ScopeInner<'ast, 'src>>>,
+    Option<&'src ParseCtx<'src>>,
+);
 
 impl<'ast, 'src> PartialEq for Scope<'ast, 'src> {
     fn eq(&self, other: &Self) -> bool {
@@ -183,14 +190,17 @@ impl<'ast, 'src> Debug for Scope<'ast, 'src> {
 
 impl<'ast, 'src> Scope<'ast, 'src> {
     pub fn new_root() -> Self {
-        Scope(Rc::new(RefCell::new(ScopeInner {
-            r#type: ScopeType::Root,
-            path: Path::root(),
-            items: IndexMap::new(),
-            star_imports: Vec::new(),
-            parent: None,
-            code: OnceCell::new(),
-        })))
+        Scope(
+            Rc::new(RefCell::new(ScopeInner {
+                r#type: ScopeType::Root,
+                path: Path::root(),
+                items: IndexMap::new(),
+                star_imports: Vec::new(),
+                parent: None,
+                code: None,
+            })),
+            None,
+        )
     }
 
     pub fn typ(&self) -> ScopeType {
@@ -202,7 +212,13 @@ impl<'ast, 'src> Scope<'ast, 'src> {
     }
 
     pub fn code(&self) -> Option<&'src ParseCtx<'src>> {
-        self.inner().code.get().copied()
+        self.inner().code.or(self.1)
+    }
+
+    /// Returns a handle to the same scope that uses the given parse context, e.g. for
+    /// registering the items of one of the files that make up a module.
+    pub fn with_code(&self, code: &'src ParseCtx<'src>) -> Self {
+        Scope(self.0.clone(), Some(code))
     }
 
     pub fn path(&self) -> Path<'ast> {
@@ -211,51 +227,48 @@ impl<'ast, 'src> Scope<'ast, 'src> {
 
     pub fn named_child(&self, r#type: ScopeType, name: &'ast str) -> Self {
         let new_path = self.0.borrow().path.extend(PathSegment(name));
-        let code = self.0.borrow().code.clone();
-
-        Scope(Rc::new(RefCell::new(ScopeInner {
-            r#type,
-            path: new_path,
-            items: IndexMap::new(),
-            star_imports: Vec::new(),
-            code,
-            parent: Some(Rc::downgrade(&self.0)),
-        })))
+
+        Scope(
+            Rc::new(RefCell::new(ScopeInner {
+                r#type,
+                path: new_path,
+                items: IndexMap::new(),
+                star_imports: Vec::new(),
+                code: self.code(),
+                parent: Some(Rc::downgrade(&self.0)),
+            })),
+            None,
+        )
     }
 
     pub fn named_child_without_code(&self, r#type: ScopeType, name: &'ast str) -> Self {
         let new_path = self.0.borrow().path.extend(PathSegment(name));
 
-        Scope(Rc::new(RefCell::new(ScopeInner {
-            r#type,
-            path: new_path,
-            items: IndexMap::new(),
-            star_imports: Vec::new(),
-            code: OnceCell::new(),
-            parent: Some(Rc::downgrade(&self.0)),
-        })))
+        Scope(
+            Rc::new(RefCell::new(ScopeInner {
+                r#type,
+                path: new_path,
+                items: IndexMap::new(),
+                star_imports: Vec::new(),
+                code: None,
+                parent: Some(Rc::downgrade(&self.0)),
+            })),
+            None,
+        )
     }
 
     pub fn anonymous_child(&self, r#type: ScopeType) -> Self {
-        let code = self.0.borrow().code.clone();
-
-        Scope(Rc::new(RefCell::new(ScopeInner {
-            r#type,
-            path: self.path(),
-            items: IndexMap::new(),
-            star_imports: Vec::new(),
-            code,
-            parent: Some(Rc::downgrade(&self.0)),
-        })))
-    }
-
-    pub fn set_code(&self, code: &'src ParseCtx<'src>) {
-        if self.0.borrow().code.set(code).is_err() {
-            panic!(
-                "source code context is already set for {}",
-                self.0.borrow().path
-            );
-        }
+        Scope(
+            Rc::new(RefCell::new(ScopeInner {
+                r#type,
+                path: self.path(),
+                items: IndexMap::new(),
+                star_imports: Vec::new(),
+                code: self.code(),
+                parent: Some(Rc::downgrade(&self.0)),
+            })),
+            None,
+        )
     }
 
     pub fn add_item(
@@ -344,11 +357,13 @@ impl<'ast, 'src> Scope<'ast, 'src> {
         }
     }
 
+    /// The parent scope. If it is a module, the handle uses the parse context of this scope,
+    /// i.e. of the file in which this scope is declared.
     pub fn parent(&self) -> Option<Self> {
         self.inner()
             .parent
             .as_ref()
-            .map(|parent| Self(parent.upgrade().unwrap()))
+            .map(|parent| Self(parent.upgrade().unwrap(), self.code()))
     }
 
     pub fn ensure_module(&self, path: Path<'ast>) -> Result<Scope<'ast, 'src>, CodeErrorKind> {
@@ -368,18 +383,57 @@ impl<'ast, 'src> Scope<'ast, 'src> {
             segments: path.segments[1..].to_vec(),
         };
 
-        for item in self.inner().items_with_name(path.segments[0].0) {
+        self.ensure_child_module(path.segments[0].0, &[])?
+            .ensure_module(remainder)
+    }
+
+    /// Returns the child module with the given name, creating it if it does not exist yet.
+    /// A module can be declared in several places (as a file, inline with `mod`, or both), and
+    /// all of them share the same scope, so the items they declare are merged.
+    pub fn ensure_child_module(
+        &self,
+        name: &'ast str,
+        attributes: &'ast [Attribute],
+    ) -> Result<Scope<'ast, 'src>, CodeErrorKind> {
+        for item in self.inner().items_with_name(name) {
             if let NamedItemKind::Module(child_scope) = &item.kind {
-                return child_scope.ensure_module(remainder);
+                return Ok(child_scope.clone());
             }
         }
 
-        let child_scope = self.named_child_without_code(ScopeType::Module, path.segments[0].0);
+        let child_scope = self.named_child_without_code(ScopeType::Module, name);
         self.add_item(
-            Some(path.segments[0].0),
-            NamedItem::new_default(NamedItemKind::Module(child_scope.clone())),
+            Some(name),
+            NamedItem::new(NamedItemKind::Module(child_scope.clone()), attributes),
         )?;
 
-        child_scope.ensure_module(remainder)
+        Ok(child_scope)
+    }
+
+    /// Location of the item with the given name that is already in this scope, e.g. for
+    /// pointing at the other definition when a name is defined twice.
+    pub fn item_span(&self, name: &'ast str) -> Option<Span> {
+        let inner = self.inner();
+        let item = inner.items_with_name(name).next()?;
+
+        let (node, code) = match &item.kind {
+            NamedItemKind::Function(_, node, scope)
+            | NamedItemKind::Method(_, node, scope)
+            | NamedItemKind::TypeDef(_, node, scope)
+            | NamedItemKind::Static(_, node, scope)
+            | NamedItemKind::Macro(_, node, scope)
+            | NamedItemKind::Type(_, node, scope)
+            | NamedItemKind::Protocol(_, node, scope) => (*node, scope.code()?),
+            NamedItemKind::Alias(_, node, code) | NamedItemKind::Const(_, node, code) => {
+                (*node, *code)
+            }
+            NamedItemKind::EnumMember(_, _, node)
+            | NamedItemKind::Placeholder(_, node)
+            | NamedItemKind::Field(node)
+            | NamedItemKind::Parameter(_, node) => (*node, self.code()?),
+            _ => return None,
+        };
+
+        Some(code.span(node))
     }
 }
@@ -1,14 +1,15 @@
 use crate::{
     ast::Span,
-    common::{AluminaError, CodeError, CodeErrorKind, FileId, Marker},
+    common::{AluminaError, CodeError, CodeErrorBuilder, CodeErrorKind, FileId},
 };
 use once_cell::unsync::OnceCell;
 use std::marker::PhantomData;
-use tree_sitter::{Query, QueryCursor};
 
 include!(concat!(env!("OUT_DIR"), "/parser.rs"));
 
-static ERROR_QUERY: once_cell::sync::OnceCell<Query> = once_cell::sync::OnceCell::new();
+/// Maximum number of syntax errors reported for a single file. After the first few, the
+/// parser has usually lost track of the structure and the rest are not helpful.
+const MAX_SYNTAX_ERRORS: usize = 20;
 
 pub struct ParseCtx<'src> {
     source: String,
@@ -17,6 +18,14 @@ pub struct ParseCtx<'src> {
     _phantom: PhantomData<&'src ()>,
 }
 
+impl std::fmt::Debug for ParseCtx<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ParseCtx")
+            .field("file_id", &self.file_id)
+            .finish_non_exhaustive()
+    }
+}
+
 impl<'src> ParseCtx<'src> {
     pub fn from_source(file_id: FileId, source: String) -> Self {
         ParseCtx {
@@ -50,37 +59,151 @@ impl<'src> ParseCtx<'src> {
         }
     }
 
-    pub fn check_syntax_errors(
-        &'src self,
-        node: tree_sitter::Node<'src>,
-    ) -> Result<(), AluminaError> {
-        let mut cursor = QueryCursor::new();
-        let query = ERROR_QUERY.get_or_init(|| Query::new(language(), "(ERROR) @node").unwrap());
-
-        let matches = cursor.matches(query, node, self.source.as_bytes());
+    /// Collects the syntax errors (`ERROR` and `MISSING` nodes) in the tree under `node`, at
+    /// most `MAX_SYNTAX_ERRORS` of them. The nodes inside an `ERROR` node are not checked, as
+    /// they are usually just the tokens the parser was skipping over while recovering.
+    pub fn check_syntax_errors(&'src self, node: tree_sitter::Node<'src>) -> Vec<CodeError> {
         let mut errors = Vec::new();
-        for m in matches {
-            let error_node = m.nodes_for_capture_index(0).next().unwrap();
-            errors.push(CodeError {
-                kind: CodeErrorKind::ParseError(self.node_text(error_node).to_string()),
-                backtrace: vec![Marker::Span(Span {
-                    start: error_node.start_byte(),
-                    end: error_node.end_byte(),
-                    line: error_node.start_position().row,
-                    column: error_node.start_position().column,
-                    file: self.file_id,
-                })],
-            })
+        let mut remaining = 0;
+
+        let mut stack = vec![node];
+        while let Some(node) = stack.pop() {
+            if !node.has_error() {
+                continue;
+            }
+
+            let kind = if node.is_missing() {
+                CodeErrorKind::MissingSyntax(describe_kind(node))
+            } else if node.kind() == "ERROR" {
+                self.describe_error(node)
+            } else {
+                // Push in reverse, so that the errors are reported in source order.
+                let mut cursor = node.walk();
+                let children: Vec<_> = node.children(&mut cursor).collect();
+                stack.extend(children.into_iter().rev());
+                continue;
+            };
+
+            if errors.len() < MAX_SYNTAX_ERRORS {
+                errors.push(CodeError::from_kind(kind, Some(self.span(node))));
+            } else {
+                remaining += 1;
+            }
         }
 
-        if errors.is_empty() {
-            Ok(())
-        } else {
-            Err(AluminaError::CodeErrors(errors))
+        if remaining > 0 {
+            errors.push(CodeError::from_kind(
+                CodeErrorKind::TooManySyntaxErrors(remaining),
+                None,
+            ));
         }
+
+        errors
+    }
+
+    fn describe_error(&'src self, node: tree_sitter::Node<'src>) -> CodeErrorKind {
+        // The error node can span a large part of the file, so only the first token is shown.
+        let mut first = node;
+        while let Some(child) = first.child(0) {
+            first = child;
+        }
+        let found = self.node_text(first).to_string();
+
+        let expected = match node.parent().map(|p| p.kind()) {
+            Some(
+                "source_file"
+                | "top_level_block"
+                | "mod_definition"
+                | "impl_block"
+                | "protocol_definition",
+            ) => "an item",
+            Some("block") => "a statement or an expression",
+            Some("struct_definition") => "a field",
+            Some("enum_definition") => "an enum variant",
+            Some("parameter_list" | "macro_parameter_list" | "closure_parameters") => "a parameter",
+            Some("arguments") => "an argument",
+            Some("generic_argument_list") => "a generic parameter",
+            Some("type_arguments" | "tuple_type" | "parameter_type_list") => "a type",
+            Some("use_declaration" | "use_list" | "scoped_use_list") => "a path",
+            Some("attributes" | "top_level_attributes" | "meta_item" | "meta_arguments") => {
+                "an attribute"
+            }
+            Some("struct_expression" | "struct_initializer") => "a field initializer",
+            Some("switch_body") => "a switch arm",
+            Some(
+                "let_declaration"
+                | "static_declaration"
+                | "const_declaration"
+                | "expression_statement"
+                | "return_expression"
+                | "defer_expression"
+                | "unary_expression"
+                | "reference_expression"
+                | "dereference_expression"
+                | "binary_expression"
+                | "assignment_expression"
+                | "compound_assignment_expr"
+                | "index_expression"
+                | "range_expression"
+                | "parenthesized_expression"
+                | "tuple_expression"
+                | "array_expression"
+                | "if_expression"
+                | "while_expression"
+                | "for_expression"
+                | "pattern",
+            ) => "an expression",
+            _ => return CodeErrorKind::ParseError(found),
+        };
+
+        CodeErrorKind::UnexpectedSyntax(expected.to_string(), found)
+    }
+
+    pub fn span(&self, node: tree_sitter::Node<'src>) -> Span {
+        Span::new(self.file_id, node.byte_range())
     }
 
     pub fn node_text(&'src self, node: tree_sitter::Node<'src>) -> &'src str {
         &self.source[node.byte_range()]
     }
 }
+
+/// Describes the node that the parser expected to find in place of a `MISSING` node.
+fn describe_kind(node: tree_sitter::Node<'_>) -> String {
+    if node.is_named() {
+        node.kind().replace('_', " ")
+    } else {
+        format!("`{}`", node.kind())
+    }
+}
+
+pub trait NodeExt<'src> {
+    /// Returns the child in a field that is always present in well-formed code. Items with
+    /// syntax errors are skipped before the visitors run, so a missing field is a bug in the
+    /// compiler rather than in the code being compiled.
+    fn expect_child(&self, field: &'static str) -> Result<tree_sitter::Node<'src>, AluminaError>;
+
+    /// Internal error for a node of a kind that the visitor does not expect in its position.
+    fn unexpected<T>(&self) -> Result<T, AluminaError>;
+}
+
+impl<'src> NodeExt<'src> for tree_sitter::Node<'src> {
+    fn expect_child(&self, field: &'static str) -> Result<tree_sitter::Node<'src>, AluminaError> {
+        self.child_by_field_name(field)
+            .ok_or_else(|| {
+                CodeErrorKind::InternalError(
+                    format!("`{}` node has no `{}` field", self.kind(), field),
+                    backtrace::Backtrace::new(),
+                )
+            })
+            .with_no_span()
+    }
+
+    fn unexpected<T>(&self) -> Result<T, AluminaError> {
+        Err(CodeErrorKind::InternalError(
+            format!("unexpected `{}` node", self.kind()),
+            backtrace::Backtrace::new(),
+        ))
+        .with_no_span()
+    }
+}
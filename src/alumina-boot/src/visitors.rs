@@ -1,14 +1,17 @@
 use tree_sitter::Node;
 
 use crate::ast::expressions::parse_string_literal;
-use crate::ast::{AstCtx, Attribute, CodegenType, ItemP, TestMetadata};
-use crate::common::{AluminaError, ArenaAllocatable, CodeErrorKind, WithSpanDuringParsing};
-
-use crate::global_ctx::GlobalCtx;
+use crate::ast::lang::LangItemKind;
+use crate::ast::{AstCtx, Attribute, CodegenType, ItemP, Span, TestMetadata};
+use crate::common::{
+    AluminaError, ArenaAllocatable, CodeErrorBuilder, CodeErrorKind, WithSpanDuringParsing,
+};
+
+use crate::global_ctx::{CfgSet, GlobalCtx};
+use crate::incremental::{Decoder, Encoder};
 use crate::name_resolution::path::{Path, PathSegment};
 use crate::name_resolution::scope::{NamedItem, NamedItemKind, Scope};
-use crate::parser::AluminaVisitor;
-use crate::parser::ParseCtx;
+use crate::parser::{AluminaVisitor, NodeExt, ParseCtx};
 
 pub struct ScopedPathVisitor<'ast, 'src> {
     ast: &'ast AstCtx<'ast>,
@@ -104,7 +107,7 @@ impl<'ast, 'src> AluminaVisitor<'src> for ScopedPathVisitor<'ast, 'src> {
 
         let name = self
             .code
-            .node_text(node.child_by_field_name("name").unwrap())
+            .node_text(node.expect_child("name")?)
             .alloc_on(self.ast);
 
         Ok(subpath.extend(PathSegment(name)))
@@ -122,7 +125,7 @@ impl<'ast, 'src> AluminaVisitor<'src> for ScopedPathVisitor<'ast, 'src> {
 
         let name = self
             .code
-            .node_text(node.child_by_field_name("name").unwrap())
+            .node_text(node.expect_child("name")?)
             .alloc_on(self.ast);
 
         Ok(subpath.extend(PathSegment(name)))
@@ -167,17 +170,17 @@ impl<'ast, 'src> AluminaVisitor<'src> for UseClauseVisitor<'ast, 'src> {
     type ReturnType = Result<(), AluminaError>;
 
     fn visit_use_as_clause(&mut self, node: Node<'src>) -> Result<(), AluminaError> {
-        let path = self.parse_use_path(node.child_by_field_name("path").unwrap())?;
+        let path = self.parse_use_path(node.expect_child("path")?)?;
         let alias = self
             .code
-            .node_text(node.child_by_field_name("alias").unwrap())
+            .node_text(node.expect_child("alias")?)
             .alloc_on(self.ast);
 
         self.scope
             .add_item(
                 Some(alias),
                 NamedItem::new(
-                    NamedItemKind::Alias(self.prefix.join_with(path), node),
+                    NamedItemKind::Alias(self.prefix.join_with(path), node, self.code),
                     self.attributes,
                 ),
             )
@@ -191,11 +194,11 @@ impl<'ast, 'src> AluminaVisitor<'src> for UseClauseVisitor<'ast, 'src> {
     }
 
     fn visit_scoped_use_list(&mut self, node: Node<'src>) -> Result<(), AluminaError> {
-        let suffix = self.parse_use_path(node.child_by_field_name("path").unwrap())?;
+        let suffix = self.parse_use_path(node.expect_child("path")?)?;
         let new_prefix = self.prefix.join_with(suffix);
         let old_prefix = std::mem::replace(&mut self.prefix, new_prefix);
 
-        self.visit(node.child_by_field_name("list").unwrap())?;
+        self.visit(node.expect_child("list")?)?;
         self.prefix = old_prefix;
 
         Ok(())
@@ -207,7 +210,7 @@ impl<'ast, 'src> AluminaVisitor<'src> for UseClauseVisitor<'ast, 'src> {
             .add_item(
                 Some(alias),
                 NamedItem::new(
-                    NamedItemKind::Alias(self.prefix.extend(PathSegment(alias)), node),
+                    NamedItemKind::Alias(self.prefix.extend(PathSegment(alias)), node, self.code),
                     self.attributes,
                 ),
             )
@@ -217,7 +220,7 @@ impl<'ast, 'src> AluminaVisitor<'src> for UseClauseVisitor<'ast, 'src> {
     }
 
     fn visit_use_wildcard(&mut self, node: Node<'src>) -> Result<(), AluminaError> {
-        let path = self.parse_use_path(node.child_by_field_name("path").unwrap())?;
+        let path = self.parse_use_path(node.expect_child("path")?)?;
         self.scope.add_star_import(self.prefix.join_with(path));
 
         Ok(())
@@ -230,7 +233,7 @@ impl<'ast, 'src> AluminaVisitor<'src> for UseClauseVisitor<'ast, 'src> {
         };
         let name = self
             .code
-            .node_text(node.child_by_field_name("name").unwrap())
+            .node_text(node.expect_child("name")?)
             .alloc_on(self.ast);
 
         self.scope
@@ -240,6 +243,7 @@ impl<'ast, 'src> AluminaVisitor<'src> for UseClauseVisitor<'ast, 'src> {
                     NamedItemKind::Alias(
                         self.prefix.join_with(path.extend(PathSegment(name))),
                         node,
+                        self.code,
                     ),
                     self.attributes,
                 ),
@@ -250,79 +254,222 @@ impl<'ast, 'src> AluminaVisitor<'src> for UseClauseVisitor<'ast, 'src> {
     }
 }
 
-pub struct AttributeVisitor<'ast, 'src> {
-    global_ctx: GlobalCtx,
-    ast: &'ast AstCtx<'ast>,
-    code: &'src ParseCtx<'src>,
-    scope: Scope<'ast, 'src>,
-    item: Option<ItemP<'ast>>,
+/// Attributes of an item, parsed without access to the AST context so that this can also be
+/// done on the worker threads of the front end. Lang items and tests can only be registered
+/// once the item has a symbol, see [`ParsedAttributes::register`].
+#[derive(Debug, Default)]
+pub struct ParsedAttributes {
     attributes: Vec<Attribute>,
-    should_skip: bool,
+    lang_items: Vec<(String, Span)>,
     test_attributes: Vec<String>,
 }
 
-impl<'ast, 'src> AttributeVisitor<'ast, 'src> {
-    pub fn parse_attributes(
-        global_ctx: GlobalCtx,
+impl ParsedAttributes {
+    pub fn register<'ast, 'src>(
+        self,
         ast: &'ast AstCtx<'ast>,
-        scope: Scope<'ast, 'src>,
+        scope: &Scope<'ast, 'src>,
         node: Node<'src>,
         item: Option<ItemP<'ast>>,
-    ) -> Result<Option<&'ast [Attribute]>, AluminaError> {
-        let mut visitor = AttributeVisitor {
-            global_ctx,
-            ast,
-            code: scope
+    ) -> Result<&'ast [Attribute], AluminaError> {
+        let mut attributes = self.attributes;
+
+        for (name, span) in self.lang_items {
+            let kind: LangItemKind = name.as_str().try_into().with_span(Some(span))?;
+            let item = item
+                .ok_or(CodeErrorKind::CannotBeALangItem)
+                .with_span(Some(span))?;
+
+            let mut name = scope.path();
+            if let Some(item_name) = node.child_by_field_name("name") {
+                let code = scope
+                    .code()
+                    .expect("cannot run on scope without parse context");
+                name = name.extend(PathSegment(code.node_text(item_name).alloc_on(ast)));
+            }
+
+            ast.add_lang_item(kind, item, name.to_string())
+                .with_span(Some(span))?;
+        }
+
+        if !self.test_attributes.is_empty() {
+            let code = scope
                 .code()
-                .expect("cannot run on scope without parse context"),
-            scope,
-            item,
-            attributes: Vec::new(),
+                .expect("cannot run on scope without parse context");
+
+            ast.require_lang_items(
+                &[LangItemKind::TestCaseMeta, LangItemKind::TestCaseMetaNew],
+                Some(code.span(node)),
+            );
+
+            ast.add_test_metadata(
+                item.ok_or(CodeErrorKind::CannotBeATest)
+                    .with_span_from(scope, node)?,
+                TestMetadata {
+                    attributes: self.test_attributes,
+                    path: scope.path(),
+                    name: Path::from(PathSegment(
+                        code.node_text(
+                            node.child_by_field_name("name")
+                                .ok_or(CodeErrorKind::CannotBeATest)
+                                .with_span_from(scope, node)?,
+                        )
+                        .alloc_on(ast),
+                    )),
+                },
+            );
+            attributes.push(Attribute::Test);
+        }
+
+        Ok(attributes.alloc_on(ast))
+    }
+}
+
+impl ParsedAttributes {
+    pub fn encode(&self, encoder: &mut Encoder) {
+        encoder.write_usize(self.attributes.len());
+        for attribute in &self.attributes {
+            match attribute {
+                Attribute::Export => encoder.write_u8(0),
+                Attribute::Test => encoder.write_u8(1),
+                Attribute::Cold => encoder.write_u8(2),
+                Attribute::TestMain => encoder.write_u8(3),
+                Attribute::Inline => encoder.write_u8(4),
+                Attribute::Align(align) => {
+                    encoder.write_u8(5);
+                    encoder.write_u32(*align);
+                }
+                Attribute::NoInline => encoder.write_u8(6),
+                Attribute::ThreadLocal => encoder.write_u8(7),
+                Attribute::Builtin => encoder.write_u8(8),
+                Attribute::ForceInline => encoder.write_u8(9),
+                Attribute::Intrinsic => encoder.write_u8(10),
+                Attribute::StaticConstructor => encoder.write_u8(11),
+                Attribute::Codegen(CodegenType::CMain) => encoder.write_u8(12),
+                Attribute::LinkName(len, bytes) => {
+                    encoder.write_u8(13);
+                    encoder.write_str(std::str::from_utf8(&bytes[..*len]).unwrap());
+                }
+                Attribute::DeriveDebug => encoder.write_u8(14),
+            }
+        }
+
+        encoder.write_usize(self.lang_items.len());
+        for (name, span) in &self.lang_items {
+            encoder.write_str(name);
+            encoder.write_u32(span.start);
+            encoder.write_u32(span.end);
+        }
+
+        encoder.write_usize(self.test_attributes.len());
+        for test_attribute in &self.test_attributes {
+            encoder.write_str(test_attribute);
+        }
+    }
+
+    pub fn decode(decoder: &mut Decoder<'_>) -> Option<Self> {
+        let mut result = ParsedAttributes::default();
+
+        for _ in 0..decoder.read_len()? {
+            let attribute = match decoder.read_u8()? {
+                0 => Attribute::Export,
+                1 => Attribute::Test,
+                2 => Attribute::Cold,
+                3 => Attribute::TestMain,
+                4 => Attribute::Inline,
+                5 => Attribute::Align(decoder.read_u32()?),
+                6 => Attribute::NoInline,
+                7 => Attribute::ThreadLocal,
+                8 => Attribute::Builtin,
+                9 => Attribute::ForceInline,
+                10 => Attribute::Intrinsic,
+                11 => Attribute::StaticConstructor,
+                12 => Attribute::Codegen(CodegenType::CMain),
+                13 => {
+                    let name = decoder.read_string()?;
+                    let mut val = [0; 255];
+                    val.get_mut(0..name.len())?.copy_from_slice(name.as_bytes());
+                    Attribute::LinkName(name.len(), val)
+                }
+                14 => Attribute::DeriveDebug,
+                _ => return None,
+            };
+            result.attributes.push(attribute);
+        }
+
+        for _ in 0..decoder.read_len()? {
+            let name = decoder.read_string()?;
+            let span = Span {
+                file: decoder.file_id(),
+                start: decoder.read_u32()?,
+                end: decoder.read_u32()?,
+            };
+            result.lang_items.push((name, span));
+        }
+
+        for _ in 0..decoder.read_len()? {
+            result.test_attributes.push(decoder.read_string()?);
+        }
+
+        Some(result)
+    }
+}
+
+pub struct AttributeVisitor<'a, 'src> {
+    cfg: &'a CfgSet,
+    code: &'src ParseCtx<'src>,
+    parsed: ParsedAttributes,
+    should_skip: bool,
+    // The node that the attributes are attached to
+    item: Node<'src>,
+}
+
+impl<'a, 'src> AttributeVisitor<'a, 'src> {
+    /// Parses the attributes of a node. Returns `None` if the node is disabled by a `#[cfg]`
+    /// attribute, which can appear anywhere in the attribute list.
+    pub fn parse(
+        cfg: &'a CfgSet,
+        code: &'src ParseCtx<'src>,
+        node: Node<'src>,
+    ) -> Result<Option<ParsedAttributes>, AluminaError> {
+        let mut visitor = AttributeVisitor {
+            cfg,
+            code,
+            parsed: ParsedAttributes::default(),
             should_skip: false,
-            test_attributes: Vec::new(),
+            item: node,
         };
 
         if let Some(node) = node.child_by_field_name("attributes") {
             visitor.visit(node)?;
         }
 
-        visitor.finalize(node)?;
-
         if visitor.should_skip {
             Ok(None)
         } else {
-            Ok(Some(visitor.attributes.alloc_on(ast)))
+            Ok(Some(visitor.parsed))
         }
     }
 
-    fn finalize(&mut self, node: tree_sitter::Node<'src>) -> Result<(), AluminaError> {
-        if !self.test_attributes.is_empty() {
-            self.ast.add_test_metadata(
-                self.item
-                    .ok_or(CodeErrorKind::CannotBeATest)
-                    .with_span_from(&self.scope, node)?,
-                TestMetadata {
-                    attributes: std::mem::take(&mut self.test_attributes),
-                    path: self.scope.path(),
-                    name: Path::from(PathSegment(
-                        self.code
-                            .node_text(
-                                node.child_by_field_name("name")
-                                    .ok_or(CodeErrorKind::CannotBeATest)
-                                    .with_span_from(&self.scope, node)?,
-                            )
-                            .alloc_on(self.ast),
-                    )),
-                },
-            );
-            self.attributes.push(Attribute::Test);
-        }
+    pub fn parse_attributes<'ast>(
+        global_ctx: GlobalCtx,
+        ast: &'ast AstCtx<'ast>,
+        scope: Scope<'ast, 'src>,
+        node: Node<'src>,
+        item: Option<ItemP<'ast>>,
+    ) -> Result<Option<&'ast [Attribute]>, AluminaError> {
+        let code = scope
+            .code()
+            .expect("cannot run on scope without parse context");
 
-        Ok(())
+        match AttributeVisitor::parse(&global_ctx.cfg_set(), code, node)? {
+            Some(parsed) => Ok(Some(parsed.register(ast, &scope, node, item)?)),
+            None => Ok(None),
+        }
     }
 }
 
-impl<'ast, 'src> AluminaVisitor<'src> for AttributeVisitor<'ast, 'src> {
+impl<'a, 'src> AluminaVisitor<'src> for AttributeVisitor<'a, 'src> {
     type ReturnType = Result<(), AluminaError>;
 
     fn visit_attributes(&mut self, node: tree_sitter::Node<'src>) -> Self::ReturnType {
@@ -341,14 +488,12 @@ impl<'ast, 'src> AluminaVisitor<'src> for AttributeVisitor<'ast, 'src> {
         self.visit_attribute_item(node)
     }
     fn visit_attribute_item(&mut self, node: Node<'src>) -> Self::ReturnType {
-        let inner = node.child_by_field_name("inner").unwrap();
+        let inner = node.expect_child("inner")?;
 
-        let name = self
-            .code
-            .node_text(inner.child_by_field_name("name").unwrap());
+        let name = self.code.node_text(inner.expect_child("name")?);
 
         match name {
-            "inline" => self.attributes.push(Attribute::Inline),
+            "inline" => self.parsed.attributes.push(Attribute::Inline),
             "align" => {
                 let align: u32 = inner
                     .child_by_field_name("arguments")
@@ -356,39 +501,55 @@ impl<'ast, 'src> AluminaVisitor<'src> for AttributeVisitor<'ast, 'src> {
                     .map(|n| self.code.node_text(n))
                     .and_then(|f| f.parse().ok())
                     .ok_or(CodeErrorKind::InvalidCfgAttribute)
-                    .with_span_from(&self.scope, node)?;
+                    .with_span_from_code(self.code, node)?;
 
-                self.attributes.push(Attribute::Align(align))
+                self.parsed.attributes.push(Attribute::Align(align))
             }
-            "cold" => self.attributes.push(Attribute::Cold),
-            "no_inline" => self.attributes.push(Attribute::NoInline),
-            "builtin" => self.attributes.push(Attribute::Builtin),
-            "export" => self.attributes.push(Attribute::Export),
-            "force_inline" => self.attributes.push(Attribute::ForceInline),
-            "thread_local" => {
-                // We can skip thread-local on programs that are compiled with threads
-                // disabled.
-                if self.global_ctx.has_flag("threading") {
-                    self.attributes.push(Attribute::ThreadLocal)
+            "cold" => self.parsed.attributes.push(Attribute::Cold),
+            "no_inline" => self.parsed.attributes.push(Attribute::NoInline),
+            "builtin" => self.parsed.attributes.push(Attribute::Builtin),
+            "export" => self.parsed.attributes.push(Attribute::Export),
+            "force_inline" => self.parsed.attributes.push(Attribute::ForceInline),
+            "thread_local" => self.parsed.attributes.push(Attribute::ThreadLocal),
+            "test_main" => self.parsed.attributes.push(Attribute::TestMain),
+            "derive" => {
+                if let Some(arguments) = inner.child_by_field_name("arguments") {
+                    let mut cursor = arguments.walk();
+                    for argument in arguments.children_by_field_name("argument", &mut cursor) {
+                        match self.code.node_text(argument) {
+                            "debug" if self.item.kind() == "enum_definition" => {
+                                self.parsed.attributes.push(Attribute::DeriveDebug)
+                            }
+                            "debug" => {
+                                return Err(CodeErrorKind::DeriveOnNonEnum("debug".to_string()))
+                                    .with_span_from_code(self.code, argument)
+                            }
+                            name => {
+                                return Err(CodeErrorKind::UnknownDerive(name.to_string()))
+                                    .with_span_from_code(self.code, argument)
+                            }
+                        }
+                    }
                 }
             }
-            "test_main" => self.attributes.push(Attribute::TestMain),
             "link_name" => {
                 let link_name = inner
                     .child_by_field_name("arguments")
                     .and_then(|n| n.child_by_field_name("argument"))
                     .ok_or(CodeErrorKind::UnknownLangItem(None))
-                    .with_span_from(&self.scope, inner)?;
+                    .with_span_from_code(self.code, inner)?;
 
                 let bytes = self.code.node_text(link_name).as_bytes();
 
                 let mut val = [0; 255];
                 val.as_mut_slice()[0..bytes.len()].copy_from_slice(bytes);
 
-                self.attributes.push(Attribute::LinkName(bytes.len(), val));
+                self.parsed
+                    .attributes
+                    .push(Attribute::LinkName(bytes.len(), val));
             }
             "test" => {
-                self.test_attributes.push(
+                self.parsed.test_attributes.push(
                     inner
                         .child_by_field_name("arguments")
                         .map(|s| self.code.node_text(s))
@@ -397,7 +558,7 @@ impl<'ast, 'src> AluminaVisitor<'src> for AttributeVisitor<'ast, 'src> {
                 );
             }
             "cfg" => {
-                let mut cfg_visitor = CfgVisitor::new(self.global_ctx.clone(), self.scope.clone());
+                let mut cfg_visitor = CfgVisitor::new(self.cfg, self.code);
                 if !cfg_visitor.visit(inner)? {
                     self.should_skip = true;
                 }
@@ -407,17 +568,13 @@ impl<'ast, 'src> AluminaVisitor<'src> for AttributeVisitor<'ast, 'src> {
                     .child_by_field_name("arguments")
                     .and_then(|n| n.child_by_field_name("argument"))
                     .ok_or(CodeErrorKind::UnknownLangItem(None))
-                    .with_span_from(&self.scope, inner)?;
-
-                self.ast.add_lang_item(
-                    self.code
-                        .node_text(lang_type)
-                        .try_into()
-                        .with_span_from(&self.scope, inner)?,
-                    self.item
-                        .ok_or(CodeErrorKind::CannotBeALangItem)
-                        .with_span_from(&self.scope, inner)?,
-                );
+                    .with_span_from_code(self.code, inner)?;
+
+                let span = self.code.span(inner);
+
+                self.parsed
+                    .lang_items
+                    .push((self.code.node_text(lang_type).to_string(), span));
             }
             "codegen" => {
                 if let Some(argument) = inner
@@ -426,7 +583,10 @@ impl<'ast, 'src> AluminaVisitor<'src> for AttributeVisitor<'ast, 'src> {
                 {
                     let codegen_type = self.code.node_text(argument);
                     match codegen_type {
-                        "c_main" => self.attributes.push(Attribute::Codegen(CodegenType::CMain)),
+                        "c_main" => self
+                            .parsed
+                            .attributes
+                            .push(Attribute::Codegen(CodegenType::CMain)),
                         _ => {}
                     }
                 }
@@ -446,33 +606,27 @@ enum State {
     Not,
 }
 
-pub struct CfgVisitor<'ast, 'src> {
-    global_ctx: GlobalCtx,
+pub struct CfgVisitor<'a, 'src> {
+    cfg: &'a CfgSet,
     code: &'src ParseCtx<'src>,
-    scope: Scope<'ast, 'src>,
     state: Vec<State>,
 }
 
-impl<'ast, 'src> CfgVisitor<'ast, 'src> {
-    pub fn new(global_ctx: GlobalCtx, scope: Scope<'ast, 'src>) -> Self {
+impl<'a, 'src> CfgVisitor<'a, 'src> {
+    pub fn new(cfg: &'a CfgSet, code: &'src ParseCtx<'src>) -> Self {
         CfgVisitor {
-            global_ctx,
-            code: scope
-                .code()
-                .expect("cannot run on scope without parse context"),
-            scope,
+            cfg,
+            code,
             state: vec![],
         }
     }
 }
 
-impl<'ast, 'src> AluminaVisitor<'src> for CfgVisitor<'ast, 'src> {
+impl<'a, 'src> AluminaVisitor<'src> for CfgVisitor<'a, 'src> {
     type ReturnType = Result<bool, AluminaError>;
 
     fn visit_meta_item(&mut self, node: Node<'src>) -> Self::ReturnType {
-        let name = self
-            .code
-            .node_text(node.child_by_field_name("name").unwrap());
+        let name = self.code.node_text(node.expect_child("name")?);
 
         if let Some(arguments) = node.child_by_field_name("arguments") {
             let ret = match name {
@@ -494,7 +648,7 @@ impl<'ast, 'src> AluminaVisitor<'src> for CfgVisitor<'ast, 'src> {
                 }
                 _ => {
                     return Err(CodeErrorKind::InvalidCfgAttribute)
-                        .with_span_from(&self.scope, node)
+                        .with_span_from_code(self.code, node)
                 }
             };
             self.state.pop();
@@ -505,9 +659,9 @@ impl<'ast, 'src> AluminaVisitor<'src> for CfgVisitor<'ast, 'src> {
                 .map(|n| self.code.node_text(n))
                 .map(parse_string_literal)
                 .transpose()
-                .with_span_from(&self.scope, node)?;
+                .with_span_from_code(self.code, node)?;
 
-            let actual = self.global_ctx.cfg(name);
+            let actual = self.cfg.get(name);
 
             let matches = match (expected, actual) {
                 (Some(value), Some(Some(cfg))) => cfg == std::str::from_utf8(&value).unwrap(),
@@ -531,7 +685,7 @@ impl<'ast, 'src> AluminaVisitor<'src> for CfgVisitor<'ast, 'src> {
                 State::Single | State::Not => {
                     if iter.next().is_some() {
                         return Err(CodeErrorKind::InvalidCfgAttribute)
-                            .with_span_from(&self.scope, node);
+                            .with_span_from_code(self.code, node);
                     }
                     return Ok(matches == matches!(state, State::Single));
                 }
@@ -550,7 +704,7 @@ impl<'ast, 'src> AluminaVisitor<'src> for CfgVisitor<'ast, 'src> {
 
         match state {
             State::Single | State::Not => {
-                Err(CodeErrorKind::InvalidCfgAttribute).with_span_from(&self.scope, node)
+                Err(CodeErrorKind::InvalidCfgAttribute).with_span_from_code(self.code, node)
             }
             State::All => Ok(true),
             State::Any => Ok(false),
@@ -0,0 +1,684 @@
+//! Tests of the library interface of the compiler with sources that do not come from disk.
+
+use std::path::Path;
+
+use alumina_boot::common::{CodeErrorKind, STACK_PER_NESTING_LEVEL};
+use alumina_boot::compiler::{CodegenDialect, Compiler, CompilerOptions, OptionalWarning};
+use alumina_boot::diagnostics::Level;
+
+fn compiler() -> Compiler {
+    let sysroot = Path::new(env!("CARGO_MANIFEST_DIR")).join("../../sysroot");
+
+    let mut compiler = Compiler::new(CompilerOptions::default());
+    compiler.add_sysroot(&sysroot).unwrap();
+    compiler
+}
+
+#[test]
+fn compile_from_memory() {
+    let mut compiler = compiler();
+    compiler.add_source("main", "fn main() { println!(\"hello\"); }");
+
+    let output = compiler.compile().unwrap();
+    assert!(output.program.contains("main"));
+    assert!(output.diagnostics.iter().all(|d| d.level != Level::Error));
+}
+
+#[test]
+fn modules_from_memory() {
+    let mut compiler = compiler();
+    compiler.add_source("greeting", "fn greet() -> &[u8] { \"hello\" }");
+    compiler.add_source("main", "fn main() { println!(\"{}\", greeting::greet()); }");
+
+    assert!(compiler.compile().is_ok());
+}
+
+#[test]
+fn errors_are_reported_as_diagnostics() {
+    let mut compiler = compiler();
+    compiler.add_source("main", "fn main() {\n    let x: i32 = \"wrong\";\n}\n");
+
+    let diagnostics = compiler.compile().unwrap_err();
+    let error = diagnostics
+        .iter()
+        .find(|d| d.level == Level::Error)
+        .expect("an error");
+
+    assert!(matches!(error.kind, CodeErrorKind::TypeMismatch(_, _)));
+    assert_eq!(error.locations[0].file, Path::new("main"));
+    assert_eq!(error.locations[0].line, 2);
+}
+
+// The tests below run on the threads of the test harness, which have a small stack, as the
+// compilation runs on its own thread.
+
+#[test]
+fn deeply_nested_expression() {
+    let depth = 10_000;
+    let source = format!(
+        "fn main() {{\n    let _x = {}1{};\n}}\n",
+        "(".repeat(depth),
+        ")".repeat(depth)
+    );
+
+    let mut compiler = compiler();
+    compiler.add_source("main", source);
+
+    let diagnostics = compiler.compile().unwrap_err();
+    assert!(diagnostics.iter().any(|d| {
+        matches!(d.kind, CodeErrorKind::TooDeeplyNested)
+            && d.locations.first().is_some_and(|l| l.line == 2)
+    }));
+}
+
+#[test]
+fn nesting_limit_follows_the_stack_size() {
+    // References need the most stack per level of nesting.
+    let nested_references = |depth: usize| {
+        let sysroot = Path::new(env!("CARGO_MANIFEST_DIR")).join("../../sysroot");
+        let mut compiler = Compiler::new(CompilerOptions {
+            stack_size: 8,
+            ..Default::default()
+        });
+        compiler.add_sysroot(&sysroot).unwrap();
+        compiler.add_source(
+            "main",
+            format!("fn main() {{\n    let _x = {}1;\n}}\n", "&".repeat(depth)),
+        );
+        compiler.compile()
+    };
+
+    let limit = 8 * 1024 * 1024 / STACK_PER_NESTING_LEVEL;
+    assert!(nested_references(limit - 10).is_ok());
+
+    let diagnostics = nested_references(2400).unwrap_err();
+    assert!(diagnostics
+        .iter()
+        .any(|d| matches!(d.kind, CodeErrorKind::TooDeeplyNested)));
+}
+
+#[test]
+fn long_else_if_chain() {
+    let mut source = "fn classify(x: i32) -> i32 {\n    if x == 0 {\n        0\n    }".to_string();
+    for i in 1..2000 {
+        source += &format!(" else if x == {} {{\n        {}\n    }}", i, i);
+    }
+    source += " else {\n        -1\n    }\n}\n\nfn main() {\n    classify(1999);\n}\n";
+
+    let mut compiler = compiler();
+    compiler.add_source("main", source);
+
+    let output = compiler.compile().unwrap();
+    assert!(output.diagnostics.iter().all(|d| d.level != Level::Error));
+}
+
+#[test]
+fn static_linkage_and_thread_locals() {
+    let sysroot = Path::new(env!("CARGO_MANIFEST_DIR")).join("../../sysroot");
+    let mut compiler = Compiler::new(CompilerOptions {
+        cfg: vec![("threading".to_string(), None)],
+        ..Default::default()
+    });
+    compiler.add_sysroot(&sysroot).unwrap();
+    compiler.add_source(
+        "main",
+        "#[thread_local] static COUNTER: i32 = 42;\n\
+         #[export] static exported_counter: i32;\n\
+         fn main() {\n    let _p = &COUNTER;\n    exported_counter = COUNTER;\n}\n",
+    );
+
+    let program = compiler.compile().unwrap().program;
+    assert!(program.contains("_Thread_local int32_t"));
+    assert!(program.contains("\nint32_t exported_counter;"));
+}
+
+#[test]
+fn dead_code_warnings() {
+    let sysroot = Path::new(env!("CARGO_MANIFEST_DIR")).join("../../sysroot");
+    let mut compiler = Compiler::new(CompilerOptions {
+        warnings: vec![OptionalWarning::DeadCode],
+        ..Default::default()
+    });
+    compiler.add_sysroot(&sysroot).unwrap();
+    compiler.add_source(
+        "main",
+        "fn used() -> i32 { LIMIT }\n\
+         fn unused() {}\n\
+         const LIMIT: i32 = 10;\n\
+         const UNUSED_LIMIT: i32 = 20;\n\
+         static UNUSED_STATIC: i32 = 0;\n\
+         fn unused_generic<T>(_t: T) {}\n\
+         #[export] fn exported() {}\n\
+         extern \"C\" fn abs(x: i32) -> i32;\n\
+         fn main() {\n    used();\n}\n",
+    );
+
+    let diagnostics = compiler.compile().unwrap().diagnostics;
+    let mut reported: Vec<_> = diagnostics
+        .iter()
+        .filter_map(|d| match &d.kind {
+            CodeErrorKind::DeadCode(_, name) => Some((Level::Warning, name.clone(), d.level)),
+            CodeErrorKind::UnusedGeneric(_, name) => Some((Level::Note, name.clone(), d.level)),
+            _ => None,
+        })
+        .map(|(expected, name, level)| {
+            assert_eq!(expected, level);
+            name
+        })
+        .collect();
+    reported.sort();
+
+    assert_eq!(
+        reported,
+        ["UNUSED_LIMIT", "UNUSED_STATIC", "unused", "unused_generic"]
+    );
+}
+
+#[test]
+fn dead_code_warnings_in_tests() {
+    let sysroot = Path::new(env!("CARGO_MANIFEST_DIR")).join("../../sysroot");
+    let mut compiler = Compiler::new(CompilerOptions {
+        cfg: vec![("test".to_string(), None)],
+        warnings: vec![OptionalWarning::DeadCode],
+        ..Default::default()
+    });
+    compiler.add_sysroot(&sysroot).unwrap();
+    compiler.add_source(
+        "main",
+        "fn used_by_main() {}\n\
+         fn used_by_test() {}\n\
+         fn unused() {}\n\
+         fn main() {\n    used_by_main();\n}\n\
+         #[test]\nfn test() {\n    used_by_test();\n}\n",
+    );
+
+    // The test runner replaces `main`, but neither it nor what it uses is dead code.
+    let diagnostics = compiler.compile().unwrap().diagnostics;
+    let reported: Vec<_> = diagnostics
+        .iter()
+        .filter_map(|d| match &d.kind {
+            CodeErrorKind::DeadCode(_, name) => Some(name.as_str()),
+            _ => None,
+        })
+        .collect();
+
+    assert_eq!(reported, ["unused"]);
+}
+
+#[test]
+fn dead_code_warnings_are_opt_in() {
+    let mut compiler = compiler();
+    compiler.add_source("main", "fn unused() {}\nfn main() {}\n");
+
+    let diagnostics = compiler.compile().unwrap().diagnostics;
+    assert!(diagnostics
+        .iter()
+        .all(|d| !matches!(d.kind, CodeErrorKind::DeadCode(_, _))));
+}
+
+fn is_syntax_error(kind: &CodeErrorKind) -> bool {
+    matches!(
+        kind,
+        CodeErrorKind::ParseError(_)
+            | CodeErrorKind::UnexpectedSyntax(_, _)
+            | CodeErrorKind::MissingSyntax(_)
+            | CodeErrorKind::TooManySyntaxErrors(_)
+    )
+}
+
+#[test]
+fn syntax_errors_do_not_hide_other_errors() {
+    let mut compiler = compiler();
+    compiler.add_source(
+        "main",
+        "fn broken() {\n    let x = ;\n}\n\nfn foo() {}\nfn foo() {}\n\nfn main() {}\n",
+    );
+
+    let diagnostics = compiler.compile().unwrap_err();
+    let errors: Vec<_> = diagnostics
+        .iter()
+        .filter(|d| d.level == Level::Error)
+        .collect();
+
+    // The syntax error in `broken` is reported first, followed by the errors in the items
+    // that were still processed.
+    assert!(is_syntax_error(&errors[0].kind));
+    assert_eq!(errors[0].locations[0].line, 2);
+    assert!(errors.iter().any(|d| {
+        matches!(d.kind, CodeErrorKind::DuplicateName(_))
+            && d.locations.first().is_some_and(|l| l.line == 6)
+    }));
+}
+
+#[test]
+fn syntax_errors_are_bounded() {
+    let mut compiler = compiler();
+    compiler.add_source("main", "fn f( { let = ; ) ] @ \n".repeat(1000));
+
+    let diagnostics = compiler.compile().unwrap_err();
+    let syntax_errors = diagnostics
+        .iter()
+        .filter(|d| is_syntax_error(&d.kind))
+        .count();
+
+    // At most 20 syntax errors per file, and one more saying how many were not shown.
+    assert!(syntax_errors > 0);
+    assert!(syntax_errors <= 21);
+}
+
+#[test]
+fn module_split_across_files() {
+    let inline = "mod util {\n    fn c() -> i32 { 3 }\n}\n\
+                  fn main() {\n    println!(\"{}\", util::a() + util::b() + util::c());\n}\n";
+
+    // `main::util` is declared inline in `main` and also mounted from two files. The module is
+    // the same no matter whether the inline declaration comes first or last.
+    for inline_first in [false, true] {
+        let mut compiler = compiler();
+        if inline_first {
+            compiler.add_source("main", inline);
+        }
+        compiler.add_source("main::util", "fn a() -> i32 { 1 }");
+        compiler.add_source("main::util", "fn b() -> i32 { a() + 1 }");
+        if !inline_first {
+            compiler.add_source("main", inline);
+        }
+
+        let output = compiler.compile().unwrap();
+        assert!(output.diagnostics.iter().all(|d| d.level != Level::Error));
+    }
+}
+
+#[test]
+fn conflicts_in_merged_modules() {
+    let mut compiler = compiler();
+    compiler.add_source("main::util", "fn a() -> i32 { 1 }\nfn b() -> i32 { 1 }\n");
+    compiler.add_source(
+        "main",
+        "mod util {\n    fn a() -> i32 { 2 }\n    fn b() -> i32 { 2 }\n}\nfn main() {}\n",
+    );
+
+    let diagnostics = compiler.compile().unwrap_err();
+
+    // Each conflicting item is reported, along with the previous definition of the name.
+    let errors: Vec<_> = diagnostics
+        .iter()
+        .filter(|d| d.level == Level::Error)
+        .collect();
+    assert_eq!(errors.len(), 2);
+
+    for (error, line) in errors.iter().zip([2, 3]) {
+        assert!(matches!(error.kind, CodeErrorKind::DuplicateName(_)));
+        assert_eq!(error.locations[0].file, Path::new("main"));
+        assert_eq!(error.locations[0].line, line);
+
+        assert_eq!(error.notes.len(), 1);
+        let note = &error.notes[0];
+        assert!(matches!(note.kind, CodeErrorKind::PreviousDefinition(_)));
+        assert_eq!(note.level, Level::Note);
+        assert_eq!(note.locations[0].file, Path::new("main::util"));
+        assert_eq!(note.locations[0].line, line - 1);
+    }
+}
+
+#[test]
+fn constant_folding_reduces_output() {
+    // Uses a good part of the standard library, which is full of checks that only apply to
+    // some of the types it is instantiated with.
+    let source = "use std::collections::{HashMap, Vector};\n\
+                  fn main() {\n    \
+                  let v: Vector<u64> = Vector::new();\n    \
+                  defer v.free();\n    \
+                  let m: HashMap<i32, &[u8]> = HashMap::new();\n    \
+                  defer m.free();\n    \
+                  for i in 0..10 {\n        \
+                  v.push((i as u64) << 2);\n        \
+                  m.insert(i, \"value\");\n    \
+                  }\n    \
+                  println!(\"{} {}\", v.len(), m.len());\n\
+                  }\n";
+
+    let compile = |optimize| {
+        let sysroot = Path::new(env!("CARGO_MANIFEST_DIR")).join("../../sysroot");
+        let mut compiler = Compiler::new(CompilerOptions {
+            optimize,
+            ..Default::default()
+        });
+        compiler.add_sysroot(&sysroot).unwrap();
+        compiler.add_source("main", source);
+
+        // The length of the generated names varies between runs, so the lines are counted
+        compiler.compile().unwrap().program.lines().count()
+    };
+
+    let plain = compile(false);
+    let optimized = compile(true);
+    assert!(
+        optimized < plain,
+        "{} lines with --opt, {} without",
+        optimized,
+        plain
+    );
+}
+
+#[test]
+fn all_expressions_have_spans() {
+    // Also covers the synthesized code, e.g. the static constructor, defers, loops, drops and
+    // the test runner glue.
+    let source = "use std::collections::Vector;\n\
+                  static TOTAL: i32 = 10 + 20;\n\
+                  struct Guard { value: i32 }\n\
+                  impl Guard {\n    \
+                  fn drop(self: &mut Guard) { self.value = 0; }\n\
+                  }\n\
+                  fn take(_g: Guard) {}\n\
+                  fn early(flag: bool) -> i32 {\n    \
+                  let g = Guard { value: 1 };\n    \
+                  if flag {\n        \
+                  return g.value;\n    \
+                  }\n    \
+                  take(g);\n    \
+                  let _h = Guard { value: 2 };\n    \
+                  2\n\
+                  }\n\
+                  fn main() {\n    \
+                  let v: Vector<i32> = Vector::new();\n    \
+                  defer v.free();\n    \
+                  for i in 0..TOTAL {\n        \
+                  v.push(i);\n    \
+                  }\n    \
+                  let f = |x: i32| -> i32 { x * 2 };\n    \
+                  println!(\"{} {} {}\", v.len(), f(TOTAL), early(true));\n\
+                  }\n\
+                  #[cfg(test)]\n\
+                  #[test]\n\
+                  fn test_early() {\n    \
+                  assert_eq!(early(false), 2);\n\
+                  }\n";
+
+    for cfg in [&[][..], &["test"]] {
+        let sysroot = Path::new(env!("CARGO_MANIFEST_DIR")).join("../../sysroot");
+        let mut compiler = Compiler::new(CompilerOptions {
+            cfg: cfg.iter().map(|flag| (flag.to_string(), None)).collect(),
+            options: vec!["verify-spans".to_string()],
+            ..Default::default()
+        });
+        compiler.add_sysroot(&sysroot).unwrap();
+        compiler.add_source("main", source);
+
+        let output = compiler.compile().unwrap();
+        let errors: Vec<_> = output
+            .diagnostics
+            .iter()
+            .filter(|d| d.level == Level::Error)
+            .map(|d| d.kind.to_string())
+            .collect();
+        assert!(errors.is_empty(), "{:?}: {:?}", cfg, errors);
+    }
+}
+
+#[test]
+fn c89_dialect_avoids_newer_keywords() {
+    let source = "#[thread_local] static COUNTER: i32 = 1;\n\
+                  #[inline] fn get() -> i32 { COUNTER }\n\
+                  fn fail() -> ! { std::process::exit(1) }\n\
+                  fn main() {\n    \
+                  if get() != std::mem::align_of::<i32>() as i32 - 3 {\n        \
+                  fail();\n    \
+                  }\n\
+                  }\n";
+
+    let compile = |dialect| {
+        let sysroot = Path::new(env!("CARGO_MANIFEST_DIR")).join("../../sysroot");
+        let mut compiler = Compiler::new(CompilerOptions {
+            cfg: vec![("threading".to_string(), None)],
+            dialect,
+            ..Default::default()
+        });
+        compiler.add_sysroot(&sysroot).unwrap();
+        compiler.add_source("main", source);
+        compiler.compile().unwrap().program
+    };
+
+    let c11 = compile(CodegenDialect::C11);
+    assert!(c11.contains("_Thread_local"));
+    assert!(c11.contains("_Noreturn"));
+    assert!(c11.contains("_Alignof"));
+
+    let c89 = compile(CodegenDialect::C89);
+    for keyword in ["_Thread_local", "_Noreturn", "_Alignof", "inline static"] {
+        assert!(!c89.contains(keyword), "`{}` in C89 output", keyword);
+    }
+    assert!(c89.contains("static __thread int32_t"));
+}
+
+#[test]
+fn standard_library_compiles() {
+    let sysroot = Path::new(env!("CARGO_MANIFEST_DIR")).join("../../sysroot");
+    let mut compiler = Compiler::new(CompilerOptions {
+        cfg: ["test", "test_std", "threading"]
+            .iter()
+            .map(|flag| (flag.to_string(), None))
+            .collect(),
+        jobs: Some(4),
+        ..Default::default()
+    });
+    compiler.add_sysroot(&sysroot).unwrap();
+
+    let output = compiler.compile().unwrap();
+    assert!(output.diagnostics.iter().all(|d| d.level != Level::Error));
+}
+
+#[test]
+fn missing_lang_items_are_reported_together() {
+    // No standard library, so none of the lang items are registered
+    let mut compiler = Compiler::new(CompilerOptions {
+        cfg: vec![("test".to_string(), None)],
+        ..Default::default()
+    });
+    compiler.add_source(
+        "main",
+        "fn main() {\n    for _ in main {}\n}\n\n#[test]\nfn test() {}\n",
+    );
+
+    let diagnostics = compiler.compile().unwrap_err();
+    let missing: Vec<_> = diagnostics
+        .iter()
+        .filter_map(|d| match &d.kind {
+            CodeErrorKind::MissingLangItem(kind) => {
+                Some((kind.to_string(), d.locations.first().map(|l| l.line)))
+            }
+            _ => None,
+        })
+        .collect();
+
+    assert!(missing.contains(&("option".to_string(), Some(2))));
+    assert!(missing.contains(&("test_case_meta".to_string(), Some(5))));
+    assert!(missing.contains(&("test_case_meta_new".to_string(), Some(5))));
+}
+
+#[test]
+fn parallel_parsing_gives_the_same_program() {
+    let compile = |jobs| {
+        let mut compiler = compiler();
+        compiler.set_jobs(jobs);
+        for i in 0..16 {
+            compiler.add_source(format!("m{}", i), format!("fn f() -> i32 {{ {} }}", i));
+        }
+        compiler.add_source(
+            "main",
+            "fn main() {\n    println!(\"{}\", m3::f() + m11::f());\n}\n",
+        );
+        // The names and the order of the items in the output are not stable between runs,
+        // but the structure is.
+        compiler.compile().unwrap().program.lines().count()
+    };
+
+    let sequential = compile(1);
+    for jobs in [2, 8] {
+        assert_eq!(compile(jobs), sequential);
+    }
+}
+
+#[test]
+fn parallel_parsing_reports_errors_in_order() {
+    let compile = |jobs| {
+        let mut compiler = compiler();
+        compiler.set_jobs(jobs);
+        for i in 0..16 {
+            compiler.add_source(format!("m{}", i), "fn broken() {\n    let x = ;\n}\n");
+        }
+        compiler.add_source("main", "fn main() {}\n");
+
+        let diagnostics = compiler.compile().unwrap_err();
+        diagnostics
+            .iter()
+            .map(|d| {
+                let location = &d.locations[0];
+                format!("{:?} {}:{}", d.kind, location.file.display(), location.line)
+            })
+            .collect::<Vec<_>>()
+    };
+
+    // Syntax errors are reported in the order in which the files were added.
+    let sequential = compile(1);
+    assert_eq!(sequential.len(), 16);
+    for (i, error) in sequential.iter().enumerate() {
+        assert!(error.ends_with(&format!(" m{}:2", i)), "{}", error);
+    }
+
+    for _ in 0..4 {
+        assert_eq!(compile(8), sequential);
+    }
+}
+
+#[test]
+fn spans_of_lowered_expressions() {
+    // The uninitialized variable check runs on the IR, so the location comes from the span
+    // of the lowered expression rather than from the AST.
+    let source = "fn main() {\n    let x: i32;\n    let _y = 1 + x;\n}\n";
+
+    let mut compiler = compiler();
+    compiler.add_source("main", source);
+
+    let diagnostics = compiler.compile().unwrap_err();
+    let error = diagnostics
+        .iter()
+        .find(|d| matches!(d.kind, CodeErrorKind::PossiblyUninitialized(_)))
+        .expect("an error");
+
+    let location = &error.locations[0];
+    assert_eq!((location.line, location.column), (3, 18));
+    assert_eq!(location.bytes, 45..46);
+    assert_eq!(&source[location.bytes.clone()], "x");
+}
+
+#[test]
+fn uninitialized_use_notes_the_skipping_branch() {
+    let source = "fn pick(n: i32) -> i32 {\n    \
+                  let x: i32;\n    \
+                  if n > 5 {\n        \
+                  x = 1;\n    \
+                  }\n    \
+                  x\n\
+                  }\n\
+                  fn main() {\n    \
+                  pick(0);\n\
+                  }\n";
+
+    let mut compiler = compiler();
+    compiler.add_source("main", source);
+
+    let diagnostics = compiler.compile().unwrap_err();
+    let error = diagnostics
+        .iter()
+        .find(|d| matches!(d.kind, CodeErrorKind::PossiblyUninitialized(_)))
+        .expect("an error");
+    assert_eq!(error.locations[0].line, 6);
+
+    assert_eq!(error.notes.len(), 1);
+    let note = &error.notes[0];
+    assert_eq!(note.level, Level::Note);
+    assert!(matches!(note.kind, CodeErrorKind::NotAssignedOnBranch(_)));
+    assert_eq!((note.locations[0].line, note.locations[0].column), (3, 5));
+}
+
+fn cache_dir(name: &str) -> std::path::PathBuf {
+    let dir = std::env::temp_dir().join(format!(
+        "alumina-incremental-{}-{}",
+        name,
+        std::process::id()
+    ));
+    let _ = std::fs::remove_dir_all(&dir);
+    dir
+}
+
+/// Compiles a small program with the incremental cache and returns the number of cache hits
+/// and misses.
+fn compile_incremental(dir: &Path, cfg: &[&str]) -> (usize, usize) {
+    let sysroot = Path::new(env!("CARGO_MANIFEST_DIR")).join("../../sysroot");
+    let mut compiler = Compiler::new(CompilerOptions {
+        cfg: cfg.iter().map(|flag| (flag.to_string(), None)).collect(),
+        incremental: Some(dir.to_path_buf()),
+        ..Default::default()
+    });
+    compiler.add_sysroot(&sysroot).unwrap();
+    compiler.add_source("main", "fn main() { println!(\"hello\"); }");
+
+    let output = compiler.compile().unwrap();
+    assert!(output.diagnostics.iter().all(|d| d.level != Level::Error));
+
+    compiler.incremental_stats().unwrap()
+}
+
+#[test]
+fn incremental_cache_is_reused() {
+    let dir = cache_dir("warm");
+
+    let (hits, misses) = compile_incremental(&dir, &[]);
+    assert_eq!(hits, 0);
+    assert!(misses > 1);
+
+    // Sources from memory are cached the same way as the ones from disk.
+    assert_eq!(compile_incremental(&dir, &[]), (misses, 0));
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn incremental_cache_depends_on_cfg() {
+    let dir = cache_dir("cfg");
+
+    let (_, misses) = compile_incremental(&dir, &[]);
+    let (hits, _) = compile_incremental(&dir, &["debug"]);
+    assert_eq!(hits, 0);
+
+    // Both sets of entries are kept
+    assert_eq!(compile_incremental(&dir, &[]), (misses, 0));
+    assert_eq!(compile_incremental(&dir, &["debug"]), (misses, 0));
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn corrupted_incremental_cache_is_discarded() {
+    let dir = cache_dir("corrupted");
+
+    let (_, misses) = compile_incremental(&dir, &[]);
+
+    let mut entries: Vec<_> = std::fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .collect();
+    entries.sort();
+
+    std::fs::write(&entries[0], b"garbage").unwrap();
+    let data = std::fs::read(&entries[1]).unwrap();
+    std::fs::write(&entries[1], &data[..data.len() / 2]).unwrap();
+
+    // The damaged entries are misses and are written again
+    assert_eq!(compile_incremental(&dir, &[]), (misses - 2, 2));
+    assert_eq!(compile_incremental(&dir, &[]), (misses, 0));
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
@@ -0,0 +1,344 @@
+//! End-to-end tests of the compiler.
+//!
+//! Every `.alu` file in `tests/cases` is compiled as the main module together with the
+//! standard library and checked against the directives in its comments:
+//!
+//! - `// expect-error: DuplicateName @ 12:5`: compilation fails with exactly the listed
+//!   errors. The location is a line and column in the test file and is omitted for errors
+//!   that do not point into it.
+//! - `// expect-warning: UnreachableCode @ 3:5`: these warnings are reported in the test
+//!   file. Warnings elsewhere (e.g. in the standard library) are ignored.
+//! - `// run-output: hello`: one line of the expected output of the program.
+//! - `// exit-code: 1`: the expected exit status of the program (0 if omitted). Programs
+//!   killed by a signal have the exit status 128 + signal, as in the shell.
+//!
+//! Cases that compile successfully are built with `cc` (or `$CC`) and run. Every case is
+//! also compiled with `--opt` and with `--codegen-dialect c89` (built as `-std=gnu89`) and
+//! has to give the same results in all of these configurations. Set the
+//! `ALUMINA_BLESS` environment variable to rewrite the directives of the failing cases
+//! to match the actual results instead.
+
+use std::fmt::{Display, Formatter};
+use std::os::unix::process::ExitStatusExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use alumina_boot::compiler::{CodegenDialect, Compiler, CompilerOptions};
+use alumina_boot::diagnostics::{Diagnostic, Level};
+
+const DIRECTIVES: &[&str] = &["expect-error", "expect-warning", "run-output", "exit-code"];
+
+/// Configurations that have to give the same results as the default one.
+const VARIANTS: &[(&str, bool, CodegenDialect)] = &[
+    ("--opt", true, CodegenDialect::C11),
+    ("--codegen-dialect c89", false, CodegenDialect::C89),
+];
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct Finding {
+    kind: String,
+    location: Option<(usize, usize)>,
+}
+
+impl Finding {
+    fn parse(value: &str) -> Self {
+        match value.split_once(" @ ") {
+            Some((kind, location)) => {
+                let (line, column) = location
+                    .split_once(':')
+                    .and_then(|(l, c)| Some((l.trim().parse().ok()?, c.trim().parse().ok()?)))
+                    .unwrap_or_else(|| panic!("malformed location in directive: {}", value));
+
+                Finding {
+                    kind: kind.trim().to_string(),
+                    location: Some((line, column)),
+                }
+            }
+            None => Finding {
+                kind: value.trim().to_string(),
+                location: None,
+            },
+        }
+    }
+
+    fn from_diagnostic(diagnostic: &Diagnostic, case: &Path) -> Self {
+        // There is no name for the error kinds other than the variant name in the Debug output.
+        let debug = format!("{:?}", diagnostic.kind);
+        let kind = debug
+            .split(|c: char| !c.is_alphanumeric() && c != '_')
+            .next()
+            .unwrap()
+            .to_string();
+
+        Finding {
+            kind,
+            location: diagnostic
+                .locations
+                .iter()
+                .find(|l| l.file == case)
+                .map(|l| (l.line, l.column)),
+        }
+    }
+}
+
+impl Display for Finding {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.location {
+            Some((line, column)) => write!(f, "{} @ {}:{}", self.kind, line, column),
+            None => write!(f, "{}", self.kind),
+        }
+    }
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Expectations {
+    errors: Vec<Finding>,
+    warnings: Vec<Finding>,
+    output: Vec<String>,
+    exit_code: i32,
+}
+
+impl Expectations {
+    fn parse(source: &str) -> Self {
+        let mut result = Expectations::default();
+        for (name, value) in source.lines().filter_map(parse_directive) {
+            match name {
+                "expect-error" => result.errors.push(Finding::parse(value)),
+                "expect-warning" => result.warnings.push(Finding::parse(value)),
+                "run-output" => result.output.push(value.to_string()),
+                "exit-code" => {
+                    result.exit_code = value
+                        .trim()
+                        .parse()
+                        .unwrap_or_else(|_| panic!("malformed exit code: {}", value))
+                }
+                _ => unreachable!(),
+            }
+        }
+
+        result.errors.sort();
+        result.warnings.sort();
+        result
+    }
+
+    fn directives(&self, remap: impl Fn(usize) -> usize) -> Vec<String> {
+        let finding = |f: &Finding| Finding {
+            kind: f.kind.clone(),
+            location: f.location.map(|(line, column)| (remap(line), column)),
+        };
+
+        let mut result = Vec::new();
+        result.extend(
+            self.errors
+                .iter()
+                .map(|f| format!("// expect-error: {}", finding(f))),
+        );
+        result.extend(
+            self.warnings
+                .iter()
+                .map(|f| format!("// expect-warning: {}", finding(f))),
+        );
+        result.extend(self.output.iter().map(|l| format!("// run-output: {}", l)));
+        if self.exit_code != 0 {
+            result.push(format!("// exit-code: {}", self.exit_code));
+        }
+
+        result
+    }
+}
+
+fn parse_directive(line: &str) -> Option<(&str, &str)> {
+    let rest = line.strip_prefix("//")?.trim_start();
+    let (name, value) = rest.split_once(':')?;
+
+    DIRECTIVES
+        .contains(&name)
+        .then(|| (name, value.strip_prefix(' ').unwrap_or(value)))
+}
+
+/// Replaces the directives in the test case with the ones matching the actual results. The
+/// new directives are placed at the top of the file, so the line numbers are adjusted for the
+/// lines that were added or removed.
+fn bless(source: &str, actual: &Expectations) -> String {
+    let mut body: Vec<(usize, &str)> = source
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| parse_directive(line).is_none())
+        .map(|(index, line)| (index + 1, line))
+        .collect();
+
+    while body.first().is_some_and(|(_, line)| line.trim().is_empty()) {
+        body.remove(0);
+    }
+
+    let header_len = actual.directives(|line| line).len();
+    let offset = if header_len > 0 { header_len + 1 } else { 0 };
+    let remap = |line: usize| {
+        offset
+            + 1
+            + body
+                .iter()
+                .position(|(original, _)| *original == line)
+                .unwrap_or(0)
+    };
+
+    let mut result = actual.directives(remap);
+    if !result.is_empty() {
+        result.push(String::new());
+    }
+    result.extend(body.iter().map(|(_, line)| line.to_string()));
+    result.push(String::new());
+
+    result.join("\n")
+}
+
+fn build_and_run(
+    program: &str,
+    case: &Path,
+    dialect: CodegenDialect,
+) -> Result<(Vec<String>, i32), String> {
+    let dir = std::env::temp_dir().join(format!("alumina-golden-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+
+    let executable = dir.join(case.file_stem().unwrap());
+    let c_file = executable.with_extension("c");
+    std::fs::write(&c_file, program).map_err(|e| e.to_string())?;
+
+    let cc = std::env::var("CC").unwrap_or_else(|_| "cc".to_string());
+    let mut build = Command::new(&cc);
+    if dialect == CodegenDialect::C89 {
+        build.arg("-std=gnu89");
+    }
+
+    let build = build
+        .arg("-o")
+        .arg(&executable)
+        .arg(&c_file)
+        .arg("-lm")
+        .output()
+        .map_err(|e| format!("could not run {}: {}", cc, e))?;
+
+    if !build.status.success() {
+        return Err(format!(
+            "C compilation failed:\n{}",
+            String::from_utf8_lossy(&build.stderr)
+        ));
+    }
+
+    let run = Command::new(&executable)
+        .output()
+        .map_err(|e| format!("could not run the test program: {}", e))?;
+
+    let output = String::from_utf8_lossy(&run.stdout)
+        .lines()
+        .map(str::to_string)
+        .collect();
+
+    // Report programs killed by a signal (e.g. aborted by a panic) the same way as the shell.
+    let exit_code = run
+        .status
+        .code()
+        .or_else(|| run.status.signal().map(|signal| 128 + signal))
+        .unwrap_or(-1);
+
+    Ok((output, exit_code))
+}
+
+fn run_case(
+    sysroot: &Path,
+    case: &Path,
+    optimize: bool,
+    dialect: CodegenDialect,
+) -> Result<Expectations, String> {
+    let mut compiler = Compiler::new(CompilerOptions {
+        debug: true,
+        optimize,
+        dialect,
+        ..Default::default()
+    });
+    compiler.add_sysroot(sysroot).map_err(|e| e.to_string())?;
+    compiler.add_file("main", case);
+
+    let (program, diagnostics) = match compiler.compile() {
+        Ok(output) => (Some(output.program), output.diagnostics),
+        Err(diagnostics) => (None, diagnostics),
+    };
+
+    let mut actual = Expectations::default();
+    for diagnostic in diagnostics {
+        let finding = Finding::from_diagnostic(&diagnostic, case);
+        match diagnostic.level {
+            Level::Error => actual.errors.push(finding),
+            Level::Warning if finding.location.is_some() => actual.warnings.push(finding),
+            _ => {}
+        }
+    }
+
+    actual.errors.sort();
+    actual.warnings.sort();
+
+    if let Some(program) = program {
+        let (output, exit_code) = build_and_run(&program, case, dialect)?;
+        actual.output = output;
+        actual.exit_code = exit_code;
+    }
+
+    Ok(actual)
+}
+
+#[test]
+fn golden() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let sysroot = root.join("../../sysroot");
+    let should_bless = std::env::var_os("ALUMINA_BLESS").is_some();
+
+    let mut cases: Vec<PathBuf> = std::fs::read_dir(root.join("tests/cases"))
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "alu"))
+        .collect();
+    cases.sort();
+
+    let mut failures = Vec::new();
+    for case in &cases {
+        let source = std::fs::read_to_string(case).unwrap();
+        let expected = Expectations::parse(&source);
+
+        match run_case(&sysroot, case, false, CodegenDialect::C11) {
+            Ok(actual) if actual == expected => {
+                for (name, optimize, dialect) in VARIANTS {
+                    match run_case(&sysroot, case, *optimize, *dialect) {
+                        Ok(variant) if variant == expected => {}
+                        Ok(variant) => failures.push(format!(
+                            "{}\nexpected with {}:\n{}\nactual with {}:\n{}",
+                            case.display(),
+                            name,
+                            expected.directives(|line| line).join("\n"),
+                            name,
+                            variant.directives(|line| line).join("\n"),
+                        )),
+                        Err(e) => {
+                            failures.push(format!("{} (with {})\n{}", case.display(), name, e))
+                        }
+                    }
+                }
+            }
+            Ok(actual) if should_bless => std::fs::write(case, bless(&source, &actual)).unwrap(),
+            Ok(actual) => failures.push(format!(
+                "{}\nexpected:\n{}\nactual:\n{}",
+                case.display(),
+                expected.directives(|line| line).join("\n"),
+                actual.directives(|line| line).join("\n"),
+            )),
+            Err(e) => failures.push(format!("{}\n{}", case.display(), e)),
+        }
+    }
+
+    assert!(
+        failures.is_empty(),
+        "{} of {} golden tests failed (set ALUMINA_BLESS=1 to update them):\n\n{}",
+        failures.len(),
+        cases.len(),
+        failures.join("\n\n")
+    );
+}